@@ -1,4 +1,259 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use crate::h2tp::content_encoding;
+use crate::h2tp::utils::buffer_pool::BufferPool;
 use std::sync::atomic::Ordering;
+use std::time::Duration;
+use crate::h2tp::clock::{Clock, RealClock};
+use crate::h2tp::status_code::StatusCode;
+use crate::h2tp::types::{ConnObserver, PreBodyCheck, StartLineCheck, TunnelHandler};
 
 pub const ATOMIC_ORDERING: Ordering = Ordering::Relaxed;
-pub const MESSAGE_BUFFER_SIZE: usize = 2048;
\ No newline at end of file
+pub const MESSAGE_BUFFER_SIZE: usize = 2048;
+
+// How large requests may be and how long they may take, the ones past a limit are rejected with
+// the status noted by it. Set fields directly or chain the setters of the same names.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Limits {
+	// bytes of a request head, start line included, 431 past it; applies to parsed responses too
+	pub max_header_bytes: usize,
+	// header lines of a request head, 431 past it; applies to parsed responses too
+	pub max_header_count: usize,
+	// length of a request target, 414 past it
+	pub max_uri_length: usize,
+	// request bodies past this size are rejected with 413
+	pub max_body_bytes: usize,
+	// max time to receive a whole request, head and body, 408 once it expires
+	pub read_timeout: Option<Duration>,
+	// max time to receive a request head, 408 once it expires
+	pub header_timeout: Option<Duration>,
+	// max time a handler has to return its response, 504 and the connection is closed after
+	pub handler_timeout: Option<Duration>,
+	// max time a kept alive connection waits for its next request, it is closed silently after
+	pub keep_alive_timeout: Option<Duration>,
+	// requests served on one connection, the response to the last one closes it
+	pub max_keep_alive_requests: Option<usize>,
+	// Pipelined requests a connection handles at once. Requests already read past are handled
+	// while earlier responses are pending; once this many are, no further request is taken from the
+	// buffer and the socket is not read until their responses are written. `None` bounds them by
+	// the read buffer only.
+	pub max_pipelined_requests: Option<usize>,
+}
+
+impl Default for Limits {
+	fn default() -> Self {
+		return Self {
+			max_header_bytes: 64 << 10,
+			max_header_count: 100,
+			max_uri_length: 8 << 10,
+			max_body_bytes: usize::MAX,
+			read_timeout: None,
+			header_timeout: Some(Duration::from_secs(30)),
+			handler_timeout: None,
+			keep_alive_timeout: Some(Duration::from_secs(60)),
+			max_keep_alive_requests: None,
+			max_pipelined_requests: Some(16),
+		};
+	}
+}
+
+impl Limits {
+	pub fn new() -> Self {
+		return Self::default();
+	}
+
+	pub fn max_header_bytes(&mut self, n: usize) -> &mut Self {
+		self.max_header_bytes = n;
+		return self;
+	}
+
+	pub fn max_header_count(&mut self, n: usize) -> &mut Self {
+		self.max_header_count = n;
+		return self;
+	}
+
+	pub fn max_uri_length(&mut self, n: usize) -> &mut Self {
+		self.max_uri_length = n;
+		return self;
+	}
+
+	pub fn max_body_bytes(&mut self, n: usize) -> &mut Self {
+		self.max_body_bytes = n;
+		return self;
+	}
+
+	pub fn read_timeout(&mut self, d: Option<Duration>) -> &mut Self {
+		self.read_timeout = d;
+		return self;
+	}
+
+	pub fn header_timeout(&mut self, d: Option<Duration>) -> &mut Self {
+		self.header_timeout = d;
+		return self;
+	}
+
+	pub fn handler_timeout(&mut self, d: Option<Duration>) -> &mut Self {
+		self.handler_timeout = d;
+		return self;
+	}
+
+	pub fn keep_alive_timeout(&mut self, d: Option<Duration>) -> &mut Self {
+		self.keep_alive_timeout = d;
+		return self;
+	}
+
+	pub fn max_keep_alive_requests(&mut self, n: Option<usize>) -> &mut Self {
+		self.max_keep_alive_requests = n;
+		return self;
+	}
+
+	pub fn max_pipelined_requests(&mut self, n: Option<usize>) -> &mut Self {
+		self.max_pipelined_requests = n;
+		return self;
+	}
+
+	// the timeouts that are set, none of them may be zero
+	pub fn timeouts(&self) -> Vec<Duration> {
+		return [self.read_timeout, self.header_timeout, self.handler_timeout, self.keep_alive_timeout]
+			.iter().filter_map(|d| *d).collect();
+	}
+}
+
+#[derive(Clone)]
+pub struct Config {
+	// when false, GET/HEAD requests that carry a body are rejected with 400
+	pub allow_body_on_get: bool,
+	// value of the `Server` header added to responses, `None` disables it
+	pub server_header: Option<String>,
+	// max time to write one response, the connection is dropped when a client stops reading
+	pub write_timeout: Option<Duration>,
+	// request bodies with `Content-Encoding` are decoded when true (gzip/deflate need feature `gzip`, br feature `brotli`)
+	pub decode_request_body: bool,
+	// upper bound of a decoded request body
+	pub max_decoded_body_size: usize,
+	// Transfer codings a request body may carry under its final `chunked`, undone once the body is
+	// read whatever `decode_request_body` says. A Transfer-Encoding with any other coding is
+	// rejected with 400. Defaults to the codings this build can decode.
+	pub transfer_codings: Vec<String>,
+	// keep a copy of the received start line and headers, see `Request::raw_headers`
+	pub keep_raw_headers: bool,
+	// `Allow` header answered to `OPTIONS *`
+	pub allow_methods: String,
+	// write response header names lowercased instead of with their supplied casing
+	pub lowercase_header_names: bool,
+	// add a `Date` header to responses lacking one
+	pub date_header: bool,
+	// time source for `Date` headers and timeouts
+	pub clock: Arc<dyn Clock>,
+	// unread request bodies up to this size are skipped to keep the connection, larger ones close it
+	pub max_drain_body_size: usize,
+	// length of the accept queue of the listening socket
+	pub listen_backlog: u32,
+	// rejects requests by their head, the unread body is drained afterwards
+	pub pre_body_check: Option<PreBodyCheck>,
+	// `CONNECT` requests are answered with 405 unless set, see `Conn::into_tunnel`
+	pub tunnel_handler: Option<TunnelHandler>,
+	// accept absolute-form targets (`GET http://host/ HTTP/1.1`), only proxies should
+	pub allow_absolute_form: bool,
+	// requests in handlers at once over all connections, the ones past it get a 503
+	pub max_concurrent_requests: Option<usize>,
+	// seconds sent in `Retry-After` with that 503
+	pub overload_retry_after: u64,
+	// Requests read once the server is shutting down are answered with this status instead of
+	// being handled, `None` handles them as usual. Either way their response and the ones still
+	// in flight carry `Connection: close` and the connection is closed after them.
+	pub shutdown_status: Option<StatusCode>,
+	// Content-Type of responses that have a body but did not set one
+	pub default_content_type: Option<String>,
+	// send `X-Content-Type-Options: nosniff` with every response
+	pub nosniff: bool,
+	// requests repeating one header name more often than this are rejected with 400
+	pub max_header_values_per_name: usize,
+	// sizes and timeouts of requests, see `Limits`
+	pub limits: Limits,
+	// when false, every connection is closed after its first response
+	pub keep_alive: bool,
+	// longest method token accepted in a request line
+	pub max_method_length: usize,
+	// Bytes after the body of a request the connection closes after cannot be a next request,
+	// they are answered with 400 instead of being ignored when set.
+	pub reject_trailing_bytes: bool,
+	// how long such a request waits for bytes past its body before it is handled, unless the
+	// client closes its side first
+	pub trailing_bytes_wait: Duration,
+	// worker threads of the runtime `Server::run_blocking` builds, one per core when `None`
+	pub worker_threads: Option<usize>,
+	// rejects requests by their request line alone, the connection is closed after the answer
+	pub start_line_check: Option<StartLineCheck>,
+	// answer `TRACE` with the received request head, 405 when false
+	pub trace_echo: bool,
+	// limits of `multipart::parse`, more parts or a larger one are a 413, a longer filename a 400
+	pub max_multipart_parts: usize,
+	pub max_multipart_part_size: usize,
+	pub max_multipart_filename_length: usize,
+	// called with the byte counts of every connection when it closes or turns into a tunnel
+	pub conn_observer: Option<ConnObserver>,
+	// `Upgrade: h2c` requests are answered with 101 and handed to it when set, see `Tunnel::request`,
+	// the upgrade is ignored otherwise
+	pub h2c_handler: Option<TunnelHandler>,
+	// HTTP/1.1 requests need exactly one `Host` unless their target is absolute-form, 400 otherwise
+	pub require_host: bool,
+	// request bodies are taken from and given back to it when set, see `BufferPool`
+	pub body_pool: Option<Arc<BufferPool>>,
+	// status lines go out as `HTTP/1.1 200 ` without their reason phrase, as HTTP/2 has none
+	pub empty_reason_phrase: bool,
+	// max time to receive a whole chunked request body, 408 once it expires
+	pub chunked_body_timeout: Option<Duration>,
+	// request bodies larger than this are written to a file in `spill_dir` as they are read,
+	// see `Request::body_path`; they are not decoded
+	pub spill_body_threshold: Option<usize>,
+	pub spill_dir: PathBuf,
+}
+
+impl Config {
+	pub fn new() -> Self {
+		return Self {
+			allow_body_on_get: true,
+			server_header: Some(format!("spk/{}", env!("CARGO_PKG_VERSION"))),
+			write_timeout: Some(Duration::from_secs(30)),
+			decode_request_body: true,
+			max_decoded_body_size: 8 << 20,
+			transfer_codings: content_encoding::DECODABLE.iter().map(|c| c.to_string()).collect(),
+			keep_raw_headers: false,
+			allow_methods: "GET, HEAD, POST, PUT, DELETE, PATCH, OPTIONS".to_string(),
+			lowercase_header_names: false,
+			date_header: true,
+			clock: Arc::new(RealClock {}),
+			max_drain_body_size: 64 << 10,
+			listen_backlog: 1024,
+			pre_body_check: None,
+			tunnel_handler: None,
+			allow_absolute_form: false,
+			max_concurrent_requests: None,
+			overload_retry_after: 1,
+			shutdown_status: Some(StatusCode::ServiceUnavailable),
+			default_content_type: None,
+			nosniff: false,
+			max_header_values_per_name: 100,
+			limits: Limits::default(),
+			keep_alive: true,
+			max_method_length: 32,
+			reject_trailing_bytes: false,
+			trailing_bytes_wait: Duration::from_millis(50),
+			worker_threads: None,
+			start_line_check: None,
+			trace_echo: false,
+			max_multipart_parts: 100,
+			max_multipart_part_size: 8 << 20,
+			max_multipart_filename_length: 255,
+			conn_observer: None,
+			h2c_handler: None,
+			require_host: false,
+			body_pool: None,
+			empty_reason_phrase: false,
+			chunked_body_timeout: None,
+			spill_body_threshold: None,
+			spill_dir: std::env::temp_dir(),
+		};
+	}
+}
\ No newline at end of file
@@ -0,0 +1,130 @@
+use tokio::net::{TcpStream, ToSocketAddrs};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use crate::h2tp::cfg::Config;
+use crate::h2tp::message::{ParseError, ReadBuffer};
+use crate::h2tp::methods;
+use crate::h2tp::request::Request;
+use crate::h2tp::response::Response;
+use crate::h2tp::types::{AsyncReader, AsyncWriter};
+
+// Sends requests over one connection and reads their responses, one at a time.
+pub struct Client<R: AsyncReader, W: AsyncWriter> {
+	r: R,
+	w: W,
+	rbuf: Option<ReadBuffer>,
+	cfg: Config,
+}
+
+impl Client<OwnedReadHalf, OwnedWriteHalf> {
+	pub async fn connect<A: ToSocketAddrs>(addr: A) -> std::io::Result<Self> {
+		let (r, w) = TcpStream::connect(addr).await?.into_split();
+		return Ok(Self::new(r, w));
+	}
+}
+
+impl<R: AsyncReader, W: AsyncWriter> Client<R, W> {
+	pub fn new(r: R, w: W) -> Self {
+		return Self {
+			r, w,
+			rbuf: None,
+			cfg: Config::new(),
+		};
+	}
+
+	// limits applied to responses, `limits.max_body_bytes` bounds their bodies
+	pub fn config(&mut self) -> &mut Config {
+		return &mut self.cfg;
+	}
+
+	pub async fn send(&mut self, req: &Request) -> Result<Response, ParseError> {
+		match req.write_to(&mut self.w).await {
+			Ok(_) => {}
+			Err(e) => {
+				return Err(ParseError::ioe(e));
+			}
+		}
+
+		let mut resp = Response::new();
+		match self.rbuf.take() {
+			Some(rb) => {
+				resp.set_read_buffer(rb);
+			}
+			None => {}
+		}
+		match resp.read_head(&mut self.r, &self.cfg).await {
+			Some(e) => {
+				return Err(e);
+			}
+			None => {}
+		}
+		if req.method() != methods::HEAD {
+			match resp.read_body(&mut self.r, &self.cfg).await {
+				Some(e) => {
+					return Err(e);
+				}
+				None => {}
+			}
+		}
+		self.rbuf = resp.take_read_buffer();
+		return Ok(resp);
+	}
+
+	pub async fn request(&mut self, method: &str, path: &str, headers: &[(&str, &str)], body: &[u8]) -> Result<Response, ParseError> {
+		let mut req = Request::new();
+		let mut builder = req.builder();
+		builder.method(method).rawpath(path);
+		for (k, v) in headers {
+			builder.headers().append(k, v);
+		}
+		if !body.is_empty() {
+			builder.body(body);
+		}
+		return self.send(&req).await;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::Arc;
+	use crate::h2tp::client::Client;
+	use crate::h2tp::FuncHandler;
+	use crate::h2tp::headers::hns;
+	use crate::h2tp::response::Response;
+	use crate::h2tp::testing::TestServer;
+
+	#[tokio::test]
+	async fn test_roundtrip() {
+		let server = TestServer::start(Arc::new(FuncHandler::new(|req| {
+			Box::pin(async move {
+				if req.path() == "/missing" {
+					return Ok(Response::not_found());
+				}
+				let mut resp = Response::new();
+				let body = req.body().map(|b| b.to_vec()).unwrap_or_default();
+				resp.builder().body(body.as_slice()).headers()
+					.append("X-Method", req.method())
+					.append("X-Token", req.headers().and_then(|h| h.get("x-token")).unwrap_or(""));
+				return Ok(resp);
+			})
+		}))).await;
+
+		let mut client = Client::connect(server.addr()).await.unwrap();
+		let resp = client.request("POST", "/echo", &[("X-Token", "t1")], b"ping").await.unwrap();
+		assert_eq!(resp.statuscode(), 200);
+		assert_eq!(resp.body().unwrap().as_ref(), b"ping");
+		let headers = resp.headers().unwrap();
+		assert_eq!(headers.get("x-method").unwrap(), "POST");
+		assert_eq!(headers.get("x-token").unwrap(), "t1");
+
+		// same connection, a reason phrase with a space, and a HEAD without a body to read
+		let resp = client.request("GET", "/missing", &[], b"").await.unwrap();
+		assert_eq!((resp.statuscode(), resp.reason()), (404, "Not Found"));
+		assert_eq!(resp.body().unwrap().as_ref(), b"Not Found");
+		let resp = client.request("HEAD", "/echo", &[(hns::CONNECTION, "close")], b"").await.unwrap();
+		assert_eq!(resp.statuscode(), 200);
+		assert!(resp.body().is_none());
+
+		drop(client);
+		server.stop().await;
+	}
+}
\ No newline at end of file
@@ -0,0 +1,69 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::time::{Duration, SystemTime};
+
+pub type Sleep<'a> = Pin<Box<dyn Future<Output=()> + Send + 'a>>;
+
+// Time source of the server, used for the `Date` header and for timeouts.
+pub trait Clock: Send + Sync {
+	fn now(&self) -> SystemTime;
+
+	fn sleep(&self, duration: Duration) -> Sleep<'_>;
+}
+
+pub struct RealClock {}
+
+impl Clock for RealClock {
+	fn now(&self) -> SystemTime {
+		return SystemTime::now();
+	}
+
+	fn sleep(&self, duration: Duration) -> Sleep<'_> {
+		return Box::pin(tokio::time::sleep(duration));
+	}
+}
+
+// A clock that only moves when told to, sleepers wake up once `advance` passes their deadline.
+#[cfg(test)]
+pub struct MockClock {
+	now: std::sync::Mutex<SystemTime>,
+	notify: tokio::sync::Notify,
+}
+
+#[cfg(test)]
+impl MockClock {
+	pub fn new(now: SystemTime) -> Self {
+		return Self {
+			now: std::sync::Mutex::new(now),
+			notify: tokio::sync::Notify::new(),
+		};
+	}
+
+	pub fn advance(&self, duration: Duration) {
+		{
+			let mut guard = self.now.lock().unwrap();
+			*guard += duration;
+		}
+		self.notify.notify_waiters();
+	}
+}
+
+#[cfg(test)]
+impl Clock for MockClock {
+	fn now(&self) -> SystemTime {
+		return *self.now.lock().unwrap();
+	}
+
+	fn sleep(&self, duration: Duration) -> Sleep<'_> {
+		let deadline = self.now() + duration;
+		return Box::pin(async move {
+			loop {
+				let notified = self.notify.notified();
+				if self.now() >= deadline {
+					return;
+				}
+				notified.await;
+			}
+		});
+	}
+}
\ No newline at end of file
@@ -1,38 +1,1890 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::future::Future;
 use std::net::SocketAddr;
+use bytes::BytesMut;
+use tokio::io::AsyncWriteExt;
+use tokio::task::JoinHandle;
 use std::sync::{Arc};
-use std::sync::atomic::{AtomicBool};
-use tokio::io::{AsyncWriteExt};
-use crate::h2tp::cfg::ATOMIC_ORDERING;
+use std::sync::atomic::{AtomicBool, AtomicUsize};
+use std::time::Duration;
+use crate::h2tp::cfg::{ATOMIC_ORDERING, Config};
+use crate::h2tp::error::Error;
 use crate::h2tp::handler::Handler;
+use crate::h2tp::headers::{Headers, hns, mime};
+use crate::h2tp::methods;
+use crate::h2tp::status_code::StatusCode;
+use crate::h2tp::message::{ParseError, ParseErrorKind, ReadBuffer};
 use crate::h2tp::request::Request;
-use crate::h2tp::response::Response;
-use crate::h2tp::types::{AsyncReader, AsyncWriter};
+use crate::h2tp::response::{Response, ResponseContext};
+use crate::h2tp::types::{AsyncReader, AsyncWriter, State, TunnelHandler};
+use crate::h2tp::utils::counted::Counted;
+
+const REQUEST_TIMEOUT: &str = "request timeout";
+
+// An accepted CONNECT request, h2c upgrade or upgrade by a handler's response, `buffered` holds
+// bytes the client sent past the request.
+pub struct Connect {
+	authority: String,
+	buffered: BytesMut,
+	request: Option<Request>,
+	upgrade: Option<TunnelHandler>,
+}
+
+impl Connect {
+	// the handler of a response's upgrade, `None` for a `CONNECT` or h2c one
+	pub fn take_upgrade(&mut self) -> Option<TunnelHandler> {
+		return self.upgrade.take();
+	}
+}
+
+// For an h2c upgrade `request` is the one that asked for it, HTTP/2 answers it as stream 1
+// (RFC 7540 3.2). `authority` is its Host then, it is empty after a handler's upgrade.
+pub struct Tunnel<R, W> {
+	pub authority: String,
+	pub buffered: BytesMut,
+	pub request: Option<Request>,
+	pub r: R,
+	pub w: W,
+}
+
+// What went over a connection during its lifetime.
+#[derive(Clone, Copy, Debug)]
+pub struct ConnMetrics {
+	pub peer: SocketAddr,
+	pub bytes_read: u64,
+	pub bytes_written: u64,
+}
+
+// Where a connection is in its request/response cycle.
+// ReadingRequest -> Handling -> Writing -> ReadingRequest is the keep-alive loop, a rejected request
+// goes through Draining to skip its body. A pipelined request goes from Handling back to
+// ReadingRequest, its response is written along with the later ones. Closing is final.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ConnState {
+	ReadingRequest,
+	Handling,
+	Writing,
+	Draining,
+	Closing,
+}
 
 pub struct Conn<R: AsyncReader, W: AsyncWriter> {
 	addr: SocketAddr,
-	r: R,
-	w: W,
+	r: Counted<R>,
+	w: Counted<W>,
 	server_is_closing: Arc<AtomicBool>,
+	state: Option<State>,
+	cfg: Arc<Config>,
+
+	cstate: ConnState,
+	rbuf: Option<ReadBuffer>,
+	req: Option<Request>,
+	resp: Option<Response>,
+	respond_after_drain: bool,
+	close_after: bool,
+	connect: Option<String>,
+	h2c: Option<Request>,
+	tunnel: Option<Connect>,
+	inflight: Option<Arc<AtomicUsize>>,
+	seq: u64,
+	order: ResponseOrder<Response>,
+	// what the response to the current request is finalized with
+	ctx: ResponseContext,
+	// request heads read so far
+	requests: usize,
+	// read-ahead requests in handlers, their responses go out before the current one's
+	pipeline: VecDeque<Pipelined>,
+	// the current request is the last one `max_keep_alive_requests` allows
+	last_request: bool,
+}
+
+// Responses go out in the order their requests were read (RFC 7230 6.3.2), whatever order they
+// complete in. Each request takes a sequence number, completed responses wait here until every
+// earlier one has been written.
+struct ResponseOrder<T> {
+	next: u64,
+	ready: BTreeMap<u64, T>,
+}
+
+impl<T> ResponseOrder<T> {
+	fn new() -> Self {
+		return Self { next: 0, ready: BTreeMap::new() };
+	}
+
+	fn push(&mut self, seq: u64, v: T) {
+		debug_assert!(seq >= self.next && !self.ready.contains_key(&seq));
+		self.ready.insert(seq, v);
+	}
+
+	// the next response in request order, if it has completed
+	fn pop(&mut self) -> Option<T> {
+		let v = self.ready.remove(&self.next)?;
+		self.next += 1;
+		return Some(v);
+	}
+}
+
+// A pipelined request being handled, with what its response is written with.
+struct Pipelined {
+	seq: u64,
+	ctx: ResponseContext,
+	close_after: bool,
+	task: JoinHandle<Option<Result<Response, Error>>>,
+}
+
+struct InflightGuard {
+	counter: Option<Arc<AtomicUsize>>,
+}
+
+impl Drop for InflightGuard {
+	fn drop(&mut self) {
+		match self.counter.as_ref() {
+			Some(counter) => {
+				counter.fetch_sub(1, ATOMIC_ORDERING);
+			}
+			None => {}
+		}
+	}
 }
 
 impl<R: AsyncReader, W: AsyncWriter> Conn<R, W> {
-	pub fn new(addr: SocketAddr, r: R, w: W, server_is_closing: Arc<AtomicBool>) -> Self {
-		return Self { addr, r, w, server_is_closing };
+	pub fn new(addr: SocketAddr, r: R, w: W, server_is_closing: Arc<AtomicBool>, state: Option<State>, cfg: Arc<Config>) -> Self {
+		return Self {
+			addr, server_is_closing, state, cfg,
+			r: Counted::new(r),
+			w: Counted::new(w),
+			cstate: ConnState::ReadingRequest,
+			rbuf: None,
+			req: None,
+			resp: None,
+			respond_after_drain: false,
+			close_after: false,
+			connect: None,
+			h2c: None,
+			tunnel: None,
+			inflight: None,
+			seq: 0,
+			order: ResponseOrder::new(),
+			ctx: ResponseContext::default(),
+			requests: 0,
+			pipeline: VecDeque::new(),
+			last_request: false,
+		};
+	}
+
+	fn server_wide(&self, req: &Request) -> Response {
+		let mut resp = Response::new();
+		if req.method() == methods::OPTIONS {
+			resp.builder().headers().append(hns::ALLOW, self.cfg.allow_methods.as_str()).content_length(0);
+		} else {
+			resp.builder().status(StatusCode::BadRequest);
+		}
+		return resp;
+	}
+
+	// the request line and headers as received, the body of a `TRACE` echo
+	fn trace(&self, req: &Request) -> Response {
+		let mut head = format!("{} {} {}\r\n", req.method(), req.path(), req.version()).into_bytes();
+		match req.headers() {
+			Some(headers) => {
+				headers.each_cased(|k, v| {
+					head.extend_from_slice(k.as_bytes());
+					head.extend_from_slice(b": ");
+					head.extend_from_slice(v.as_bytes());
+					head.extend_from_slice(b"\r\n");
+				});
+			}
+			None => {}
+		}
+		let mut resp = Response::new();
+		resp.builder().body(head.as_slice()).headers().content_type(mime::MESSAGE_HTTP);
+		return resp;
+	}
+
+	async fn write_response(&mut self, resp: &mut Response) -> bool {
+		let cfg = Arc::clone(&self.cfg);
+		// a streamed body is paced by its handler, it may take longer than any write timeout
+		let timeout = if resp.is_streaming() { None } else { cfg.write_timeout };
+		let fut = resp.write_stream_to(&mut self.w, &cfg);
+		return match timeout {
+			Some(duration) => {
+				tokio::select! {
+					result = fut => {
+						result.is_ok()
+					}
+					_ = cfg.clock.sleep(duration) => {
+						false
+					}
+				}
+			}
+			None => {
+				fut.await.is_ok()
+			}
+		};
+	}
+
+	// answers a request that could not be parsed, if it is worth answering at all
+	async fn reject(&mut self, e: ParseError) {
+		if !self.flush_pipeline().await {
+			return;
+		}
+		if e.is_protocol() {
+			let mut resp = Response::new();
+			resp.builder().status(e.statuscode());
+			self.write_response(&mut resp).await;
+		}
+	}
+
+	// Advances the connection by one state, see `ConnState`.
+	pub async fn step(&mut self, handler: &Arc<dyn Handler + Send + Sync>) -> ConnState {
+		self.cstate = match self.cstate {
+			ConnState::ReadingRequest => {
+				self.read_request().await
+			}
+			ConnState::Handling => {
+				let req = self.req.take().unwrap();
+				let guard = match self.acquire() {
+					Some(g) => {
+						g
+					}
+					None => {
+						self.respond_with(StatusCode::ServiceUnavailable);
+						let retry_after = self.cfg.overload_retry_after.to_string();
+						self.resp.as_mut().unwrap().builder().headers().append(hns::RETRY_AFTER, retry_after.as_str());
+						self.cstate = ConnState::Writing;
+						return self.cstate;
+					}
+				};
+				// bytes past a request that may upgrade the connection need not be a request
+				let upgrade = req.headers().is_some_and(|h| h.get(hns::UPGRADE).is_some());
+				let fut = handle(handler, req, Arc::clone(&self.cfg), guard);
+				// a request read ahead of earlier responses is handled alongside the earlier ones,
+				// up to `max_pipelined_requests` at once
+				let buffered = self.rbuf.as_ref().is_some_and(|rb| rb.remains() > 0);
+				let room = self.cfg.limits.max_pipelined_requests.is_none_or(|n| self.pipeline.len() + 1 < n);
+				let pipelining = self.connect.is_none() && !self.close_after && !upgrade;
+				if pipelining && buffered && room {
+					let task = tokio::spawn(fut);
+					self.pipeline.push_back(Pipelined { seq: self.seq, ctx: self.ctx, close_after: self.close_after, task });
+					ConnState::ReadingRequest
+				} else if pipelining && !self.pipeline.is_empty() {
+					// the last one of the run, no further request is taken until all of them are answered
+					let task = tokio::spawn(fut);
+					self.pipeline.push_back(Pipelined { seq: self.seq, ctx: self.ctx, close_after: self.close_after, task });
+					if self.flush_pipeline().await { ConnState::ReadingRequest } else { ConnState::Closing }
+				} else if !self.flush_pipeline().await {
+					ConnState::Closing
+				} else {
+					let result = fut.await;
+					self.handled(result)
+				}
+			}
+			ConnState::Writing => {
+				self.write().await
+			}
+			ConnState::Draining => {
+				self.drain().await
+			}
+			ConnState::Closing => {
+				ConnState::Closing
+			}
+		};
+		if self.cstate == ConnState::Closing {
+			// what was handled before the connection failed is still answered
+			self.flush_pipeline().await;
+		}
+		return self.cstate;
+	}
+
+	// `Writing` with the response of a handler, `None` when it ran past `handler_timeout`
+	fn handled(&mut self, result: Option<Result<Response, Error>>) -> ConnState {
+		return match result {
+			Some(Ok(resp)) => {
+				self.resp = Some(resp);
+				ConnState::Writing
+			}
+			Some(Err(_)) => {
+				ConnState::Closing
+			}
+			None => {
+				self.respond_with(StatusCode::GatewayTimeout);
+				self.resp.as_mut().unwrap().builder().headers().append(hns::CONNECTION, "close");
+				self.close_after = true;
+				ConnState::Writing
+			}
+		};
+	}
+
+	// Writes the responses of the pipelined requests in request order, waiting for each handler.
+	// The state of the current request is set aside meanwhile. `false` once the connection can not
+	// go on, the handlers not waited for are aborted then.
+	async fn flush_pipeline(&mut self) -> bool {
+		if self.pipeline.is_empty() {
+			return true;
+		}
+		let (resp, req, connect, h2c) = (self.resp.take(), self.req.take(), self.connect.take(), self.h2c.take());
+		let (ctx, close_after, seq) = (self.ctx, self.close_after, self.seq);
+		let mut ok = true;
+		while let Some(p) = self.pipeline.pop_front() {
+			if !ok {
+				p.task.abort();
+				continue;
+			}
+			let result = match p.task.await {
+				Ok(result) => {
+					result
+				}
+				Err(_) => {
+					ok = false;
+					continue;
+				}
+			};
+			self.ctx = p.ctx;
+			self.close_after = p.close_after;
+			self.seq = p.seq;
+			ok = self.handled(result) == ConnState::Writing && self.write_current().await == ConnState::ReadingRequest;
+		}
+		(self.resp, self.req, self.connect, self.h2c) = (resp, req, connect, h2c);
+		(self.ctx, self.close_after, self.seq) = (ctx, close_after, seq);
+		return ok;
+	}
+
+	// counts shared by every connection of a server, once `max_concurrent_requests` are in handlers
+	// further ones are answered with 503 right away
+	pub fn inflight(&mut self, counter: Arc<AtomicUsize>) -> &mut Self {
+		self.inflight = Some(counter);
+		return self;
+	}
+
+	fn acquire(&self) -> Option<InflightGuard> {
+		return match (self.inflight.as_ref(), self.cfg.max_concurrent_requests) {
+			(Some(counter), Some(limit)) => {
+				if counter.fetch_add(1, ATOMIC_ORDERING) >= limit {
+					counter.fetch_sub(1, ATOMIC_ORDERING);
+					None
+				} else {
+					Some(InflightGuard { counter: Some(Arc::clone(counter)) })
+				}
+			}
+			_ => {
+				Some(InflightGuard { counter: None })
+			}
+		};
+	}
+
+	fn respond_with(&mut self, code: StatusCode) {
+		let mut resp = Response::new();
+		resp.builder().status(code);
+		self.resp = Some(resp);
+	}
+
+	async fn read_request(&mut self) -> ConnState {
+		let mut req = Request::new();
+		req.set_peer_addr(self.addr);
+		req.set_state(self.state.clone());
+		let mut buffered = false;
+		match self.rbuf.take() {
+			Some(rb) => {
+				buffered = rb.remains() > 0;
+				req.set_read_buffer(rb);
+			}
+			None => {}
+		}
+		// the socket is read again only once the pipelined requests are answered
+		if !buffered && !self.flush_pipeline().await {
+			return ConnState::Closing;
+		}
+		// sequence number of the request being read, see `ResponseOrder`
+		self.seq = self.order.next + self.pipeline.len() as u64;
+
+		let cfg = Arc::clone(&self.cfg);
+		// an idle connection waits for the first bytes of its next request, the request
+		// timeouts only start with them
+		if self.requests > 0 && !buffered {
+			match cfg.limits.keep_alive_timeout {
+				Some(duration) => {
+					let err = tokio::select! {
+						e = req.fill_buffer(&mut self.r) => {
+							e
+						}
+						_ = cfg.clock.sleep(duration) => {
+							return ConnState::Closing;
+						}
+					};
+					match err {
+						Some(e) => {
+							self.reject(e).await;
+							return ConnState::Closing;
+						}
+						None => {}
+					}
+				}
+				None => {}
+			}
+		}
+
+		let started = cfg.clock.now();
+		let head_timeout = match (cfg.limits.header_timeout, cfg.limits.read_timeout) {
+			(Some(a), Some(b)) => {
+				Some(a.min(b))
+			}
+			(a, b) => {
+				a.or(b)
+			}
+		};
+		match within(&cfg, head_timeout, req.read_head(&mut self.r, &cfg)).await {
+			Some(e) => {
+				self.reject(e).await;
+				return ConnState::Closing;
+			}
+			None => {}
+		}
+		self.requests += 1;
+		self.last_request = cfg.limits.max_keep_alive_requests.is_some_and(|n| self.requests >= n);
+		self.respond_after_drain = false;
+		self.close_after = !self.cfg.keep_alive || self.last_request || wants_close(req.headers());
+		self.ctx = ResponseContext::of(&req, &cfg);
+		self.ctx.closing |= self.last_request;
+		self.connect = None;
+
+		if self.server_is_closing.load(ATOMIC_ORDERING) {
+			self.close_after = true;
+			self.ctx.closing = true;
+			match self.cfg.shutdown_status {
+				Some(code) => {
+					// the body is left unread, the connection is closed right after
+					self.respond_with(code);
+					return ConnState::Writing;
+				}
+				None => {}
+			}
+		}
+
+		let rejected = match self.cfg.pre_body_check.as_ref() {
+			Some(check) => {
+				check(&req)
+			}
+			None => {
+				None
+			}
+		};
+		match rejected {
+			Some(code) => {
+				// answer first, the client may be waiting for it before sending the body
+				self.respond_with(code);
+				self.req = Some(req);
+				return ConnState::Writing;
+			}
+			None => {}
+		}
+
+		if req.method() == methods::CONNECT {
+			let status = if self.cfg.tunnel_handler.is_none() {
+				Some(StatusCode::MethodNotAllowed)
+			} else if !is_authority_form(req.path()) {
+				Some(StatusCode::BadRequest)
+			} else {
+				None
+			};
+			match status {
+				Some(code) => {
+					self.respond_with(code);
+					self.close_after = true;
+					return ConnState::Writing;
+				}
+				None => {}
+			}
+			self.connect = Some(req.path().to_string());
+		}
+
+		if req.method() == methods::TRACE {
+			let resp = if self.cfg.trace_echo {
+				self.trace(&req)
+			} else {
+				let mut resp = Response::new();
+				resp.builder().status(StatusCode::MethodNotAllowed).headers().append(hns::ALLOW, self.cfg.allow_methods.as_str());
+				resp
+			};
+			// a TRACE body is meaningless, it is read past like the one of a server-wide request
+			self.resp = Some(resp);
+			self.respond_after_drain = true;
+			self.req = Some(req);
+			return ConnState::Draining;
+		}
+
+		if req.path() == "*" {
+			// asterisk-form targets the server itself, only OPTIONS may use it
+			self.resp = Some(self.server_wide(&req));
+			self.respond_after_drain = true;
+			self.req = Some(req);
+			return ConnState::Draining;
+		}
+
+		let has_body = req.headers().is_some_and(|h| h.content_length().unwrap_or(0) > 0 || h.is_chunked());
+		if req.version() == "HTTP/1.1" && has_body && wants_continue(req.headers()) {
+			// flushed right away, the client may hold the body back until it sees this; earlier
+			// responses go first
+			if !self.flush_pipeline().await {
+				return ConnState::Closing;
+			}
+			let interim = async {
+				self.w.write_all(b"HTTP/1.1 100 Continue\r\n\r\n").await?;
+				return self.w.flush().await;
+			};
+			if interim.await.is_err() {
+				return ConnState::Closing;
+			}
+		}
+
+		let cfg = Arc::clone(&self.cfg);
+		// what is left of `read_timeout` after the head
+		let body_timeout = cfg.limits.read_timeout.map(|d| {
+			return d.saturating_sub(cfg.clock.now().duration_since(started).unwrap_or(Duration::ZERO));
+		});
+		let err = if self.close_after && cfg.reject_trailing_bytes {
+			within(&cfg, body_timeout, req.read_body_exact(&mut self.r, &cfg)).await
+		} else {
+			within(&cfg, body_timeout, req.read_body(&mut self.r, &cfg)).await
+		};
+		match err {
+			Some(e) => {
+				self.reject(e).await;
+				return ConnState::Closing;
+			}
+			None => {}
+		}
+		self.rbuf = req.take_read_buffer();
+		if self.cfg.h2c_handler.is_some() && is_h2c_upgrade(&req) {
+			// the request is read as HTTP/1.1, the answer to it comes over HTTP/2
+			let mut resp = Response::new();
+			resp.builder().status(StatusCode::SwitchingProtocols).headers()
+				.append(hns::CONNECTION, "Upgrade")
+				.append(hns::UPGRADE, "h2c");
+			self.resp = Some(resp);
+			self.h2c = Some(req);
+			return ConnState::Writing;
+		}
+		self.req = Some(req);
+		return ConnState::Handling;
+	}
+
+	async fn drain(&mut self) -> ConnState {
+		let mut req = self.req.take().unwrap();
+		match req.drain_body(&mut self.r, self.cfg.max_drain_body_size).await {
+			Some(e) => {
+				if !self.respond_after_drain || !e.is_protocol() {
+					return ConnState::Closing;
+				}
+				self.close_after = true;
+			}
+			None => {}
+		}
+		self.rbuf = req.take_read_buffer();
+		if self.respond_after_drain {
+			self.respond_after_drain = false;
+			return ConnState::Writing;
+		}
+		return ConnState::ReadingRequest;
+	}
+
+	async fn write(&mut self) -> ConnState {
+		if !self.flush_pipeline().await {
+			return ConnState::Closing;
+		}
+		return self.write_current().await;
+	}
+
+	async fn write_current(&mut self) -> ConnState {
+		let resp = self.resp.take().unwrap();
+		self.order.push(self.seq, resp);
+		let mut resp = match self.order.pop() {
+			Some(v) => {
+				v
+			}
+			None => {
+				// an earlier response is still pending
+				return ConnState::ReadingRequest;
+			}
+		};
+		// the response to a rejected head goes out before its body is drained
+		let drain_after = self.req.is_some();
+		if self.server_is_closing.load(ATOMIC_ORDERING) {
+			// what was asked before the shutdown is still answered, the client is told not to ask again
+			self.ctx.closing = true;
+			self.close_after = true;
+		}
+
+		let tunneling = self.connect.is_some() && (200..300).contains(&resp.statuscode());
+		if tunneling {
+			// a 2xx to CONNECT has no body, the tunnel starts right after the head
+			resp.builder().bodyless().headers().remove(hns::CONTENT_LENGTH).remove(hns::TRANSFER_ENCODING);
+		}
+
+		resp.finalize_with(&self.ctx, &self.cfg);
+
+		let upgrading = self.h2c.is_some() && resp.statuscode() == StatusCode::SwitchingProtocols as u16;
+		if upgrading {
+			// whatever `Connection` says, the connection goes on as HTTP/2
+			if self.write_response(&mut resp).await {
+				let req = self.h2c.take().unwrap();
+				let authority = req.authority().unwrap_or("").to_string();
+				self.tunnel = Some(Connect { authority, buffered: self.take_buffered(), request: Some(req), upgrade: None });
+			}
+			return ConnState::Closing;
+		}
+		self.h2c = None;
+
+		let upgrade = if resp.statuscode() == StatusCode::SwitchingProtocols as u16 { resp.take_upgrade() } else { None };
+		if upgrade.is_some() {
+			// the handler's protocol follows the head, bytes past the request are its first ones
+			if self.write_response(&mut resp).await {
+				self.tunnel = Some(Connect { authority: String::new(), buffered: self.take_buffered(), request: None, upgrade });
+			}
+			return ConnState::Closing;
+		}
+
+		if !self.write_response(&mut resp).await || self.close_after || wants_close(resp.headers()) {
+			return ConnState::Closing;
+		}
+		if tunneling {
+			let buffered = self.take_buffered();
+			self.tunnel = Some(Connect { authority: self.connect.take().unwrap(), buffered, request: None, upgrade: None });
+			return ConnState::Closing;
+		}
+		if drain_after {
+			return ConnState::Draining;
+		}
+		return ConnState::ReadingRequest;
 	}
 
-	pub async fn as_server(&mut self, handler: Arc<dyn Handler + Send + Sync>) {
+	// bytes read past the last request, the first ones of a tunnel
+	fn take_buffered(&mut self) -> BytesMut {
+		let mut buffered = BytesMut::new();
+		match self.rbuf.as_ref() {
+			Some(rb) => {
+				buffered.extend_from_slice(rb.unread());
+			}
+			None => {}
+		}
+		return buffered;
+	}
+
+	// Serves requests until the connection is done. A `Connect` is returned when a `CONNECT` request
+	// or an h2c upgrade was accepted, the connection then belongs to the tunnel, see `into_tunnel`.
+	pub async fn as_server(&mut self, handler: Arc<dyn Handler + Send + Sync>) -> Option<Connect> {
 		loop {
-			let mut req = Request::new();
+			match self.step(&handler).await {
+				ConnState::Closing => {
+					match self.cfg.conn_observer.as_ref() {
+						Some(observer) => {
+							observer(&self.metrics());
+						}
+						None => {}
+					}
+					return self.tunnel.take();
+				}
+				_ => {}
+			}
+		}
+	}
 
-			req.from(&mut self.r).await.unwrap();
+	// bytes read and written so far, the buffered ones of a pipelined request included
+	pub fn metrics(&self) -> ConnMetrics {
+		return ConnMetrics {
+			peer: self.addr,
+			bytes_read: self.r.count(),
+			bytes_written: self.w.count(),
+		};
+	}
 
-			let mut resp = handler.handle(req).await.unwrap();
+	pub fn into_tunnel(self, connect: Connect) -> Tunnel<R, W> {
+		return Tunnel {
+			authority: connect.authority,
+			buffered: connect.buffered,
+			request: connect.request,
+			r: self.r.into_inner(),
+			w: self.w.into_inner(),
+		};
+	}
+}
 
-			if self.server_is_closing.load(ATOMIC_ORDERING) {
-				return;
+// The response of `handler`, `None` once `handler_timeout` passed. Only producing the response is
+// timed, writing it out is not.
+fn handle(handler: &Arc<dyn Handler + Send + Sync>, req: Request, cfg: Arc<Config>, guard: InflightGuard) -> impl Future<Output=Option<Result<Response, Error>>> + Send + 'static {
+	let fut = handler.handle(req);
+	return async move {
+		let result = match cfg.limits.handler_timeout {
+			Some(duration) => {
+				tokio::select! {
+					result = fut => {
+						Some(result)
+					}
+					_ = cfg.clock.sleep(duration) => {
+						None
+					}
+				}
+			}
+			None => {
+				Some(fut.await)
 			}
+		};
+		drop(guard);
+		return result;
+	};
+}
+
+// `fut`, or a 408 once `duration` passed
+async fn within<F: Future<Output=Option<ParseError>>>(cfg: &Config, duration: Option<Duration>, fut: F) -> Option<ParseError> {
+	return match duration {
+		Some(duration) => {
+			tokio::select! {
+				e = fut => {
+					e
+				}
+				_ = cfg.clock.sleep(duration) => {
+					Some(ParseError::uekind(REQUEST_TIMEOUT, ParseErrorKind::Timeout))
+				}
+			}
+		}
+		None => {
+			fut.await
+		}
+	};
+}
+
+// `Connection: close` from either side ends the connection after the response
+fn wants_close(headers: Option<&Headers>) -> bool {
+	return match headers.and_then(|h| h.get_combined(hns::CONNECTION)) {
+		Some(v) => {
+			v.split(',').any(|token| { return token.trim().eq_ignore_ascii_case("close"); })
+		}
+		None => {
+			false
+		}
+	};
+}
+
+// `Expect: 100-continue`, the client waits for a 100 before sending the body (RFC 9110 10.1.1)
+fn wants_continue(headers: Option<&Headers>) -> bool {
+	return headers.and_then(|h| h.get(hns::EXPECT)).is_some_and(|v| v.trim().eq_ignore_ascii_case("100-continue"));
+}
+
+// `Upgrade: h2c` with exactly one `HTTP2-Settings`, both named in `Connection` (RFC 7540 3.2)
+fn is_h2c_upgrade(req: &Request) -> bool {
+	let headers = match req.headers() {
+		Some(h) => {
+			h
+		}
+		None => {
+			return false;
+		}
+	};
+	let has_token = |name: &str, token: &str| {
+		return headers.get_combined(name).is_some_and(|v| {
+			return v.split(',').any(|t| t.trim().eq_ignore_ascii_case(token));
+		});
+	};
+	return has_token(hns::UPGRADE, "h2c")
+		&& headers.get_all(hns::HTTP2_SETTINGS).is_some_and(|v| v.len() == 1)
+		&& has_token(hns::CONNECTION, "upgrade")
+		&& has_token(hns::CONNECTION, "http2-settings");
+}
+
+// `host:port`, the only target form of CONNECT
+fn is_authority_form(target: &str) -> bool {
+	return match target.rfind(':') {
+		Some(idx) => {
+			let (host, port) = (&target[..idx], &target[idx + 1..]);
+			!host.is_empty() && !host.contains('/') && !host.contains('@')
+				&& !port.is_empty() && port.len() <= 5 && port.bytes().all(|b| b.is_ascii_digit())
+		}
+		None => {
+			false
+		}
+	};
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io;
+	use std::pin::Pin;
+	use std::sync::Arc;
+	use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+	use std::task::{Context, Poll};
+	use std::time::{Duration, UNIX_EPOCH};
+	use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+	use crate::h2tp::cfg::Config;
+	use crate::h2tp::clock::MockClock;
+	use crate::h2tp::conn::{Conn, ConnState, ResponseOrder, Tunnel};
+	use crate::h2tp::FuncHandler;
+	use crate::h2tp::handler::{BoxedFuture, Handler};
+	use crate::h2tp::headers::mime;
+	use crate::h2tp::request::Request;
+	use crate::h2tp::response::Response;
+	use crate::h2tp::status_code::StatusCode;
+	use crate::h2tp::types::{BoxedReader, BoxedWriter, Upgraded};
+
+	async fn serve(cfg: Config, handler: Arc<dyn Handler + Send + Sync>, raw: &[u8]) -> String {
+		let (client, server) = tokio::io::duplex(65536);
+		let (sr, sw) = tokio::io::split(server);
+		let mut conn = Conn::new(
+			"127.0.0.1:8080".parse().unwrap(), sr, sw,
+			Arc::new(AtomicBool::new(false)), None, Arc::new(cfg),
+		);
+		let task = tokio::spawn(async move {
+			conn.as_server(handler).await;
+		});
+
+		let (mut cr, mut cw) = tokio::io::split(client);
+		cw.write_all(raw).await.unwrap();
+		cw.shutdown().await.unwrap();
+		let mut out = vec![];
+		cr.read_to_end(&mut out).await.unwrap();
+		task.await.unwrap();
+		return String::from_utf8_lossy(&out).to_string();
+	}
+
+	fn hello() -> Arc<dyn Handler + Send + Sync> {
+		return Arc::new(FuncHandler::new(|_| {
+			Box::pin(async {
+				let mut resp = Response::new();
+				resp.builder().body(b"Hello World");
+				return Ok(resp);
+			})
+		}));
+	}
+
+	#[tokio::test]
+	async fn test_shared_state() {
+		let (client, server) = tokio::io::duplex(4096);
+		let (sr, sw) = tokio::io::split(server);
+		let counter = Arc::new(AtomicUsize::new(0));
+		let mut conn = Conn::new(
+			"127.0.0.1:8080".parse().unwrap(), sr, sw,
+			Arc::new(AtomicBool::new(false)), Some(counter.clone()), Arc::new(Config::new()),
+		);
+		let handler = Arc::new(FuncHandler::new(|req| {
+			Box::pin(async move {
+				let counter = req.state::<AtomicUsize>().unwrap();
+				let n = counter.fetch_add(1, Ordering::SeqCst) + 1;
+				let mut resp = Response::new();
+				resp.builder().body(n.to_string().as_bytes());
+				return Ok(resp);
+			})
+		}));
+		let task = tokio::spawn(async move {
+			conn.as_server(handler).await;
+		});
+
+		let (mut cr, mut cw) = tokio::io::split(client);
+		let mut buf = [0u8; 1024];
+		for expected in ["1", "2"] {
+			cw.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+			let size = cr.read(&mut buf).await.unwrap();
+			let text = std::str::from_utf8(&buf[..size]).unwrap();
+			assert!(text.ends_with(&format!("\r\n\r\n{}", expected)));
+		}
+		cw.shutdown().await.unwrap();
+		task.await.unwrap();
+		assert_eq!(counter.load(Ordering::SeqCst), 2);
+	}
+
+	#[tokio::test]
+	async fn test_server_header() {
+		let raw = b"GET / HTTP/1.1\r\n\r\n";
+
+		let out = serve(Config::new(), hello(), raw).await;
+		assert!(out.contains(&format!("\r\nServer: spk/{}\r\n", env!("CARGO_PKG_VERSION"))));
+
+		let mut cfg = Config::new();
+		cfg.server_header = Some("custom/1.0".to_string());
+		let out = serve(cfg, hello(), raw).await;
+		assert!(out.contains("\r\nServer: custom/1.0\r\n"));
+
+		let mut cfg = Config::new();
+		cfg.server_header = None;
+		let out = serve(cfg, hello(), raw).await;
+		assert!(!out.contains("Server:"));
+		assert!(out.ends_with("Hello World"));
+	}
+
+	// accepts `limit` bytes, then never becomes writable again
+	struct StalledWriter {
+		written: usize,
+		limit: usize,
+	}
+
+	impl AsyncWrite for StalledWriter {
+		fn poll_write(mut self: Pin<&mut Self>, _: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+			if self.written >= self.limit {
+				return Poll::Pending;
+			}
+			let size = buf.len().min(self.limit - self.written);
+			self.written += size;
+			return Poll::Ready(Ok(size));
+		}
+
+		fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+			return Poll::Ready(Ok(()));
+		}
+
+		fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+			return Poll::Ready(Ok(()));
+		}
+	}
+
+	#[tokio::test]
+	async fn test_write_timeout() {
+		let mut cfg = Config::new();
+		cfg.write_timeout = Some(Duration::from_millis(50));
+		let r: &[u8] = b"GET / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\n";
+		let mut conn = Conn::new(
+			"127.0.0.1:8080".parse().unwrap(), r, StalledWriter { written: 0, limit: 8 },
+			Arc::new(AtomicBool::new(false)), None, Arc::new(cfg),
+		);
+		let result = tokio::time::timeout(Duration::from_secs(5), conn.as_server(hello())).await;
+		assert!(result.is_ok());
+		assert_eq!(conn.w.get_ref().written, 8);
+	}
+
+	#[tokio::test]
+	async fn test_write_timeout_mock_clock() {
+		let clock = Arc::new(MockClock::new(UNIX_EPOCH));
+		let mut cfg = Config::new();
+		cfg.write_timeout = Some(Duration::from_secs(30));
+		cfg.clock = clock.clone();
+		let r: &[u8] = b"GET / HTTP/1.1\r\n\r\n";
+		let mut conn = Conn::new(
+			"127.0.0.1:8080".parse().unwrap(), r, StalledWriter { written: 0, limit: 8 },
+			Arc::new(AtomicBool::new(false)), None, Arc::new(cfg),
+		);
+		let task = tokio::spawn(async move {
+			conn.as_server(hello()).await;
+		});
+
+		// nothing expires while the mock clock stands still
+		tokio::time::sleep(Duration::from_millis(20)).await;
+		assert!(!task.is_finished());
+
+		let result = tokio::time::timeout(Duration::from_secs(5), async {
+			while !task.is_finished() {
+				clock.advance(Duration::from_secs(1));
+				tokio::task::yield_now().await;
+			}
+		}).await;
+		assert!(result.is_ok());
+	}
+
+	#[tokio::test]
+	async fn test_keep_alive_limits() {
+		let mut cfg = Config::new();
+		cfg.server_header = None;
+		cfg.date_header = false;
+		cfg.limits.max_keep_alive_requests(Some(2));
+		let out = serve(cfg, hello(), b"GET / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\n").await;
+		assert_eq!(
+			out,
+			"HTTP/1.1 200 OK\r\nContent-Length: 11\r\n\r\nHello World\
+			HTTP/1.1 200 OK\r\nContent-Length: 11\r\nConnection: close\r\n\r\nHello World",
+		);
+
+		// an idle connection is closed without a word, a slow request gets a 408
+		let mut cfg = Config::new();
+		cfg.limits.keep_alive_timeout(Some(Duration::from_millis(30))).read_timeout(Some(Duration::from_millis(60)));
+		let (client, server) = tokio::io::duplex(4096);
+		let (sr, sw) = tokio::io::split(server);
+		let mut conn = Conn::new(
+			"127.0.0.1:8080".parse().unwrap(), sr, sw,
+			Arc::new(AtomicBool::new(false)), None, Arc::new(cfg.clone()),
+		);
+		let (mut cr, mut cw) = tokio::io::split(client);
+		cw.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+		let result = tokio::time::timeout(Duration::from_secs(5), conn.as_server(hello())).await;
+		assert!(result.is_ok());
+		drop(conn);
+		let mut out = vec![];
+		cr.read_to_end(&mut out).await.unwrap();
+		let out = String::from_utf8(out).unwrap();
+		assert!(out.starts_with("HTTP/1.1 200 OK\r\n") && out.ends_with("Hello World"), "{}", out);
+
+		let (client, server) = tokio::io::duplex(4096);
+		let (sr, sw) = tokio::io::split(server);
+		let mut conn = Conn::new(
+			"127.0.0.1:8080".parse().unwrap(), sr, sw,
+			Arc::new(AtomicBool::new(false)), None, Arc::new(cfg),
+		);
+		let (mut cr, mut cw) = tokio::io::split(client);
+		// the head in time, not the body
+		cw.write_all(b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nHel").await.unwrap();
+		let result = tokio::time::timeout(Duration::from_secs(5), conn.as_server(hello())).await;
+		assert!(result.is_ok());
+		let mut buf = [0u8; 1024];
+		let size = cr.read(&mut buf).await.unwrap();
+		assert!(std::str::from_utf8(&buf[..size]).unwrap().starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+	}
+
+	#[tokio::test]
+	async fn test_handler_timeout() {
+		let handler: Arc<dyn Handler + Send + Sync> = Arc::new(FuncHandler::new(|_| {
+			Box::pin(async {
+				tokio::time::sleep(Duration::from_secs(60)).await;
+				return Ok(Response::new());
+			})
+		}));
+		let mut cfg = Config::new();
+		cfg.server_header = None;
+		cfg.date_header = false;
+		cfg.limits.handler_timeout = Some(Duration::from_millis(50));
+		let out = tokio::time::timeout(
+			Duration::from_secs(5),
+			serve(cfg.clone(), handler, b"GET / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\n"),
+		).await.unwrap();
+		assert_eq!(out, "HTTP/1.1 504 Gateway Timeout\r\nConnection: close\r\nContent-Length: 0\r\n\r\n");
+
+		// handlers in time are not affected
+		let out = serve(cfg, hello(), b"GET / HTTP/1.1\r\n\r\n").await;
+		assert!(out.starts_with("HTTP/1.1 200 OK\r\n"));
+	}
+
+	#[tokio::test]
+	async fn test_date_header() {
+		let mut cfg = Config::new();
+		cfg.server_header = None;
+		cfg.clock = Arc::new(MockClock::new(UNIX_EPOCH + Duration::from_secs(784111777)));
+		let out = serve(cfg, hello(), b"GET / HTTP/1.1\r\n\r\n").await;
+		assert_eq!(out, "HTTP/1.1 200 OK\r\nContent-Length: 11\r\nDate: Sun, 06 Nov 1994 08:49:37 GMT\r\n\r\nHello World");
+
+		let mut cfg = Config::new();
+		cfg.date_header = false;
+		let out = serve(cfg, hello(), b"GET / HTTP/1.1\r\n\r\n").await;
+		assert!(!out.contains("Date:"));
+	}
+
+	#[tokio::test]
+	async fn test_pipelined_requests() {
+		let mut cfg = Config::new();
+		cfg.server_header = None;
+		cfg.date_header = false;
+		cfg.limits.max_pipelined_requests(Some(4));
+		let (client, server) = tokio::io::duplex(256);
+		let (sr, sw) = tokio::io::split(server);
+		let mut conn = Conn::new(
+			"127.0.0.1:8080".parse().unwrap(), sr, sw,
+			Arc::new(AtomicBool::new(false)), None, Arc::new(cfg),
+		);
+		let max = Arc::new(AtomicUsize::new(0));
+		let handler: Arc<dyn Handler + Send + Sync> = Arc::new(Concurrency { current: Arc::new(AtomicUsize::new(0)), max: Arc::clone(&max) });
+		let task = tokio::spawn(async move {
+			conn.as_server(handler).await;
+		});
+
+		let (mut cr, mut cw) = tokio::io::split(client);
+		let writer = tokio::spawn(async move {
+			let mut raw = String::new();
+			for i in 0..100 {
+				raw.push_str(&format!("GET /{:03} HTTP/1.1\r\n\r\n", i));
+			}
+			// the duplex holds 256 bytes only, so this completes only as the server consumes requests
+			cw.write_all(raw.as_bytes()).await.unwrap();
+			cw.shutdown().await.unwrap();
+		});
+
+		let mut out = vec![];
+		cr.read_to_end(&mut out).await.unwrap();
+		writer.await.unwrap();
+		task.await.unwrap();
+
+		let mut expected = String::new();
+		for i in 0..100 {
+			expected.push_str(&format!("HTTP/1.1 200 OK\r\nContent-Length: 3\r\n\r\n{:03}", i));
+		}
+		assert_eq!(String::from_utf8(out).unwrap(), expected);
+		// a read brings in a dozen requests, only four of them are handled at once
+		assert_eq!(max.load(Ordering::SeqCst), 4);
+	}
+
+	// what `LoggedReader` and `LoggedWriter` saw, shared
+	#[derive(Default)]
+	struct PipeLog {
+		written: Vec<u8>,
+		// responses written by the time of each read
+		responses_at_read: Vec<usize>,
+	}
+
+	impl PipeLog {
+		fn responses(&self) -> usize {
+			return self.written.windows(9).filter(|w| w == b"HTTP/1.1 ").count();
+		}
+	}
+
+	// hands out one chunk per read
+	struct LoggedReader {
+		chunks: Vec<Vec<u8>>,
+		log: Arc<std::sync::Mutex<PipeLog>>,
+	}
+
+	impl tokio::io::AsyncRead for LoggedReader {
+		fn poll_read(mut self: Pin<&mut Self>, _: &mut Context<'_>, buf: &mut tokio::io::ReadBuf<'_>) -> Poll<io::Result<()>> {
+			let mut log = self.log.lock().unwrap();
+			let responses = log.responses();
+			log.responses_at_read.push(responses);
+			drop(log);
+			if !self.chunks.is_empty() {
+				let chunk = self.chunks.remove(0);
+				buf.put_slice(&chunk);
+			}
+			return Poll::Ready(Ok(()));
+		}
+	}
+
+	struct LoggedWriter {
+		log: Arc<std::sync::Mutex<PipeLog>>,
+	}
+
+	impl AsyncWrite for LoggedWriter {
+		fn poll_write(self: Pin<&mut Self>, _: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+			self.log.lock().unwrap().written.extend_from_slice(buf);
+			return Poll::Ready(Ok(buf.len()));
+		}
+
+		fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+			return Poll::Ready(Ok(()));
+		}
+
+		fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+			return Poll::Ready(Ok(()));
+		}
+	}
+
+	// answers with the path after a while, counting how many requests it has at once
+	struct Concurrency {
+		current: Arc<AtomicUsize>,
+		max: Arc<AtomicUsize>,
+	}
+
+	impl Handler for Concurrency {
+		fn handle(&self, req: Request) -> BoxedFuture {
+			let (current, max) = (Arc::clone(&self.current), Arc::clone(&self.max));
+			return Box::pin(async move {
+				let n = current.fetch_add(1, Ordering::SeqCst) + 1;
+				max.fetch_max(n, Ordering::SeqCst);
+				tokio::time::sleep(Duration::from_millis(5)).await;
+				current.fetch_sub(1, Ordering::SeqCst);
+				let mut resp = Response::new();
+				resp.builder().body(&req.path().as_bytes()[1..]);
+				return Ok(resp);
+			});
+		}
+	}
+
+	#[tokio::test]
+	async fn test_pipeline_depth() {
+		for (limit, depth) in [(Some(2), 2), (Some(1), 1), (None, 5)] {
+			let mut cfg = Config::new();
+			cfg.server_header = None;
+			cfg.date_header = false;
+			cfg.limits.max_pipelined_requests(limit);
+			let log = Arc::new(std::sync::Mutex::new(PipeLog::default()));
+			let chunks = vec![
+				(1..=5).map(|i| format!("GET /{} HTTP/1.1\r\n\r\n", i)).collect::<String>().into_bytes(),
+				"GET /6 HTTP/1.1\r\n\r\nGET /7 HTTP/1.1\r\n\r\n".to_string().into_bytes(),
+			];
+			let mut conn = Conn::new(
+				"127.0.0.1:8080".parse().unwrap(),
+				LoggedReader { chunks, log: Arc::clone(&log) }, LoggedWriter { log: Arc::clone(&log) },
+				Arc::new(AtomicBool::new(false)), None, Arc::new(cfg),
+			);
+			let max = Arc::new(AtomicUsize::new(0));
+			conn.as_server(Arc::new(Concurrency { current: Arc::new(AtomicUsize::new(0)), max: Arc::clone(&max) })).await;
+
+			// five requests came in one read; no more than the limit are taken from it at once, and
+			// the socket is read again only once all five are answered
+			assert_eq!(max.load(Ordering::SeqCst), depth);
+			let log = log.lock().unwrap();
+			assert_eq!(log.responses_at_read, vec![0, 5, 7]);
+			let expected = (1..=7).map(|i| format!("HTTP/1.1 200 OK\r\nContent-Length: 1\r\n\r\n{}", i)).collect::<String>();
+			assert_eq!(String::from_utf8(log.written.clone()).unwrap(), expected);
+		}
+	}
+
+	// starts the shutdown once the first response is out
+	struct ShutdownAfterFlush<W> {
+		w: W,
+		closing: Arc<AtomicBool>,
+	}
+
+	impl<W: AsyncWrite + Unpin> AsyncWrite for ShutdownAfterFlush<W> {
+		fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+			return Pin::new(&mut self.w).poll_write(cx, buf);
+		}
+
+		fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+			self.closing.store(true, Ordering::SeqCst);
+			return Pin::new(&mut self.w).poll_flush(cx);
+		}
+
+		fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+			return Pin::new(&mut self.w).poll_shutdown(cx);
+		}
+	}
+
+	#[tokio::test]
+	async fn test_requests_during_shutdown() {
+		let raw = b"GET /1 HTTP/1.1\r\n\r\nGET /2 HTTP/1.1\r\n\r\nGET /3 HTTP/1.1\r\n\r\n";
+		let first = "HTTP/1.1 200 OK\r\nContent-Length: 11\r\n\r\nHello World";
+		for (status, second) in [
+			(Some(StatusCode::ServiceUnavailable), "HTTP/1.1 503 Service Unavailable\r\nConnection: close\r\nContent-Length: 0\r\n\r\n"),
+			(None, "HTTP/1.1 200 OK\r\nContent-Length: 11\r\nConnection: close\r\n\r\nHello World"),
+		] {
+			let mut cfg = Config::new();
+			cfg.server_header = None;
+			cfg.date_header = false;
+			cfg.shutdown_status = status;
+			// one at a time, the second request is read once the shutdown started
+			cfg.limits.max_pipelined_requests(Some(1));
+			let (client, server) = tokio::io::duplex(4096);
+			let (sr, sw) = tokio::io::split(server);
+			let closing = Arc::new(AtomicBool::new(false));
+			let mut conn = Conn::new(
+				"127.0.0.1:8080".parse().unwrap(), sr, ShutdownAfterFlush { w: sw, closing: Arc::clone(&closing) },
+				closing, None, Arc::new(cfg),
+			);
+			let (mut cr, mut cw) = tokio::io::split(client);
+			cw.write_all(raw).await.unwrap();
+			conn.as_server(hello()).await;
+			drop(conn);
+
+			// the third request is never answered, the second one said the connection is done
+			let mut out = vec![];
+			cr.read_to_end(&mut out).await.unwrap();
+			assert_eq!(String::from_utf8(out).unwrap(), format!("{}{}", first, second));
+		}
+	}
+
+	#[test]
+	fn test_response_order() {
+		let mut order = ResponseOrder::new();
+		order.push(1, "second");
+		assert!(order.pop().is_none());
+		order.push(0, "first");
+		order.push(2, "third");
+		assert_eq!(order.pop(), Some("first"));
+		assert_eq!(order.pop(), Some("second"));
+		assert_eq!(order.pop(), Some("third"));
+		assert!(order.pop().is_none());
+	}
+
+	#[tokio::test]
+	async fn test_pipelined_order() {
+		let mut cfg = Config::new();
+		cfg.server_header = None;
+		cfg.date_header = false;
+		// the first request takes longer, its response must still be first
+		let handler: Arc<dyn Handler + Send + Sync> = Arc::new(FuncHandler::new(|req| {
+			Box::pin(async move {
+				if req.path() == "/slow" {
+					tokio::time::sleep(Duration::from_millis(20)).await;
+				}
+				let mut resp = Response::new();
+				resp.builder().body(&req.path().as_bytes()[1..]);
+				return Ok(resp);
+			})
+		}));
+		let out = serve(cfg, handler, b"GET /slow HTTP/1.1\r\n\r\nGET /fast HTTP/1.1\r\n\r\n").await;
+		assert_eq!(out, "HTTP/1.1 200 OK\r\nContent-Length: 4\r\n\r\nslowHTTP/1.1 200 OK\r\nContent-Length: 4\r\n\r\nfast");
+	}
+
+	#[tokio::test]
+	async fn test_options_asterisk() {
+		let mut cfg = Config::new();
+		cfg.server_header = None;
+		cfg.date_header = false;
+		cfg.allow_methods = "GET, OPTIONS".to_string();
+		let out = serve(cfg, hello(), b"OPTIONS * HTTP/1.1\r\n\r\nGET * HTTP/1.1\r\n\r\n").await;
+		assert_eq!(
+			out,
+			"HTTP/1.1 200 OK\r\nAllow: GET, OPTIONS\r\nContent-Length: 0\r\n\r\nHTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n",
+		);
+	}
+
+	#[tokio::test]
+	async fn test_drain_unread_body() {
+		let mut cfg = Config::new();
+		cfg.server_header = None;
+		cfg.date_header = false;
+		let raw = b"POST /ignored HTTP/1.1\r\nContent-Length: 5\r\n\r\nHelloGET / HTTP/1.1\r\n\r\n";
+		let out = serve(cfg.clone(), hello(), raw).await;
+		assert_eq!(out, "HTTP/1.1 200 OK\r\nContent-Length: 11\r\n\r\nHello World".repeat(2));
+
+		let raw = b"OPTIONS * HTTP/1.1\r\nContent-Length: 5\r\n\r\nHelloGET / HTTP/1.1\r\n\r\n";
+		let out = serve(cfg.clone(), hello(), raw).await;
+		assert!(out.ends_with("\r\n\r\nHello World"));
+		assert_eq!(out.matches("HTTP/1.1 200 OK").count(), 2);
+
+		// too large to skip over, the connection is closed after the response
+		cfg.max_drain_body_size = 4;
+		let out = serve(cfg, hello(), raw).await;
+		assert_eq!(out.matches("HTTP/1.1 200 OK").count(), 1);
+		assert!(!out.contains("Hello World"));
+	}
+
+	#[tokio::test]
+	async fn test_pre_body_check() {
+		let mut cfg = Config::new();
+		cfg.server_header = None;
+		cfg.date_header = false;
+		cfg.pre_body_check = Some(Arc::new(|req| {
+			return match req.headers().and_then(|h| h.content_type()) {
+				Some(mime::JSON) => {
+					None
+				}
+				_ => {
+					Some(StatusCode::UnsupportedMediaType)
+				}
+			};
+		}));
+		let (client, server) = tokio::io::duplex(4096);
+		let (sr, sw) = tokio::io::split(server);
+		let mut conn = Conn::new(
+			"127.0.0.1:8080".parse().unwrap(), sr, sw,
+			Arc::new(AtomicBool::new(false)), None, Arc::new(cfg),
+		);
+		let task = tokio::spawn(async move {
+			conn.as_server(hello()).await;
+		});
+
+		let (mut cr, mut cw) = tokio::io::split(client);
+		cw.write_all(b"POST / HTTP/1.1\r\nContent-Type: text/xml\r\nContent-Length: 10\r\n\r\n<a/>").await.unwrap();
+		// answered while most of the body is still unsent
+		let mut buf = [0u8; 1024];
+		let size = cr.read(&mut buf).await.unwrap();
+		assert_eq!(&buf[..size], b"HTTP/1.1 415 Unsupported Media Type\r\nContent-Length: 0\r\n\r\n");
+
+		cw.write_all(b"</a>\r\nPOST / HTTP/1.1\r\nContent-Type: application/json\r\nContent-Length: 2\r\n\r\n{}").await.unwrap();
+		let size = cr.read(&mut buf).await.unwrap();
+		assert!(std::str::from_utf8(&buf[..size]).unwrap().ends_with("\r\n\r\nHello World"));
+		cw.shutdown().await.unwrap();
+		task.await.unwrap();
+	}
+
+	#[tokio::test]
+	async fn test_continue_flushed() {
+		let mut cfg = Config::new();
+		cfg.server_header = None;
+		cfg.date_header = false;
+		let (client, server) = tokio::io::duplex(4096);
+		let (sr, sw) = tokio::io::split(server);
+		// nothing reaches the client before a flush
+		let mut conn = Conn::new(
+			"127.0.0.1:8080".parse().unwrap(), sr, tokio::io::BufWriter::new(sw),
+			Arc::new(AtomicBool::new(false)), None, Arc::new(cfg),
+		);
+		let task = tokio::spawn(async move {
+			conn.as_server(hello()).await;
+		});
+
+		let (mut cr, mut cw) = tokio::io::split(client);
+		cw.write_all(b"POST / HTTP/1.1\r\nExpect: 100-continue\r\nContent-Length: 4\r\n\r\n").await.unwrap();
+		// the body is sent only once the interim response is in
+		let mut buf = [0u8; 1024];
+		let size = tokio::time::timeout(Duration::from_secs(5), cr.read(&mut buf)).await.unwrap().unwrap();
+		assert_eq!(&buf[..size], b"HTTP/1.1 100 Continue\r\n\r\n");
+
+		cw.write_all(b"ping").await.unwrap();
+		cw.shutdown().await.unwrap();
+		let mut out = vec![];
+		cr.read_to_end(&mut out).await.unwrap();
+		task.await.unwrap();
+		assert_eq!(out.as_slice(), b"HTTP/1.1 200 OK\r\nContent-Length: 11\r\n\r\nHello World");
+	}
+
+	#[tokio::test]
+	async fn test_connect_tunnel() {
+		let out = serve(Config::new(), hello(), b"CONNECT spk.local:443 HTTP/1.1\r\n\r\n").await;
+		assert!(out.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+
+		let mut cfg = Config::new();
+		cfg.server_header = None;
+		cfg.date_header = false;
+		cfg.tunnel_handler = Some(Arc::new(|_| { Box::pin(async {}) }));
+		let out = serve(cfg.clone(), hello(), b"CONNECT /index.html HTTP/1.1\r\n\r\n").await;
+		assert!(out.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+
+		let (client, server) = tokio::io::duplex(4096);
+		let (sr, sw) = tokio::io::split(server);
+		let mut conn = Conn::new(
+			"127.0.0.1:8080".parse().unwrap(), sr, sw,
+			Arc::new(AtomicBool::new(false)), None, Arc::new(cfg),
+		);
+		let (mut cr, mut cw) = tokio::io::split(client);
+		cw.write_all(b"CONNECT spk.local:443 HTTP/1.1\r\nHost: spk.local:443\r\n\r\nping").await.unwrap();
+		let connect = conn.as_server(hello()).await.unwrap();
+		let mut tunnel = conn.into_tunnel(connect);
+		assert_eq!(tunnel.authority, "spk.local:443");
+		assert_eq!(tunnel.buffered.as_ref(), b"ping");
+
+		let mut buf = [0u8; 1024];
+		let size = cr.read(&mut buf).await.unwrap();
+		assert_eq!(&buf[..size], b"HTTP/1.1 200 OK\r\n\r\n");
+
+		// the raw streams are ours now
+		tunnel.w.write_all(b"pong").await.unwrap();
+		let size = cr.read(&mut buf).await.unwrap();
+		assert_eq!(&buf[..size], b"pong");
+		cw.write_all(b"raw bytes").await.unwrap();
+		let size = tunnel.r.read(&mut buf).await.unwrap();
+		assert_eq!(&buf[..size], b"raw bytes");
+	}
+
+	#[tokio::test]
+	async fn test_conn_state() {
+		let handler = hello();
+		let r: &[u8] = b"GET / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\nConnection: keep-alive, Close\r\n\r\nGET / HTTP/1.1\r\n\r\n";
+		let mut cfg = Config::new();
+		cfg.limits.max_pipelined_requests(Some(1));
+		let mut conn = Conn::new(
+			"127.0.0.1:8080".parse().unwrap(), r, Vec::<u8>::new(),
+			Arc::new(AtomicBool::new(false)), None, Arc::new(cfg),
+		);
+
+		// keep-alive goes back to reading
+		assert_eq!(conn.step(&handler).await, ConnState::Handling);
+		assert_eq!(conn.step(&handler).await, ConnState::Writing);
+		assert_eq!(conn.step(&handler).await, ConnState::ReadingRequest);
+
+		// `Connection: close` ends the connection after the response
+		assert_eq!(conn.step(&handler).await, ConnState::Handling);
+		assert_eq!(conn.step(&handler).await, ConnState::Writing);
+		assert_eq!(conn.step(&handler).await, ConnState::Closing);
+		assert_eq!(conn.step(&handler).await, ConnState::Closing);
+		assert_eq!(String::from_utf8_lossy(conn.w.get_ref()).matches("Hello World").count(), 2);
+
+		// the first request is handled while the second one is read, both are answered with it
+		let mut conn = Conn::new(
+			"127.0.0.1:8080".parse().unwrap(), r, Vec::<u8>::new(),
+			Arc::new(AtomicBool::new(false)), None, Arc::new(Config::new()),
+		);
+		assert_eq!(conn.step(&handler).await, ConnState::Handling);
+		assert_eq!(conn.step(&handler).await, ConnState::ReadingRequest);
+		assert_eq!(conn.step(&handler).await, ConnState::Handling);
+		assert_eq!(String::from_utf8_lossy(conn.w.get_ref()).matches("Hello World").count(), 0);
+		assert_eq!(conn.step(&handler).await, ConnState::Writing);
+		assert_eq!(String::from_utf8_lossy(conn.w.get_ref()).matches("Hello World").count(), 1);
+		assert_eq!(conn.step(&handler).await, ConnState::Closing);
+		assert_eq!(String::from_utf8_lossy(conn.w.get_ref()).matches("Hello World").count(), 2);
+
+		let mut cfg = Config::new();
+		cfg.pre_body_check = Some(Arc::new(|_| { Some(StatusCode::Forbidden) }));
+		let r: &[u8] = b"POST / HTTP/1.1\r\nContent-Length: 2\r\n\r\n{}";
+		let mut conn = Conn::new(
+			"127.0.0.1:8080".parse().unwrap(), r, Vec::<u8>::new(),
+			Arc::new(AtomicBool::new(false)), None, Arc::new(cfg),
+		);
+		assert_eq!(conn.step(&handler).await, ConnState::Writing);
+		assert_eq!(conn.step(&handler).await, ConnState::Draining);
+		assert_eq!(conn.step(&handler).await, ConnState::ReadingRequest);
+		assert_eq!(conn.step(&handler).await, ConnState::Closing);
+	}
+
+	struct BlockingHandler {
+		release: Arc<tokio::sync::Notify>,
+	}
+
+	impl Handler for BlockingHandler {
+		fn handle(&self, _: Request) -> BoxedFuture {
+			let release = Arc::clone(&self.release);
+			return Box::pin(async move {
+				release.notified().await;
+				return Ok(Response::new());
+			});
+		}
+	}
+
+	#[tokio::test]
+	async fn test_overload() {
+		let mut cfg = Config::new();
+		cfg.max_concurrent_requests = Some(1);
+		cfg.overload_retry_after = 3;
+		let cfg = Arc::new(cfg);
+		let counter = Arc::new(AtomicUsize::new(0));
+		let release = Arc::new(tokio::sync::Notify::new());
+		let handler: Arc<dyn Handler + Send + Sync> = Arc::new(BlockingHandler { release: Arc::clone(&release) });
+
+		let mut clients = vec![];
+		let mut tasks = vec![];
+		for _ in 0..2 {
+			let (client, server) = tokio::io::duplex(4096);
+			let (sr, sw) = tokio::io::split(server);
+			let mut conn = Conn::new(
+				"127.0.0.1:8080".parse().unwrap(), sr, sw,
+				Arc::new(AtomicBool::new(false)), None, Arc::clone(&cfg),
+			);
+			conn.inflight(Arc::clone(&counter));
+			let hc = Arc::clone(&handler);
+			tasks.push(tokio::spawn(async move {
+				conn.as_server(hc).await;
+			}));
+			clients.push(tokio::io::split(client));
+		}
+
+		clients[0].1.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+		while counter.load(Ordering::SeqCst) == 0 {
+			tokio::task::yield_now().await;
+		}
+		clients[1].1.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+		let mut buf = [0u8; 1024];
+		let size = clients[1].0.read(&mut buf).await.unwrap();
+		let text = std::str::from_utf8(&buf[..size]).unwrap();
+		assert!(text.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+		assert!(text.contains("\r\nRetry-After: 3\r\n"));
+
+		release.notify_one();
+		let size = clients[0].0.read(&mut buf).await.unwrap();
+		assert!(std::str::from_utf8(&buf[..size]).unwrap().starts_with("HTTP/1.1 200 OK\r\n"));
+		assert_eq!(counter.load(Ordering::SeqCst), 0);
+
+		for (_, cw) in clients.iter_mut() {
+			cw.shutdown().await.unwrap();
+		}
+		for task in tasks {
+			task.await.unwrap();
+		}
+	}
+
+	#[tokio::test]
+	async fn test_connection_limits() {
+		let mut cfg = Config::new();
+		cfg.keep_alive = false;
+		let out = serve(cfg, hello(), b"GET / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\n").await;
+		assert_eq!(out.matches("Hello World").count(), 1);
+		assert!(out.contains("\r\nConnection: close\r\n"));
+
+		let mut cfg = Config::new();
+		cfg.limits.max_body_bytes = 4;
+		let out = serve(cfg, hello(), b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nHello").await;
+		assert!(out.starts_with("HTTP/1.1 413 Payload Too Large\r\n"));
+
+		// a head that never completes
+		let mut cfg = Config::new();
+		cfg.limits.header_timeout = Some(Duration::from_millis(20));
+		let (client, server) = tokio::io::duplex(4096);
+		let (sr, sw) = tokio::io::split(server);
+		let mut conn = Conn::new(
+			"127.0.0.1:8080".parse().unwrap(), sr, sw,
+			Arc::new(AtomicBool::new(false)), None, Arc::new(cfg),
+		);
+		let (mut cr, mut cw) = tokio::io::split(client);
+		cw.write_all(b"GET / HTTP/1.1\r\n").await.unwrap();
+		let result = tokio::time::timeout(Duration::from_secs(5), conn.as_server(hello())).await;
+		assert!(result.is_ok());
+		let mut buf = [0u8; 1024];
+		let size = cr.read(&mut buf).await.unwrap();
+		assert!(std::str::from_utf8(&buf[..size]).unwrap().starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+	}
+
+	#[tokio::test]
+	async fn test_te_trailers() {
+		let handler: Arc<dyn Handler + Send + Sync> = Arc::new(FuncHandler::new(|_| {
+			Box::pin(async {
+				let mut resp = Response::new();
+				resp.builder().body(b"ok").trailer("X-Checksum", "1");
+				return Ok(resp);
+			})
+		}));
+		let mut cfg = Config::new();
+		cfg.server_header = None;
+		cfg.date_header = false;
+
+		let out = serve(cfg.clone(), Arc::clone(&handler), b"GET / HTTP/1.1\r\nTE: gzip, Trailers;q=1\r\n\r\n").await;
+		assert_eq!(out, "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nTrailer: X-Checksum\r\n\r\n2\r\nok\r\n0\r\nX-Checksum: 1\r\n\r\n");
+
+		let out = serve(cfg, handler, b"GET / HTTP/1.1\r\n\r\n").await;
+		assert_eq!(out, "HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok");
+	}
+
+	#[tokio::test]
+	async fn test_trailing_bytes() {
+		let mut cfg = Config::new();
+		cfg.server_header = None;
+		cfg.date_header = false;
+		cfg.reject_trailing_bytes = true;
+
+		// keep-alive, what follows the body is the next request
+		let raw = b"POST / HTTP/1.1\r\nContent-Length: 2\r\n\r\n{}GET / HTTP/1.1\r\n\r\n";
+		let out = serve(cfg.clone(), hello(), raw).await;
+		assert_eq!(out.matches("Hello World").count(), 2);
+
+		let raw = b"POST / HTTP/1.1\r\nConnection: close\r\nContent-Length: 2\r\n\r\n{}GET /admin HTTP/1.1\r\n\r\n";
+		let out = serve(cfg.clone(), hello(), raw).await;
+		assert_eq!(out, "HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n");
+
+		let out = serve(cfg.clone(), hello(), b"POST / HTTP/1.1\r\nConnection: close\r\nContent-Length: 2\r\n\r\n{}").await;
+		assert!(out.ends_with("Hello World"));
+
+		// off by default, the extra bytes are dropped with the connection
+		cfg.reject_trailing_bytes = false;
+		let out = serve(cfg, hello(), raw).await;
+		assert_eq!(out.matches("Hello World").count(), 1);
+	}
+
+	#[tokio::test]
+	async fn test_trace() {
+		let mut cfg = Config::new();
+		cfg.server_header = None;
+		cfg.date_header = false;
+		cfg.allow_methods = "GET, HEAD".to_string();
+		let raw = b"TRACE /a?b HTTP/1.1\r\nHost: spk.local\r\nX-Hop: 1\r\n\r\n";
+		let out = serve(cfg.clone(), hello(), raw).await;
+		assert_eq!(out, "HTTP/1.1 405 Method Not Allowed\r\nAllow: GET, HEAD\r\nContent-Length: 0\r\n\r\n");
+
+		cfg.trace_echo = true;
+		let out = serve(cfg, hello(), raw).await;
+		let echoed = "TRACE /a?b HTTP/1.1\r\nHost: spk.local\r\nX-Hop: 1\r\n";
+		assert!(out.starts_with("HTTP/1.1 200 OK\r\n"));
+		assert!(out.contains("\r\nContent-Type: message/http\r\n"));
+		assert!(out.contains(format!("\r\nContent-Length: {}\r\n", echoed.len()).as_str()));
+		assert!(out.ends_with(format!("\r\n\r\n{}", echoed).as_str()));
+	}
+
+	#[tokio::test]
+	async fn test_event_stream() {
+		let handler: Arc<dyn Handler + Send + Sync> = Arc::new(FuncHandler::new(|_| {
+			Box::pin(async {
+				let (resp, mut stream) = Response::event_stream();
+				tokio::spawn(async move {
+					stream.send(None, None, "hello").await.unwrap();
+					stream.send(Some("tick"), None, "1").await.unwrap();
+				});
+				return Ok(resp);
+			})
+		}));
+		let mut cfg = Config::new();
+		cfg.server_header = None;
+		cfg.date_header = false;
+		let out = serve(cfg, handler, b"GET /events HTTP/1.1\r\n\r\nGET /events HTTP/1.1\r\n\r\n").await;
+
+		// chunked like any stream, the connection is kept for the next request
+		let chunk = |v: &str| { return format!("{:x}\r\n{}\r\n", v.len(), v); };
+		let expected = format!(
+			"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nTransfer-Encoding: chunked\r\n\r\n{}{}0\r\n\r\n",
+			chunk("data: hello\n\n"), chunk("event: tick\ndata: 1\n\n"),
+		);
+		assert_eq!(out, expected.repeat(2));
+	}
+
+	#[tokio::test]
+	async fn test_streaming_response() {
+		let handler: Arc<dyn Handler + Send + Sync> = Arc::new(FuncHandler::new(|_| {
+			Box::pin(async {
+				let (mut resp, mut writer) = Response::streaming();
+				resp.builder().headers().content_type(mime::TEXT);
+				tokio::spawn(async move {
+					for piece in ["Hello", ", ", "", "World"] {
+						writer.write(piece.as_bytes()).await.unwrap();
+						tokio::task::yield_now().await;
+					}
+					writer.finish();
+				});
+				return Ok(resp);
+			})
+		}));
+		let mut cfg = Config::new();
+		cfg.server_header = None;
+		cfg.date_header = false;
+		let out = serve(cfg, handler, b"GET / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\n").await;
+		assert_eq!(
+			out,
+			"HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nHello\r\n2\r\n, \r\n5\r\nWorld\r\n0\r\n\r\n".repeat(2),
+		);
+
+		// the sent response decodes, and the connection was kept for the next one
+		let mut stream = out.as_bytes();
+		for _ in 0..2 {
+			let mut resp = Response::new();
+			let head_cfg = Config::new();
+			assert!(resp.from(&mut stream, &head_cfg).await.is_none());
+			assert_eq!(resp.body().unwrap().as_ref(), b"Hello, World");
+			match resp.take_read_buffer() {
+				Some(rb) => {
+					stream = &out.as_bytes()[out.len() - rb.remains()..];
+				}
+				None => {}
+			}
+		}
+	}
+
+	#[tokio::test]
+	async fn test_streaming_http10() {
+		let handler: Arc<dyn Handler + Send + Sync> = Arc::new(FuncHandler::new(|_| {
+			Box::pin(async {
+				let (mut resp, mut writer) = Response::streaming();
+				resp.builder().trailer("X-Checksum", "1").headers().content_type(mime::TEXT);
+				tokio::spawn(async move {
+					for piece in ["Hello", ", ", "World"] {
+						writer.write(piece.as_bytes()).await.unwrap();
+						tokio::task::yield_now().await;
+					}
+					writer.finish();
+				});
+				return Ok(resp);
+			})
+		}));
+		let mut cfg = Config::new();
+		cfg.server_header = None;
+		cfg.date_header = false;
+		// the body ends with the connection, the second request is never answered
+		let out = serve(cfg, handler, b"GET / HTTP/1.0\r\nTE: trailers\r\n\r\nGET / HTTP/1.0\r\n\r\n").await;
+		assert_eq!(out, "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nConnection: close\r\n\r\nHello, World");
+	}
+
+	#[tokio::test]
+	async fn test_streaming_content_length() {
+		let handler: Arc<dyn Handler + Send + Sync> = Arc::new(FuncHandler::new(|req| {
+			Box::pin(async move {
+				let (mut resp, mut writer) = Response::streaming();
+				resp.builder().headers().content_length(10);
+				let pieces: &[&str] = if req.path() == "/short" { &["01234"] } else { &["01234", "56789"] };
+				for piece in pieces {
+					writer.write(piece.as_bytes()).await.unwrap();
+				}
+				return Ok(resp);
+			})
+		}));
+		let mut cfg = Config::new();
+		cfg.server_header = None;
+		cfg.date_header = false;
+		let out = serve(cfg.clone(), Arc::clone(&handler), b"GET / HTTP/1.1\r\n\r\n").await;
+		assert_eq!(out, "HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n0123456789");
+
+		// falls short of its length, the connection closes instead of serving the next request
+		let out = serve(cfg, handler, b"GET /short HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\n").await;
+		assert_eq!(out, "HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n01234");
+	}
+
+	#[tokio::test]
+	async fn test_metrics() {
+		let seen = Arc::new(std::sync::Mutex::new(vec![]));
+		let mut cfg = Config::new();
+		let sink = Arc::clone(&seen);
+		cfg.conn_observer = Some(Arc::new(move |m| {
+			sink.lock().unwrap().push(*m);
+		}));
+		let raw = b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nHelloGET / HTTP/1.1\r\nConnection: close\r\n\r\n";
+		let out = serve(cfg, hello(), raw).await;
+
+		let seen = seen.lock().unwrap();
+		assert_eq!(seen.len(), 1);
+		assert_eq!(seen[0].peer, "127.0.0.1:8080".parse().unwrap());
+		assert_eq!(seen[0].bytes_read, raw.len() as u64);
+		assert_eq!(seen[0].bytes_written, out.len() as u64);
+		assert!(out.matches("Hello World").count() == 2);
+	}
+
+	#[tokio::test]
+	async fn test_unknown_transfer_coding() {
+		let mut cfg = Config::new();
+		cfg.server_header = None;
+		cfg.date_header = false;
+		let out = serve(cfg, hello(), b"POST / HTTP/1.1\r\nTransfer-Encoding: gzip\r\n\r\nspk!GET / HTTP/1.1\r\n\r\n").await;
+		// the connection is not used past a body of unknown length
+		assert_eq!(out, "HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n");
+
+		// nor past one whose length is told twice
+		let mut cfg = Config::new();
+		cfg.server_header = None;
+		cfg.date_header = false;
+		let out = serve(cfg, hello(), b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nContent-Length: 4\r\n\r\n4\r\nspk!\r\n0\r\n\r\nGET / HTTP/1.1\r\n\r\n").await;
+		assert_eq!(out, "HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n");
+	}
+
+	#[tokio::test]
+	async fn test_h2c_upgrade() {
+		let raw = b"GET /h2 HTTP/1.1\r\nHost: spk.local\r\nConnection: Upgrade, HTTP2-Settings\r\nUpgrade: h2c\r\nHTTP2-Settings: AAMAAABkAARAAAAAAAIAAAAA\r\n\r\nPRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+		let mut cfg = Config::new();
+		cfg.server_header = None;
+		cfg.date_header = false;
+		// without support the upgrade is just a header
+		let out = serve(cfg.clone(), hello(), b"GET /h2 HTTP/1.1\r\nConnection: Upgrade, HTTP2-Settings\r\nUpgrade: h2c\r\nHTTP2-Settings: AAMAAABkAARAAAAAAAIAAAAA\r\n\r\n").await;
+		assert_eq!(out, "HTTP/1.1 200 OK\r\nContent-Length: 11\r\n\r\nHello World");
+
+		cfg.h2c_handler = Some(Arc::new(|_| { Box::pin(async {}) }));
+		let (client, server) = tokio::io::duplex(4096);
+		let (sr, sw) = tokio::io::split(server);
+		let mut conn = Conn::new(
+			"127.0.0.1:8080".parse().unwrap(), sr, sw,
+			Arc::new(AtomicBool::new(false)), None, Arc::new(cfg.clone()),
+		);
+		let (mut cr, mut cw) = tokio::io::split(client);
+		cw.write_all(raw).await.unwrap();
+		let connect = conn.as_server(hello()).await.unwrap();
+		let tunnel = conn.into_tunnel(connect);
+		assert_eq!(tunnel.authority, "spk.local");
+		assert_eq!(tunnel.request.as_ref().unwrap().path(), "/h2");
+		// the client preface is left for the HTTP/2 layer
+		assert_eq!(tunnel.buffered.as_ref(), b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n");
+
+		let mut buf = [0u8; 1024];
+		let size = cr.read(&mut buf).await.unwrap();
+		assert_eq!(&buf[..size], b"HTTP/1.1 101 Switching Protocols\r\nConnection: Upgrade\r\nUpgrade: h2c\r\n\r\n");
+
+		// no HTTP2-Settings, no upgrade
+		let out = serve(cfg, hello(), b"GET / HTTP/1.1\r\nConnection: Upgrade\r\nUpgrade: h2c\r\n\r\n").await;
+		assert!(out.starts_with("HTTP/1.1 200 OK\r\n"));
+	}
+
+	#[tokio::test]
+	async fn test_handler_upgrade() {
+		let handler: Arc<dyn Handler + Send + Sync> = Arc::new(FuncHandler::new(|_| {
+			Box::pin(async {
+				let mut resp = Response::new();
+				// echoes what it got, the bytes that came with the request first
+				resp.builder().upgrade("echo", Arc::new(|mut upgraded: Upgraded| {
+					Box::pin(async move {
+						upgraded.w.write_all(upgraded.buffered.as_ref()).await.unwrap();
+						let mut buf = [0u8; 64];
+						let size = upgraded.r.read(&mut buf).await.unwrap();
+						upgraded.w.write_all(&buf[..size]).await.unwrap();
+						upgraded.w.flush().await.unwrap();
+					})
+				}));
+				return Ok(resp);
+			})
+		}));
+		let mut cfg = Config::new();
+		cfg.server_header = None;
+		cfg.date_header = false;
+		let (client, server) = tokio::io::duplex(4096);
+		let (sr, sw) = tokio::io::split(server);
+		let mut conn = Conn::new(
+			"127.0.0.1:8080".parse().unwrap(), sr, sw,
+			Arc::new(AtomicBool::new(false)), None, Arc::new(cfg),
+		);
+		let (mut cr, mut cw) = tokio::io::split(client);
+		cw.write_all(b"GET /echo HTTP/1.1\r\nConnection: Upgrade\r\nUpgrade: echo\r\n\r\nearly;").await.unwrap();
+		let mut connect = conn.as_server(handler).await.unwrap();
+		let upgrade = connect.take_upgrade().unwrap();
+		let tunnel = conn.into_tunnel(connect);
+		assert_eq!(tunnel.buffered.as_ref(), b"early;");
+		let task = tokio::spawn(upgrade(Tunnel {
+			authority: tunnel.authority,
+			buffered: tunnel.buffered,
+			request: tunnel.request,
+			r: Box::new(tunnel.r) as BoxedReader,
+			w: Box::new(tunnel.w) as BoxedWriter,
+		}));
+		cw.write_all(b"late").await.unwrap();
+		task.await.unwrap();
 
-			self.w.write(b"HTTP/1.0 200 OK\r\nContent-Length: 11\r\n\r\nHello World").await.err();
+		let mut out = vec![];
+		let mut buf = [0u8; 1024];
+		while !out.ends_with(b"late") {
+			let size = cr.read(&mut buf).await.unwrap();
+			assert!(size > 0);
+			out.extend_from_slice(&buf[..size]);
 		}
+		assert_eq!(
+			String::from_utf8(out).unwrap(),
+			"HTTP/1.1 101 Switching Protocols\r\nConnection: Upgrade\r\nUpgrade: echo\r\n\r\nearly;late",
+		);
 	}
 }
\ No newline at end of file
@@ -0,0 +1,257 @@
+use bytes::BytesMut;
+use crate::h2tp::message::{ParseError, ParseErrorKind};
+use crate::h2tp::utils::quoted_string::split_list;
+
+const UNSUPPORTED_ENCODING: &str = "unsupported content encoding";
+const DECOMPRESSED_TOO_LARGE: &str = "decompressed body too large";
+#[cfg(any(feature = "gzip", feature = "brotli"))]
+const BAD_COMPRESSED_BODY: &str = "bad compressed body";
+
+// codings responses can be sent with, preferred first between equal q-values
+const SUPPORTED: &[&str] = &[
+	#[cfg(feature = "brotli")]
+	"br",
+	#[cfg(feature = "gzip")]
+	"gzip",
+];
+
+// codings `decode_body` undoes
+pub const DECODABLE: &[&str] = &[
+	#[cfg(feature = "gzip")]
+	"gzip",
+	#[cfg(feature = "gzip")]
+	"x-gzip",
+	#[cfg(feature = "gzip")]
+	"deflate",
+	#[cfg(feature = "brotli")]
+	"br",
+];
+
+// q-value of `coding` in an `Accept-Encoding` value, in thousandths, `None` when not listed
+fn qvalue(accept: &str, coding: &str) -> Option<u32> {
+	for item in split_list(accept, ',') {
+		let mut params = split_list(item, ';').into_iter();
+		if !params.next().unwrap_or("").eq_ignore_ascii_case(coding) {
+			continue;
+		}
+		for param in params {
+			match param.trim().split_once('=') {
+				Some((k, v)) if k.trim().eq_ignore_ascii_case("q") => {
+					return Some(match v.trim().parse::<f32>() {
+						Ok(q) => {
+							(q.clamp(0.0, 1.0) * 1000.0).round() as u32
+						}
+						Err(_) => {
+							0
+						}
+					});
+				}
+				_ => {}
+			}
+		}
+		return Some(1000);
+	}
+	return None;
+}
+
+// q-value `coding` gets from an `Accept-Encoding` value, from the `*` entry when not listed itself
+fn accepted(accept: &str, coding: &str) -> Option<u32> {
+	return qvalue(accept, coding).or_else(|| qvalue(accept, "*"));
+}
+
+// True when `accept` refuses an unencoded response, with `identity;q=0` or a `*;q=0` that
+// identity is not listed besides.
+pub fn identity_refused(accept: &str) -> bool {
+	return accepted(accept, "identity") == Some(0);
+}
+
+// Picks the coding to send a response with from the `Accept-Encoding` of its request, `None`
+// means identity. Codings with q=0 are refused, identity wins only when listed with a higher q.
+// Codings not listed take the q-value of `*` when there is one.
+pub fn negotiate(accept: &str) -> Option<&'static str> {
+	let mut best: Option<(&'static str, u32)> = None;
+	for coding in SUPPORTED {
+		match accepted(accept, coding) {
+			Some(q) if q > 0 && best.is_none_or(|(_, bq)| q > bq) => {
+				best = Some((coding, q));
+			}
+			_ => {}
+		}
+	}
+	return match best {
+		Some((coding, q)) => {
+			if accepted(accept, "identity").is_some_and(|iq| iq > q) { None } else { Some(coding) }
+		}
+		None => {
+			None
+		}
+	};
+}
+
+// Compresses `src` with a coding `negotiate` returned.
+#[cfg(any(feature = "gzip", feature = "brotli"))]
+pub fn encode(coding: &str, src: &[u8]) -> Vec<u8> {
+	use std::io::Write;
+
+	match coding {
+		#[cfg(feature = "gzip")]
+		"gzip" => {
+			let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+			encoder.write_all(src).unwrap();
+			return encoder.finish().unwrap();
+		}
+		#[cfg(feature = "brotli")]
+		"br" => {
+			let mut encoder = brotli::CompressorWriter::new(Vec::new(), 4096, 5, 22);
+			encoder.write_all(src).unwrap();
+			return encoder.into_inner();
+		}
+		_ => {
+			unreachable!("unsupported coding {}", coding);
+		}
+	}
+}
+
+// Decodes `body` according to a `Content-Encoding` value, codings are undone in reverse order.
+// The decoded output is bounded by `limit` bytes to defend against zip bombs.
+pub fn decode_body(encoding: &str, body: &BytesMut, limit: usize) -> Result<Option<BytesMut>, ParseError> {
+	let mut current: Option<BytesMut> = None;
+	for coding in encoding.rsplit(',') {
+		let coding = coding.trim().to_ascii_lowercase();
+		if coding.is_empty() || coding == "identity" {
+			continue;
+		}
+
+		let src: &[u8] = match current.as_ref() {
+			Some(v) => {
+				v.as_ref()
+			}
+			None => {
+				body.as_ref()
+			}
+		};
+		match decode(coding.as_str(), src, limit) {
+			Ok(v) => {
+				current = Some(v);
+			}
+			Err(e) => {
+				return Err(e);
+			}
+		}
+	}
+	return Ok(current);
+}
+
+#[cfg(any(feature = "gzip", feature = "brotli"))]
+fn decode(coding: &str, src: &[u8], limit: usize) -> Result<BytesMut, ParseError> {
+	use std::io::Read;
+
+	let mut dist = Vec::new();
+	let result = match coding {
+		#[cfg(feature = "gzip")]
+		"gzip" | "x-gzip" => {
+			flate2::read::GzDecoder::new(src).take(limit as u64 + 1).read_to_end(&mut dist)
+		}
+		#[cfg(feature = "gzip")]
+		"deflate" => {
+			flate2::read::ZlibDecoder::new(src).take(limit as u64 + 1).read_to_end(&mut dist)
+		}
+		#[cfg(feature = "brotli")]
+		"br" => {
+			brotli::Decompressor::new(src, 4096).take(limit as u64 + 1).read_to_end(&mut dist)
+		}
+		_ => {
+			return Err(ParseError::uekind(UNSUPPORTED_ENCODING, ParseErrorKind::UnsupportedMediaType));
+		}
+	};
+	match result {
+		Ok(_) => {}
+		Err(_) => {
+			return Err(ParseError::ue(BAD_COMPRESSED_BODY));
+		}
+	}
+	if dist.len() > limit {
+		return Err(ParseError::uekind(DECOMPRESSED_TOO_LARGE, ParseErrorKind::TooLarge));
+	}
+	return Ok(BytesMut::from(dist.as_slice()));
+}
+
+// nothing to negotiate without codings
+#[cfg(not(any(feature = "gzip", feature = "brotli")))]
+pub fn encode(coding: &str, _src: &[u8]) -> Vec<u8> {
+	unreachable!("unsupported coding {}", coding);
+}
+
+#[cfg(not(any(feature = "gzip", feature = "brotli")))]
+fn decode(_coding: &str, _src: &[u8], _limit: usize) -> Result<BytesMut, ParseError> {
+	return Err(ParseError::uekind(UNSUPPORTED_ENCODING, ParseErrorKind::UnsupportedMediaType));
+}
+
+#[cfg(test)]
+mod tests {
+	use bytes::BytesMut;
+	use crate::h2tp::content_encoding::{decode_body, identity_refused};
+	use crate::h2tp::status_code::StatusCode;
+
+	#[cfg(feature = "gzip")]
+	fn gzip(v: &[u8]) -> Vec<u8> {
+		use std::io::Write;
+		let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+		encoder.write_all(v).unwrap();
+		return encoder.finish().unwrap();
+	}
+
+	#[cfg(feature = "gzip")]
+	#[test]
+	fn test_gzip() {
+		let body = BytesMut::from(gzip(b"Hello Gzip").as_slice());
+		let decoded = decode_body("gzip", &body, 1024).unwrap().unwrap();
+		assert_eq!(decoded.as_ref(), b"Hello Gzip");
+
+		let body = BytesMut::from(gzip(&[b'a'; 4096]).as_slice());
+		let err = decode_body("gzip", &body, 1024).err().unwrap();
+		assert!(err.statuscode() == StatusCode::PayloadTooLarge);
+	}
+
+	#[cfg(all(feature = "gzip", feature = "brotli"))]
+	#[test]
+	fn test_negotiate() {
+		use crate::h2tp::content_encoding::{encode, negotiate};
+
+		assert_eq!(negotiate("br, gzip"), Some("br"));
+		assert_eq!(negotiate("gzip, br"), Some("br"));
+		assert_eq!(negotiate("gzip;q=1.0, br;q=0.5"), Some("gzip"));
+		assert_eq!(negotiate("br;q=0, gzip;q=0.2"), Some("gzip"));
+		assert_eq!(negotiate("gzip;q=0.5, identity"), None);
+		assert_eq!(negotiate("compress, deflate"), None);
+		assert_eq!(negotiate(""), None);
+		assert_eq!(negotiate("*"), Some("br"));
+		assert_eq!(negotiate("br;q=0, *"), Some("gzip"));
+		assert_eq!(negotiate("*;q=0.5, identity"), None);
+		assert_eq!(negotiate("identity;q=0, *;q=0"), None);
+
+		let data = "brotli ".repeat(100);
+		let encoded = encode("br", data.as_bytes());
+		assert!(encoded.len() < data.len());
+		let decoded = decode_body("br", &BytesMut::from(encoded.as_slice()), 4096).unwrap().unwrap();
+		assert_eq!(decoded.as_ref(), data.as_bytes());
+	}
+
+	#[test]
+	fn test_identity_refused() {
+		assert!(!identity_refused(""));
+		assert!(!identity_refused("gzip;q=0"));
+		assert!(identity_refused("identity;q=0"));
+		assert!(identity_refused("gzip, *;q=0"));
+		assert!(!identity_refused("identity, *;q=0"));
+		assert!(!identity_refused("*;q=0.1"));
+	}
+
+	#[test]
+	fn test_unknown_encoding() {
+		let body = BytesMut::from(&b"????"[..]);
+		let err = decode_body("compress", &body, 1024).err().unwrap();
+		assert!(err.statuscode() == StatusCode::UnsupportedMediaType);
+		assert!(decode_body("identity", &body, 1024).unwrap().is_none());
+	}
+}
\ No newline at end of file
@@ -4,7 +4,7 @@ use crate::h2tp::error::Error;
 use crate::h2tp::request::Request;
 use crate::h2tp::response::Response;
 
-type BoxedFuture = Pin<Box<dyn Future<Output=Result<Response, Error>> + Send>>;
+pub type BoxedFuture = Pin<Box<dyn Future<Output=Result<Response, Error>> + Send>>;
 type FuncType = fn(req: Request) -> BoxedFuture;
 
 pub trait Handler {
@@ -29,3 +29,52 @@ impl Handler for FuncHandler {
 	}
 }
 
+
+
+#[cfg(test)]
+mod tests {
+	use std::sync::Arc;
+	use crate::h2tp::cfg::Config;
+	use crate::h2tp::FuncHandler;
+	use crate::h2tp::handler::{BoxedFuture, Handler};
+	use crate::h2tp::request::Request;
+	use crate::h2tp::response::Response;
+
+	struct RequestId(u64);
+
+	struct RequestIdMiddleware {
+		next: Arc<dyn Handler + Send + Sync>,
+	}
+
+	impl Handler for RequestIdMiddleware {
+		fn handle(&self, mut req: Request) -> BoxedFuture {
+			req.extensions_mut().insert(RequestId(42));
+			return self.next.handle(req);
+		}
+	}
+
+	#[tokio::test]
+	async fn test_extensions() {
+		let handler = RequestIdMiddleware {
+			next: Arc::new(FuncHandler::new(|req| {
+				Box::pin(async move {
+					let id = req.extensions().get::<RequestId>().unwrap().0;
+					let mut resp = Response::new();
+					resp.builder().body(id.to_string().as_bytes());
+					return Ok(resp);
+				})
+			})),
+		};
+
+		let mut stream: &[u8] = b"GET / HTTP/1.1\r\n\r\n";
+		let mut req = Request::new();
+		assert!(req.from(&mut stream, &Config::new()).await.is_none());
+		let resp = handler.handle(req).await.unwrap();
+		assert_eq!(resp.body().unwrap().as_ref(), b"42");
+
+		let mut req = Request::new();
+		req.extensions_mut().insert(RequestId(1));
+		req.clear();
+		assert!(req.extensions().get::<RequestId>().is_none());
+	}
+}
\ No newline at end of file
@@ -1,6 +1,9 @@
+use std::collections::HashMap;
 use std::fmt;
 use std::fmt::{Formatter};
+use std::sync::OnceLock;
 use crate::h2tp::utils::multi_map::MultiMap;
+use crate::h2tp::utils::quoted_string::split_list;
 
 macro_rules! pub_str_const {
 		($name:ident, $val:expr) => {
@@ -18,7 +21,7 @@ pub mod hns {
 	pub_str_const!(LAST_MODIFIED, "last-modified");
 	pub_str_const!(IF_MODIFIED_SINCE, "if-modified-since");
 	pub_str_const!(IF_UNMODIFIED_SINCE, "if-unmodified-since");
-	pub_str_const!(E_TAG, "e-tag");
+	pub_str_const!(E_TAG, "etag");
 	pub_str_const!(IF_MATCH, "if-match");
 	pub_str_const!(IF_NONE_MATCH, "if-none-match");
 	pub_str_const!(ACCEPT_ENCODING, "accept-encoding");
@@ -32,7 +35,7 @@ pub mod hns {
 	pub_str_const!(USER_AGENT, "user-agent");
 	pub_str_const!(ALLOW, "allow");
 	pub_str_const!(SERVER, "server");
-	pub_str_const!(ACCEPT_RANGE, "accept-range");
+	pub_str_const!(ACCEPT_RANGES, "accept-ranges");
 	pub_str_const!(RANGE, "range");
 	pub_str_const!(IF_RANGE, "if-range");
 	pub_str_const!(CONTENT_RANGE, "content-range");
@@ -44,6 +47,19 @@ pub mod hns {
 	pub_str_const!(DATE, "date");
 	pub_str_const!(RETRY_AFTER, "retry-after");
 	pub_str_const!(UPGRADE, "upgrade");
+	pub_str_const!(ACCEPT, "accept");
+	pub_str_const!(AUTHORIZATION, "authorization");
+	pub_str_const!(WWW_AUTHENTICATE, "www-authenticate");
+	pub_str_const!(X_CONTENT_TYPE_OPTIONS, "x-content-type-options");
+	pub_str_const!(TE, "te");
+	pub_str_const!(TRAILER, "trailer");
+	pub_str_const!(FORWARDED, "forwarded");
+	pub_str_const!(X_FORWARDED_FOR, "x-forwarded-for");
+	pub_str_const!(CACHE_CONTROL, "cache-control");
+	pub_str_const!(VARY, "vary");
+	pub_str_const!(X_REQUEST_ID, "x-request-id");
+	pub_str_const!(HTTP2_SETTINGS, "http2-settings");
+	pub_str_const!(EXPECT, "expect");
 }
 
 pub mod mime {
@@ -63,12 +79,70 @@ pub mod mime {
 	pub_str_const!(JSON, "application/json");
 	pub_str_const!(WWW_FORM_URLENCODED, "application/x-www-form-urlencoded");
 	pub_str_const!(MULTIPART_FORM, "multipart/form-data");
+	pub_str_const!(MULTIPART_BYTERANGES, "multipart/byteranges");
+	pub_str_const!(EVENT_STREAM, "text/event-stream");
+	pub_str_const!(MESSAGE_HTTP, "message/http");
 
 	pub_str_const!(WOFF, "font/woff");
 }
 
+// Field names are stored lowercased for lookups, the casing they were supplied with is kept
+// separately and used when the headers are written out.
+// Iteration order is stable: names come in the order they were first added, repeated values of a
+// name follow each other at that position.
 pub struct Headers {
-	m: MultiMap,
+	m: MultiMap<HeaderValue>,
+	names: Option<HashMap<String, String>>,
+}
+
+// Field values are opaque octets, they are kept exactly as received. `as_str` is a lossy UTF-8 view,
+// the lossy copy is only made, once, for values that are not valid UTF-8.
+pub struct HeaderValue {
+	bytes: Vec<u8>,
+	lossy: OnceLock<String>,
+}
+
+impl HeaderValue {
+	pub fn new(v: &str) -> Self {
+		return Self::from_bytes(v.as_bytes());
+	}
+
+	pub fn from_bytes(v: &[u8]) -> Self {
+		return Self {
+			bytes: v.to_vec(),
+			lossy: OnceLock::new(),
+		};
+	}
+
+	pub fn as_str(&self) -> &str {
+		return match std::str::from_utf8(&self.bytes) {
+			Ok(v) => {
+				v
+			}
+			Err(_) => {
+				self.lossy.get_or_init(|| String::from_utf8_lossy(&self.bytes).into_owned()).as_str()
+			}
+		};
+	}
+
+	pub fn as_bytes(&self) -> &[u8] {
+		return self.bytes.as_slice();
+	}
+}
+
+// "content-type" -> "Content-Type"
+pub fn canonical_name(k: &str) -> String {
+	let mut name = String::with_capacity(k.len());
+	let mut upper = true;
+	for c in k.chars() {
+		if upper {
+			name.push(c.to_ascii_uppercase());
+		} else {
+			name.push(c.to_ascii_lowercase());
+		}
+		upper = c == '-';
+	}
+	return name;
 }
 
 pub struct Builder<'h> {
@@ -76,18 +150,72 @@ pub struct Builder<'h> {
 }
 
 impl<'h> Builder<'h> {
+	fn key(&mut self, k: &str) -> String {
+		let lk = k.to_ascii_lowercase();
+		if lk != k {
+			if self.headers.names.is_none() {
+				self.headers.names = Some(HashMap::new());
+			}
+			self.headers.names.as_mut().unwrap().insert(lk.clone(), k.to_string());
+		}
+		return lk;
+	}
+
 	pub fn append(&mut self, k: &str, v: &str) -> &mut Self {
-		self.headers.m.append(k, v);
+		let k = self.key(k);
+		self.headers.m.append(&k, HeaderValue::new(v));
+		return self;
+	}
+
+	pub fn append_bytes(&mut self, k: &str, v: &[u8]) -> &mut Self {
+		let k = self.key(k);
+		self.headers.m.append(&k, HeaderValue::from_bytes(v));
 		return self;
 	}
 
+	// `append_bytes`, unless `k` holds `limit` values already
+	pub fn append_limited(&mut self, k: &str, v: &[u8], limit: usize) -> bool {
+		let count = match self.headers.m.get(&k.to_ascii_lowercase()) {
+			Some(vals) => {
+				vals.len()
+			}
+			None => {
+				0
+			}
+		};
+		if count >= limit {
+			return false;
+		}
+		self.append_bytes(k, v);
+		return true;
+	}
+
 	pub fn reset(&mut self, k: &str, v: &str) -> &mut Self {
-		self.headers.m.reset(k, v);
+		let k = self.key(k);
+		self.headers.m.reset(&k, HeaderValue::new(v));
+		return self;
+	}
+
+	pub fn remove(&mut self, k: &str) -> &mut Self {
+		let k = k.to_ascii_lowercase();
+		self.headers.m.remove(&k);
+		match self.headers.names.as_mut() {
+			Some(names) => {
+				names.remove(&k);
+			}
+			None => {}
+		}
 		return self;
 	}
 
 	pub fn clear(&mut self) -> &mut Self {
 		self.headers.m.clear();
+		match self.headers.names.as_mut() {
+			Some(names) => {
+				names.clear();
+			}
+			None => {}
+		}
 		return self;
 	}
 
@@ -106,8 +234,8 @@ impl<'h> Builder<'h> {
 
 macro_rules! getone {
     ($name:ident -> $key:expr) => {
-		pub fn $name(&self) -> Option<&String> {
-			return self.m.getone($key);
+		pub fn $name(&self) -> Option<&str> {
+			return self.m.getone($key).map(HeaderValue::as_str);
 		}
 	};
 }
@@ -117,15 +245,24 @@ impl Headers {
 	pub fn new() -> Self {
 		return Self {
 			m: MultiMap::new(),
+			names: None,
 		};
 	}
 
 	pub fn builder(&mut self) -> Builder { return Builder { headers: self }; }
 
+	// appends every pair, as one `append` each
+	pub fn extend<I: IntoIterator<Item=(String, String)>>(&mut self, iter: I) {
+		let mut builder = self.builder();
+		for (k, v) in iter {
+			builder.append(k.as_str(), v.as_str());
+		}
+	}
+
 	pub fn content_length(&self) -> Option<usize> {
 		match self.m.getone(hns::CONTENT_LENGTH) {
 			Some(v) => {
-				return match v.parse::<i32>() {
+				return match v.as_str().parse::<i32>() {
 					Ok(num) => {
 						if num < 0 {
 							return None;
@@ -143,14 +280,103 @@ impl Headers {
 		}
 	}
 
+	pub fn get(&self, k: &str) -> Option<&str> {
+		return self.get_value(k).map(HeaderValue::as_str);
+	}
+
+	pub fn get_bytes(&self, k: &str) -> Option<&[u8]> {
+		return self.get_value(k).map(HeaderValue::as_bytes);
+	}
+
+	pub fn get_value(&self, k: &str) -> Option<&HeaderValue> {
+		return self.m.getone(k.to_ascii_lowercase().as_str());
+	}
+
+	pub fn get_all(&self, k: &str) -> Option<&Vec<HeaderValue>> {
+		return self.m.get(k.to_ascii_lowercase().as_str());
+	}
+
+	// Joins all values of a field with ", " (RFC 7230 3.2.2).
+	// `Set-Cookie` can not be combined this way, use `get_all` instead.
+	pub fn get_combined(&self, name: &str) -> Option<String> {
+		let name = name.to_ascii_lowercase();
+		if name == hns::SET_COOKIE {
+			return None;
+		}
+		return match self.m.get(name.as_str()) {
+			Some(vals) => {
+				Some(vals.iter().map(HeaderValue::as_str).collect::<Vec<&str>>().join(", "))
+			}
+			None => {
+				None
+			}
+		};
+	}
+
 	getone!(content_type -> hns::CONTENT_TYPE);
 
 	getone!(transfer_encoding -> hns::TRANSFER_ENCODING);
 
+	// iterates with lowercased names
+	pub fn each<F: FnMut(&str, &HeaderValue)>(&self, func: F) {
+		self.m.each(func);
+	}
+
+	// iterates with the supplied casing, names set in lowercase are canonicalized
+	pub fn each_cased<F: FnMut(&str, &HeaderValue)>(&self, mut func: F) {
+		self.m.each(|k, v| {
+			match self.names.as_ref().and_then(|names| names.get(k)) {
+				Some(name) => {
+					func(name, v);
+				}
+				None => {
+					func(canonical_name(k).as_str(), v);
+				}
+			}
+		});
+	}
+
+	// the transfer codings over every Transfer-Encoding line, lowercased, in the order applied
+	pub fn transfer_codings(&self) -> Vec<String> {
+		return match self.get_combined(hns::TRANSFER_ENCODING) {
+			Some(v) => {
+				split_list(&v, ',').into_iter().map(|c| c.to_ascii_lowercase()).collect()
+			}
+			None => {
+				vec![]
+			}
+		};
+	}
+
+	// chunked is what frames the message only when it is the final coding (RFC 7230 3.3.3)
 	pub fn is_chunked(&self) -> bool {
-		return match self.transfer_encoding() {
+		return self.transfer_codings().last().is_some_and(|c| c == "chunked");
+	}
+
+	// A Transfer-Encoding this server cannot frame a message by: one that does not end in a single
+	// `chunked`, or has a coding besides it that is not in `recognized`.
+	pub fn has_unknown_transfer_coding(&self, recognized: &[String]) -> bool {
+		if self.m.get(hns::TRANSFER_ENCODING).is_none() {
+			return false;
+		}
+		return match self.transfer_codings().split_last() {
+			Some((last, rest)) => {
+				last != "chunked" || rest.iter().any(|c| c == "chunked" || !recognized.contains(c))
+			}
+			None => {
+				true
+			}
+		};
+	}
+
+	// `TE: trailers`, the client accepts trailer fields after a chunked body (RFC 7230 4.3)
+	pub fn wants_trailers(&self) -> bool {
+		return match self.get_combined(hns::TE) {
 			Some(v) => {
-				v.contains("chunked")
+				split_list(&v, ',').into_iter().any(|token| {
+					let name = token.split(';').next().unwrap_or("");
+					return name.trim().eq_ignore_ascii_case("trailers");
+				})
 			}
 			None => {
 				false
@@ -163,8 +389,105 @@ impl fmt::Debug for Headers {
 	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
 		write!(f, "Headers<\r\n")?;
 		self.m.each(|k, v| {
-			println!("\t{}: {}", k, v);
+			println!("\t{}: {}", k, v.as_str());
 		});
 		write!(f, ">")
 	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::h2tp::headers::{Headers, hns};
+	use crate::h2tp::response::Response;
+
+	#[test]
+	fn test_get_combined() {
+		let mut headers = Headers::new();
+		headers.builder()
+			.append(hns::ACCEPT, "text/html")
+			.append(hns::ACCEPT, "application/json;q=0.9")
+			.append(hns::SET_COOKIE, "a=1")
+			.append(hns::SET_COOKIE, "b=2");
+
+		assert_eq!(headers.get_combined("Accept").unwrap(), "text/html, application/json;q=0.9");
+		assert!(headers.get_combined(hns::SET_COOKIE).is_none());
+		assert_eq!(headers.get_all(hns::SET_COOKIE).unwrap().len(), 2);
+		assert!(headers.get_combined(hns::HOST).is_none());
+	}
+
+	#[test]
+	fn test_name_casing() {
+		let mut headers = Headers::new();
+		headers.builder()
+			.append("Content-Type", "text/html")
+			.append("WWW-Authenticate", "Basic")
+			.append(hns::CONTENT_LENGTH, "0");
+
+		assert_eq!(headers.content_type().unwrap(), "text/html");
+		assert_eq!(headers.get("www-authenticate").unwrap(), "Basic");
+
+		let mut names = vec![];
+		headers.each_cased(|k, _| { names.push(k.to_string()); });
+		assert_eq!(names, vec!["Content-Type", "WWW-Authenticate", "Content-Length"]);
+
+		let mut names = vec![];
+		headers.each(|k, _| { names.push(k.to_string()); });
+		assert_eq!(names, vec!["content-type", "www-authenticate", "content-length"]);
+	}
+
+	#[test]
+	fn test_bytes_value() {
+		let raw = "caf\u{e9} \u{1f604}".as_bytes();
+		let mut headers = Headers::new();
+		headers.builder()
+			.append_bytes("X-Name", raw)
+			.append_bytes("X-Latin1", b"caf\xe9");
+
+		assert_eq!(headers.get_bytes("x-name").unwrap(), raw);
+		assert_eq!(headers.get("x-name").unwrap(), "caf\u{e9} \u{1f604}");
+		assert_eq!(headers.get_bytes("x-latin1").unwrap(), b"caf\xe9");
+		assert_eq!(headers.get("x-latin1").unwrap(), "caf\u{fffd}");
+		// only the value that is not UTF-8 got a lossy copy
+		assert!(headers.get_value("x-name").unwrap().lossy.get().is_none());
+		assert!(headers.get_value("x-latin1").unwrap().lossy.get().is_some());
+	}
+
+	#[test]
+	fn test_append_limited() {
+		let mut headers = Headers::new();
+		let mut builder = headers.builder();
+		for _ in 0..2 {
+			assert!(builder.append_limited("Cookie", b"a=1", 2));
+		}
+		assert!(!builder.append_limited("cookie", b"a=1", 2));
+		assert!(builder.append_limited("X-Other", b"1", 2));
+		assert_eq!(headers.get_all("cookie").unwrap().len(), 2);
+	}
+
+	#[test]
+	fn test_extend() {
+		let cors = vec![
+			("Access-Control-Allow-Origin".to_string(), "*".to_string()),
+			("Access-Control-Allow-Methods".to_string(), "GET, POST".to_string()),
+			("Vary".to_string(), "Origin".to_string()),
+		];
+		let mut headers = Headers::new();
+		headers.builder().append(hns::VARY, "Accept");
+		headers.extend(cors.clone());
+
+		let mut seen = vec![];
+		headers.each_cased(|k, v| {
+			seen.push(format!("{}: {}", k, v.as_str()));
+		});
+		assert_eq!(seen, vec![
+			"Vary: Accept", "Vary: Origin",
+			"Access-Control-Allow-Origin: *",
+			"Access-Control-Allow-Methods: GET, POST",
+		]);
+
+		let mut resp = Response::new();
+		resp.headers_from(cors);
+		assert_eq!(resp.headers().unwrap().get("access-control-allow-origin").unwrap(), "*");
+		assert_eq!(resp.headers().unwrap().get("vary").unwrap(), "Origin");
+	}
 }
\ No newline at end of file
@@ -2,18 +2,71 @@ use std::fmt;
 use std::fmt::{Formatter};
 use std::io::ErrorKind;
 use bytes::BytesMut;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::io::{AsyncReadExt};
-use crate::h2tp::cfg::MESSAGE_BUFFER_SIZE;
-use crate::h2tp::headers::Headers;
+use crate::h2tp::cfg::{Config, Limits, MESSAGE_BUFFER_SIZE};
+use crate::h2tp::clock::Clock;
+use crate::h2tp::headers::{Headers, hns};
+use crate::h2tp::status_code::StatusCode;
+use crate::h2tp::utils::buffer_pool::BufferPool;
+use crate::h2tp::utils::temp_file::TempFile;
 use crate::h2tp::{headers, types};
 
 pub struct Message {
 	pub startline: (String, String, String),
 	pub headers: Option<Headers>,
 	pub body: Option<BytesMut>,
+	pub bodylen: Option<usize>,
+	pub rawheaders: Option<Vec<u8>>,
+	bodylimit: usize,
 	buf: Option<BytesMut>,
 	bufsize: usize,
 	bufremains: usize,
+	pool: Option<Arc<BufferPool>>,
+	chunked_timeout: Option<(Arc<dyn Clock>, Duration)>,
+	// directory and size past which a body goes to a file, see `set_body_spill`
+	spill: Option<(PathBuf, usize)>,
+	body_file: Option<TempFile>,
+	// codings accepted under a final chunked, see `set_transfer_codings`
+	transfer_codings: Vec<String>,
+	// bytes and lines of a trailer section, see `set_trailer_limits`
+	trailer_limits: (usize, usize),
+}
+
+// The read buffer of a message, handed over to the next message of the same connection.
+pub struct ReadBuffer {
+	buf: BytesMut,
+	size: usize,
+	remains: usize,
+}
+
+impl ReadBuffer {
+	// count of read but not consumed bytes
+	pub fn remains(&self) -> usize { self.remains }
+
+	pub fn unread(&self) -> &[u8] {
+		return &self.buf[self.size - self.remains..self.size];
+	}
+}
+
+// Which start line a message begins with: `GET / HTTP/1.1` or `HTTP/1.1 404 Not Found`.
+// Both are three fields, only the reason phrase of a status line may contain spaces.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MessageKind {
+	Request,
+	Response,
+}
+
+// where `read_chunked_body` is within a chunk
+#[derive(Clone, Copy)]
+enum ChunkState {
+	Size,
+	SizeLF(usize),
+	Data(usize),
+	DataCR,
+	DataLF,
 }
 
 #[derive(PartialEq)]
@@ -25,23 +78,63 @@ enum ParseStatus {
 	HeadersOK,
 }
 
+// What went wrong while reading a message, protocol errors are answered with `statuscode`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ParseErrorKind {
+	// the stream failed or was closed, there is nobody to answer
+	Io,
+	BadRequest,
+	TooLarge,
+	UriTooLong,
+	HeadersTooLarge,
+	UnsupportedMediaType,
+	Timeout,
+	UnsupportedVersion,
+	// the stream closed before the body the message announced was complete
+	TruncatedBody,
+	// turned down by `Config::start_line_check`
+	Rejected(StatusCode),
+}
+
+impl ParseErrorKind {
+	pub fn statuscode(&self) -> StatusCode {
+		return match self {
+			ParseErrorKind::Io | ParseErrorKind::BadRequest | ParseErrorKind::TruncatedBody => StatusCode::BadRequest,
+			ParseErrorKind::TooLarge => StatusCode::PayloadTooLarge,
+			ParseErrorKind::UriTooLong => StatusCode::URITooLong,
+			ParseErrorKind::HeadersTooLarge => StatusCode::RequestHeaderFieldsTooLarge,
+			ParseErrorKind::UnsupportedMediaType => StatusCode::UnsupportedMediaType,
+			ParseErrorKind::Timeout => StatusCode::RequestTimeout,
+			ParseErrorKind::UnsupportedVersion => StatusCode::HTTPVersionNotSupported,
+			ParseErrorKind::Rejected(code) => *code,
+		};
+	}
+}
+
 pub struct ParseError {
 	ioe: Option<std::io::Error>,
 	ue: Option<&'static str>,
+	kind: ParseErrorKind,
 }
 
 impl ParseError {
-	fn ioe(v: std::io::Error) -> Self {
+	pub fn ioe(v: std::io::Error) -> Self {
 		return Self {
 			ioe: Some(v),
 			ue: None,
+			kind: ParseErrorKind::Io,
 		};
 	}
 
-	fn ue(v: &'static str) -> Self {
+	pub fn ue(v: &'static str) -> Self {
+		return Self::uekind(v, ParseErrorKind::BadRequest);
+	}
+
+	pub fn uekind(v: &'static str, kind: ParseErrorKind) -> Self {
 		return Self {
 			ioe: None,
 			ue: Some(v),
+			kind,
 		};
 	}
 
@@ -49,13 +142,23 @@ impl ParseError {
 		return Self {
 			ioe: None,
 			ue: None,
+			kind: ParseErrorKind::Io,
 		};
 	}
 
+	pub fn kind(&self) -> ParseErrorKind { self.kind }
+
+	// the status code to answer a protocol error with
+	pub fn statuscode(&self) -> StatusCode { self.kind.statuscode() }
+
 	pub fn is_empty(&self) -> bool {
 		return self.ioe.is_none() && self.ue.is_none();
 	}
 
+	pub fn is_protocol(&self) -> bool {
+		return self.ue.is_some();
+	}
+
 	pub fn is_eof(&self) -> bool {
 		return match self.ioe.as_ref() {
 			Some(v) => {
@@ -89,19 +192,187 @@ impl fmt::Debug for ParseError {
 }
 
 const BAD_REQUEST: &str = "bad request";
+const MALFORMED_REQUEST_LINE: &str = "malformed request line";
+const BODY_TOO_LARGE: &str = "body too large";
+const MALFORMED_STATUS_LINE: &str = "malformed status line";
+const START_LINE_REJECTED: &str = "rejected by request line";
+const MALFORMED_CHUNK_SIZE: &str = "malformed chunk size";
+// hex digits of the largest chunk size, leading zeros aside
+const MAX_CHUNK_SIZE_DIGITS: usize = 16;
+const METHOD_TOO_LONG: &str = "method too long";
+const TRUNCATED_BODY: &str = "body shorter than announced";
+const TOO_MANY_HEADER_VALUES: &str = "too many values for one header";
+const UNKNOWN_TRANSFER_CODING: &str = "unknown transfer coding";
+const LENGTH_WITH_TRANSFER_CODING: &str = "content length with transfer encoding";
+const CHUNKED_BODY_TIMEOUT: &str = "chunked body timeout";
+const HEADERS_TOO_LARGE: &str = "headers too large";
+const TOO_MANY_HEADERS: &str = "too many headers";
+const URI_TOO_LONG: &str = "uri too long";
+const MALFORMED_VERSION: &str = "malformed http version";
+const UNSUPPORTED_VERSION: &str = "unsupported http version";
+const NULL_BYTE: &str = "null byte";
+
+// `HTTP/<digit>.<digit>` (RFC 9112 2.3), 400 otherwise; only major version 1 is spoken here,
+// anything else, HTTP/2 without an upgrade included, is answered with 505
+fn check_version(version: &str) -> Option<ParseError> {
+	let digits = match version.strip_prefix("HTTP/") {
+		Some(v) => {
+			v.as_bytes()
+		}
+		None => {
+			return Some(ParseError::ue(MALFORMED_VERSION));
+		}
+	};
+	if digits.len() != 3 || !digits[0].is_ascii_digit() || digits[1] != b'.' || !digits[2].is_ascii_digit() {
+		return Some(ParseError::ue(MALFORMED_VERSION));
+	}
+	if digits[0] != b'1' {
+		return Some(ParseError::uekind(UNSUPPORTED_VERSION, ParseErrorKind::UnsupportedVersion));
+	}
+	return None;
+}
+
+// optional whitespace around a field value is SP and HTAB only (RFC 7230 3.2.3)
+fn trim_ows(v: &[u8]) -> &[u8] {
+	let is_ows = |c: &u8| { return *c == b' ' || *c == b'\t'; };
+	let start = v.iter().position(|c| !is_ows(c)).unwrap_or(v.len());
+	let end = v.iter().rposition(|c| !is_ows(c)).map_or(start, |idx| idx + 1);
+	return &v[start..end];
+}
 
 impl Message {
 	pub fn new() -> Self {
+		let limits = Limits::default();
 		return Self {
 			startline: (String::new(), String::new(), String::new()),
 			headers: None,
 			body: None,
+			bodylen: None,
+			rawheaders: None,
+			bodylimit: usize::MAX,
 			buf: None,
 			bufsize: 0,
 			bufremains: 0,
+			pool: None,
+			chunked_timeout: None,
+			spill: None,
+			body_file: None,
+			transfer_codings: vec![],
+			trailer_limits: (limits.max_header_bytes, limits.max_header_count),
 		};
 	}
 
+	// bodies read from now on come from `pool`, and go back to it on `clear` and drop
+	pub fn set_body_pool(&mut self, pool: Option<Arc<BufferPool>>) {
+		self.pool = pool;
+	}
+
+	// a chunked body has to be read whole within `duration` of `clock`, 408 otherwise
+	pub fn set_chunked_body_timeout(&mut self, timeout: Option<(Arc<dyn Clock>, Duration)>) {
+		self.chunked_timeout = timeout;
+	}
+
+	// Bodies read from now on that grow past `threshold` bytes are written to a file in `dir`
+	// as they come in, `body` is `None` then and `body_path` tells where it is. The file is
+	// removed on `clear` and drop.
+	pub fn set_body_spill(&mut self, spill: Option<(PathBuf, usize)>) {
+		self.spill = spill;
+	}
+
+	// Codings `read_body` accepts before a final `chunked`, they are left on the body. Without any,
+	// only a single `chunked` is.
+	pub fn set_transfer_codings(&mut self, codings: Vec<String>) {
+		self.transfer_codings = codings;
+	}
+
+	// a trailer section is held to the limits of a head, `max_header_bytes` and `max_header_count`
+	pub fn set_trailer_limits(&mut self, limits: &Limits) {
+		self.trailer_limits = (limits.max_header_bytes, limits.max_header_count);
+	}
+
+	pub fn body_path(&self) -> Option<&Path> {
+		return self.body_file.as_ref().map(|f| f.path());
+	}
+
+	// bytes of the body read so far, those in the file included
+	fn body_size(&self) -> usize {
+		let spilled = self.body_file.as_ref().map_or(0, |f| f.len() as usize);
+		return self.body.as_ref().map_or(0, |b| b.len()) + spilled;
+	}
+
+	// moves the body read so far to the file once it is past the threshold, or for good when
+	// `done` and the file was started
+	async fn spill_body(&mut self, done: bool) -> Option<ParseError> {
+		let (dir, threshold) = match self.spill.as_ref() {
+			Some(v) => {
+				v
+			}
+			None => {
+				return None;
+			}
+		};
+		let len = self.body.as_ref().map_or(0, |b| b.len());
+		if self.body_file.is_none() && len <= *threshold {
+			return None;
+		}
+		if self.body_file.is_none() {
+			match TempFile::create(dir).await {
+				Ok(f) => {
+					self.body_file = Some(f);
+				}
+				Err(e) => {
+					return Some(ParseError::ioe(e));
+				}
+			}
+		}
+		let file = self.body_file.as_mut().unwrap();
+		if len > 0 && (done || len > *threshold) {
+			match file.write(self.body.as_ref().unwrap()).await {
+				Ok(_) => {}
+				Err(e) => {
+					return Some(ParseError::ioe(e));
+				}
+			}
+			self.body.as_mut().unwrap().clear();
+		}
+		if done {
+			match file.flush().await {
+				Ok(_) => {}
+				Err(e) => {
+					return Some(ParseError::ioe(e));
+				}
+			}
+			self.release_body();
+			self.body = None;
+		}
+		return None;
+	}
+
+	fn alloc_body(&self, capacity: usize) -> BytesMut {
+		return match self.pool.as_ref() {
+			Some(pool) => {
+				pool.take(capacity)
+			}
+			None => {
+				BytesMut::with_capacity(capacity)
+			}
+		};
+	}
+
+	fn release_body(&mut self) {
+		match self.pool.as_ref() {
+			Some(pool) => {
+				match self.body.take() {
+					Some(body) => {
+						pool.put(body);
+					}
+					None => {}
+				}
+			}
+			None => {}
+		}
+	}
+
 	pub fn clear(&mut self) {
 		self.startline.0.clear();
 		self.startline.1.clear();
@@ -112,14 +383,38 @@ impl Message {
 			}
 			None => {}
 		}
-		self.bufremains = 0;
-		self.bufsize = 0;
+		// buffered bytes past this message belong to the next pipelined one, keep them
+		self.bodylen = None;
+		match self.rawheaders.as_mut() {
+			Some(rawref) => {
+				rawref.clear();
+			}
+			None => {}
+		}
+		self.release_body();
 		match self.body.as_mut() {
 			Some(bodyref) => {
 				bodyref.clear();
 			}
 			None => {}
 		}
+		self.body_file = None;
+	}
+
+	fn alloc_buf(&mut self) {
+		if self.buf.is_none() {
+			let mut buf = BytesMut::with_capacity(MESSAGE_BUFFER_SIZE);
+			unsafe {
+				buf.set_len(MESSAGE_BUFFER_SIZE);
+			}
+			self.buf = Some(buf);
+		}
+	}
+
+	// waits for the first bytes of the message unless some are buffered already
+	pub async fn fill_buffer<R: types::AsyncReader>(&mut self, stream: &mut R) -> Option<ParseError> {
+		self.alloc_buf();
+		return self.read(stream).await;
 	}
 
 	pub async fn read<R: tokio::io::AsyncRead + Unpin>(&mut self, stream: &mut R) -> Option<ParseError> {
@@ -151,6 +446,10 @@ impl Message {
 		loop {
 			match self.read(stream).await {
 				Some(e) => {
+					if e.is_empty() {
+						// not a graceful close, the client promised more
+						return Some(ParseError::uekind(TRUNCATED_BODY, ParseErrorKind::TruncatedBody));
+					}
 					return Some(e);
 				}
 				None => {}
@@ -166,8 +465,14 @@ impl Message {
 				bodyref.extend_from_slice(bytesslice);
 			} else {
 				self.bufremains -= remain;
-				remain = 0;
 				bodyref.extend_from_slice(&bytesslice[0..remain]);
+				remain = 0;
+			}
+			match self.spill_body(false).await {
+				Some(e) => {
+					return Some(e);
+				}
+				None => {}
 			}
 
 			if remain == 0 {
@@ -177,104 +482,208 @@ impl Message {
 		return None;
 	}
 
-	pub async fn read_byte<R: types::AsyncReader>(&mut self, stream: &mut R) -> Result<u8, ParseError> {
-		let bufref = self.buf.as_mut().unwrap().as_mut();
+	// HTTP/1.0 style framing of responses without Content-Length or chunked encoding:
+	// the body is everything up to the peer closing the connection.
+	pub async fn read_until_close<R: types::AsyncReader>(&mut self, stream: &mut R, limit: usize) -> Option<ParseError> {
+		if self.body.is_none() {
+			self.body = Some(self.alloc_body(4096));
+		}
+		loop {
+			match self.read(stream).await {
+				Some(e) => {
+					if e.is_empty() {
+						break;
+					}
+					return Some(e);
+				}
+				None => {}
+			}
 
-		let c: u8;
-		if self.bufremains > 0 {
-			self.bufremains -= 1;
-			c = bufref[self.bufsize - self.bufremains];
-		} else {
-			match stream.read_u8().await {
-				Ok(v) => {
-					c = v;
+			if self.body_size() + self.bufremains > limit {
+				return Some(ParseError::uekind(BODY_TOO_LARGE, ParseErrorKind::TooLarge));
+			}
+			let bodyref = self.body.as_mut().unwrap();
+			let bufref = self.buf.as_mut().unwrap().as_mut();
+			bodyref.extend_from_slice(&bufref[self.bufsize - self.bufremains..self.bufsize]);
+			self.bufremains = 0;
+			match self.spill_body(false).await {
+				Some(e) => {
+					return Some(e);
 				}
-				Err(e) => {
-					return Err(ParseError::ioe(e));
+				None => {}
+			}
+		}
+		self.bodylen = Some(self.body_size());
+		return self.spill_body(true).await;
+	}
+
+	// goes through the message buffer, the stream is only read when the buffer is used up
+	pub async fn read_byte<R: types::AsyncReader>(&mut self, stream: &mut R) -> Result<u8, ParseError> {
+		match self.read(stream).await {
+			Some(e) => {
+				if e.is_empty() {
+					return Err(ParseError::ioe(std::io::Error::from(ErrorKind::UnexpectedEof)));
 				}
+				return Err(e);
 			}
+			None => {}
 		}
+
+		let bufref = self.buf.as_mut().unwrap().as_mut();
+		let c = bufref[self.bufsize - self.bufremains];
+		self.bufremains -= 1;
 		return Ok(c);
 	}
 
+	// Size lines, data and the CRLF after them are all parsed out of the buffered bytes in one pass,
+	// the stream is only read again once the buffer is used up.
 	pub async fn read_chunked_body<R: types::AsyncReader>(&mut self, stream: &mut R) -> Option<ParseError> {
-		let mut current_chunk_size: Option<usize> = None;
+		let mut state = ChunkState::Size;
 		let mut numbuf = String::new();
-		let mut skip_newline = false;
 
 		loop {
-			match current_chunk_size {
-				None => {
-					match self.read(stream).await {
-						Some(e) => {
-							return Some(e);
-						}
-						None => {}
+			match self.read(stream).await {
+				Some(e) => {
+					if !e.is_empty() {
+						return Some(e);
 					}
-
-					let bufref = self.buf.as_mut().unwrap().as_mut();
-					let bytesslice: &[u8] = &bufref[self.bufsize - self.bufremains..self.bufsize];
-					for c in bytesslice {
-						self.bufremains -= 1;
-						let c = *c;
-
-						if skip_newline {
-							if c != b'\n' {
-								return Some(ParseError::ue(BAD_REQUEST));
-							}
-							skip_newline = false;
-							break;
+					return match state {
+						ChunkState::Size => {
+							Some(e)
 						}
+						ChunkState::Data(_) => {
+							Some(ParseError::uekind(TRUNCATED_BODY, ParseErrorKind::TruncatedBody))
+						}
+						_ => {
+							Some(ParseError::ioe(std::io::Error::from(ErrorKind::UnexpectedEof)))
+						}
+					};
+				}
+				None => {}
+			}
 
+			let spilled = self.body_file.as_ref().map_or(0, |f| f.len() as usize);
+			let bodyref = self.body.as_mut().unwrap();
+			let bufref = self.buf.as_ref().unwrap().as_ref();
+			let bytesslice: &[u8] = &bufref[self.bufsize - self.bufremains..self.bufsize];
+			let mut used = 0;
+			while used < bytesslice.len() {
+				let c = bytesslice[used];
+				match state {
+					ChunkState::Size => {
+						used += 1;
 						if c == b'\r' {
-							match numbuf.parse::<i32>() {
+							match usize::from_str_radix(numbuf.as_str(), 16) {
 								Ok(v) => {
-									if v < 0 {
-										return Some(ParseError::ue(BAD_REQUEST));
-									}
-									current_chunk_size = Some(v as usize);
+									state = ChunkState::SizeLF(v);
 								}
 								Err(_) => {
-									return Some(ParseError::ue(BAD_REQUEST));
+									return Some(ParseError::ue(MALFORMED_CHUNK_SIZE));
 								}
 							}
+							numbuf.clear();
 							continue;
 						}
+						// hex digits only, no whitespace around them and no sign `from_str_radix` would take
+						if !c.is_ascii_hexdigit() {
+							return Some(ParseError::ue(MALFORMED_CHUNK_SIZE));
+						}
+						// leading zeros are kept as one, past them a size has 16 digits at most
+						if numbuf == "0" {
+							numbuf.clear();
+						}
+						if numbuf.len() == MAX_CHUNK_SIZE_DIGITS {
+							return Some(ParseError::ue(MALFORMED_CHUNK_SIZE));
+						}
 						numbuf.push(c as char);
 					}
-				}
-				Some(remain) => {
-					match self.read_sized_body(stream, remain).await {
-						Some(e) => {
-							return Some(e);
+					ChunkState::SizeLF(size) => {
+						used += 1;
+						if c != b'\n' {
+							return Some(ParseError::ue(BAD_REQUEST));
 						}
-						None => {
-							match self.read_byte(stream).await {
-								Ok(c) => {
-									if c != b'\r' {
-										return Some(ParseError::ue(BAD_REQUEST));
-									}
-								}
-								Err(e) => {
-									return Some(e);
-								}
-							}
-							match self.read_byte(stream).await {
-								Ok(c) => {
-									if c != b'\n' {
-										return Some(ParseError::ue(BAD_REQUEST));
-									}
-								}
-								Err(e) => {
-									return Some(e);
-								}
-							}
-							if remain == 0 {
-								break;
-							}
+						if size == 0 {
+							self.bufremains -= used;
+							return self.read_chunked_trailers(stream).await;
+						}
+						// `size` is the client's, it may be anything up to `usize::MAX`
+						if (bodyref.len() + spilled).checked_add(size).is_none_or(|total| total > self.bodylimit) {
+							return Some(ParseError::uekind(BODY_TOO_LARGE, ParseErrorKind::TooLarge));
+						}
+						state = ChunkState::Data(size);
+					}
+					ChunkState::Data(remain) => {
+						let size = remain.min(bytesslice.len() - used);
+						bodyref.extend_from_slice(&bytesslice[used..used + size]);
+						used += size;
+						state = if size == remain { ChunkState::DataCR } else { ChunkState::Data(remain - size) };
+					}
+					ChunkState::DataCR => {
+						used += 1;
+						if c != b'\r' {
+							return Some(ParseError::ue(BAD_REQUEST));
+						}
+						state = ChunkState::DataLF;
+					}
+					ChunkState::DataLF => {
+						used += 1;
+						if c != b'\n' {
+							return Some(ParseError::ue(BAD_REQUEST));
 						}
+						state = ChunkState::Size;
+					}
+				}
+			}
+			self.bufremains -= used;
+			match self.spill_body(false).await {
+				Some(e) => {
+					return Some(e);
+				}
+				None => {}
+			}
+		}
+	}
+
+	// Consumes the trailer section after the last chunk, up to and including the final empty line.
+	// Trailer fields are discarded, they still count against `trailer_limits` like header lines.
+	async fn read_chunked_trailers<R: types::AsyncReader>(&mut self, stream: &mut R) -> Option<ParseError> {
+		let (max_bytes, max_lines) = self.trailer_limits;
+		let mut linesize = 0;
+		let mut size = 0;
+		let mut lines = 0;
+		loop {
+			match self.read_byte(stream).await {
+				Ok(c) => {
+					size += 1;
+					if size > max_bytes {
+						return Some(ParseError::uekind(HEADERS_TOO_LARGE, ParseErrorKind::HeadersTooLarge));
+					}
+					if c != b'\r' {
+						linesize += 1;
+						continue;
 					}
 				}
+				Err(e) => {
+					return Some(e);
+				}
+			}
+			match self.read_byte(stream).await {
+				Ok(c) => {
+					if c != b'\n' {
+						return Some(ParseError::ue(BAD_REQUEST));
+					}
+				}
+				Err(e) => {
+					return Some(e);
+				}
+			}
+			if linesize == 0 {
+				break;
+			}
+			linesize = 0;
+			lines += 1;
+			if lines > max_lines {
+				return Some(ParseError::uekind(TOO_MANY_HEADERS, ParseErrorKind::HeadersTooLarge));
 			}
 		}
 		return None;
@@ -284,16 +693,28 @@ impl Message {
 		let mut cl: Option<usize> = None;
 		match &self.headers {
 			Some(href) => {
+				// where the body ends is unknown, nothing after this head can be trusted either
+				if href.has_unknown_transfer_coding(&self.transfer_codings) {
+					return Some(ParseError::ue(UNKNOWN_TRANSFER_CODING));
+				}
 				cl = href.content_length();
+				// either one may be what a proxy in front framed it by (RFC 7230 3.3.3)
+				if cl.is_some() && href.get(hns::TRANSFER_ENCODING).is_some() {
+					return Some(ParseError::ue(LENGTH_WITH_TRANSFER_CODING));
+				}
 			}
 			None => {}
 		}
 
 		match cl {
 			Some(cl) => {
+				if cl > self.bodylimit {
+					return Some(ParseError::uekind(BODY_TOO_LARGE, ParseErrorKind::TooLarge));
+				}
 				if self.body.is_none() {
-					let buf = BytesMut::with_capacity(cl);
-					self.body = Some(buf);
+					// a body bound for a file is never held whole
+					let capacity = self.spill.as_ref().map_or(cl, |(_, threshold)| cl.min(*threshold + MESSAGE_BUFFER_SIZE));
+					self.body = Some(self.alloc_body(capacity));
 				}
 				match self.read_sized_body(stream, cl).await {
 					Some(e) => {
@@ -301,6 +722,7 @@ impl Message {
 					}
 					None => {}
 				}
+				self.bodylen = Some(cl);
 			}
 			None => {
 				let mut is_chunked = false;
@@ -312,35 +734,158 @@ impl Message {
 				}
 				if is_chunked {
 					if self.body.is_none() {
-						let buf = BytesMut::with_capacity(4096);
-						self.body = Some(buf);
+						self.body = Some(self.alloc_body(4096));
 					}
-					match self.read_chunked_body(stream).await {
+					// the chunks may trickle in for ever, unlike a sized body it has no end in sight
+					let err = match self.chunked_timeout.clone() {
+						Some((clock, duration)) => {
+							tokio::select! {
+								e = self.read_chunked_body(stream) => {
+									e
+								}
+								_ = clock.sleep(duration) => {
+									Some(ParseError::uekind(CHUNKED_BODY_TIMEOUT, ParseErrorKind::Timeout))
+								}
+							}
+						}
+						None => {
+							self.read_chunked_body(stream).await
+						}
+					};
+					match err {
 						Some(e) => {
 							return Some(e);
 						}
 						None => {}
 					}
+					self.bodylen = Some(self.body_size());
 				}
 			}
 		}
-		return None;
+		return self.spill_body(true).await;
 	}
 
-	pub async fn from<R: types::AsyncReader>(&mut self, stream: &mut R) -> Option<ParseError> {
-		if self.buf.is_none() {
-			let mut buf = BytesMut::with_capacity(MESSAGE_BUFFER_SIZE);
-			unsafe {
-				buf.set_len(MESSAGE_BUFFER_SIZE);
+	// Whether bytes follow the message: buffered ones, or the next ones the stream receives. Waits
+	// until either they or the end of the stream come, callers bound it with a timeout.
+	pub async fn has_trailing_bytes<R: types::AsyncReader>(&mut self, stream: &mut R) -> Result<bool, ParseError> {
+		if self.bufremains > 0 {
+			return Ok(true);
+		}
+		let buf = match self.buf.as_mut() {
+			Some(v) => {
+				v
+			}
+			None => {
+				return Ok(false);
+			}
+		};
+		loop {
+			match stream.read(buf.as_mut()).await {
+				Ok(size) => {
+					self.bufsize = size;
+					self.bufremains = size;
+					return Ok(size > 0);
+				}
+				Err(e) => {
+					if e.kind() != ErrorKind::Interrupted {
+						return Err(ParseError::ioe(e));
+					}
+				}
+			}
+		}
+	}
+
+	// `read_body`, failing with 413 once the body grows past `limit` bytes
+	pub async fn read_body_limited<R: types::AsyncReader>(&mut self, stream: &mut R, limit: usize) -> Option<ParseError> {
+		self.bodylimit = limit;
+		let err = self.read_body(stream).await;
+		self.bodylimit = usize::MAX;
+		return err;
+	}
+
+	// Like `read_body`, but a message without any framing is read until the connection closes,
+	// which is only valid for responses.
+	pub async fn read_response_body<R: types::AsyncReader>(&mut self, stream: &mut R, limit: usize) -> Option<ParseError> {
+		let framed = match self.headers.as_ref() {
+			Some(href) => {
+				// a response with other transfer codings runs until the connection closes (RFC 7230 3.3.3)
+				if href.get(hns::TRANSFER_ENCODING).is_some() { href.is_chunked() } else { href.content_length().is_some() }
+			}
+			None => {
+				false
+			}
+		};
+		if framed {
+			return self.read_body(stream).await;
+		}
+		return self.read_until_close(stream, limit).await;
+	}
+
+	pub async fn from<R: types::AsyncReader>(&mut self, stream: &mut R, cfg: &Config) -> Option<ParseError> {
+		match self.read_headers(stream, cfg).await {
+			Some(e) => {
+				return Some(e);
+			}
+			None => {}
+		}
+		return self.read_body(stream).await;
+	}
+
+	// `from`, reading through `buf` of the caller instead of a buffer of its own, its capacity is
+	// read into at once. Bytes already in `buf` are taken as the start of the message; the ones
+	// read past it are left at the front of `buf` for the next message.
+	pub async fn read_with_buffer<R: types::AsyncReader>(&mut self, stream: &mut R, cfg: &Config, buf: &mut BytesMut) -> Option<ParseError> {
+		let unread = buf.len();
+		if buf.capacity() == 0 {
+			buf.reserve(MESSAGE_BUFFER_SIZE);
+		}
+		let capacity = buf.capacity();
+		buf.resize(capacity, 0);
+		let own = (self.buf.replace(std::mem::take(buf)), self.bufsize, self.bufremains);
+		self.bufsize = unread;
+		self.bufremains = unread;
+
+		let err = self.from(stream, cfg).await;
+
+		*buf = self.buf.take().unwrap();
+		buf.copy_within(self.bufsize - self.bufremains..self.bufsize, 0);
+		buf.truncate(self.bufremains);
+		(self.buf, self.bufsize, self.bufremains) = own;
+		return err;
+	}
+
+	pub async fn read_headers<R: types::AsyncReader>(&mut self, stream: &mut R, cfg: &Config) -> Option<ParseError> {
+		return self.read_headers_as(stream, cfg, MessageKind::Request).await;
+	}
+
+	pub async fn read_headers_as<R: types::AsyncReader>(&mut self, stream: &mut R, cfg: &Config, kind: MessageKind) -> Option<ParseError> {
+		let malformed = match kind {
+			MessageKind::Request => {
+				MALFORMED_REQUEST_LINE
+			}
+			MessageKind::Response => {
+				MALFORMED_STATUS_LINE
+			}
+		};
+		self.alloc_buf();
+		if cfg.keep_raw_headers {
+			match self.rawheaders.as_mut() {
+				Some(rawref) => {
+					rawref.clear();
+				}
+				None => {
+					self.rawheaders = Some(Vec::new());
+				}
 			}
-			self.buf = Some(buf);
 		}
 
 		let mut status: ParseStatus = ParseStatus::Empty;
 		let mut skip_newline = false;
 		let mut hkey = String::new();
-		let mut hval = String::new();
+		let mut hval: Vec<u8> = Vec::new();
 		let mut hkvsep = false;
+		let mut headbytes = 0;
+		let mut headercount = 0;
 
 		loop {
 			match self.read(stream).await {
@@ -356,6 +901,17 @@ impl Message {
 			for c in bytesslice {
 				self.bufremains -= 1;
 				let c = *c;
+				headbytes += 1;
+				if headbytes > cfg.limits.max_header_bytes {
+					return Some(ParseError::uekind(HEADERS_TOO_LARGE, ParseErrorKind::HeadersTooLarge));
+				}
+				if cfg.keep_raw_headers {
+					self.rawheaders.as_mut().unwrap().push(c);
+				}
+				// never valid in a head, it may cut a value short further down the line
+				if c == 0 {
+					return Some(ParseError::ue(NULL_BYTE));
+				}
 
 				if skip_newline {
 					if c != b'\n' {
@@ -372,22 +928,63 @@ impl Message {
 				match status {
 					ParseStatus::Empty => {
 						if c == b' ' {
+							if self.startline.0.is_empty() {
+								return Some(ParseError::ue(malformed));
+							}
 							status = ParseStatus::Startline1;
+						} else if c == b'\r' || c == b'\n' {
+							return Some(ParseError::ue(malformed));
 						} else {
+							if kind == MessageKind::Request && self.startline.0.len() >= cfg.max_method_length {
+								return Some(ParseError::ue(METHOD_TOO_LONG));
+							}
 							self.startline.0.push(c as char);
 						}
 					}
 					ParseStatus::Startline1 => {
 						if c == b' ' {
+							if self.startline.1.is_empty() {
+								return Some(ParseError::ue(malformed));
+							}
 							status = ParseStatus::Startline2;
+						} else if c == b'\r' || c == b'\n' {
+							return Some(ParseError::ue(malformed));
 						} else {
+							if kind == MessageKind::Request && self.startline.1.len() >= cfg.limits.max_uri_length {
+								return Some(ParseError::uekind(URI_TOO_LONG, ParseErrorKind::UriTooLong));
+							}
 							self.startline.1.push(c as char);
 						}
 					}
 					ParseStatus::Startline2 => {
 						if c == b'\r' {
+							// a status line may end with an empty reason phrase, a request line needs its version
+							if self.startline.2.is_empty() && kind == MessageKind::Request {
+								return Some(ParseError::ue(malformed));
+							}
+							if kind == MessageKind::Request {
+								match check_version(&self.startline.2) {
+									Some(e) => {
+										return Some(e);
+									}
+									None => {}
+								}
+								match cfg.start_line_check.as_ref() {
+									Some(check) => {
+										match check(&self.startline.0, &self.startline.1, &self.startline.2) {
+											Some(code) => {
+												return Some(ParseError::uekind(START_LINE_REJECTED, ParseErrorKind::Rejected(code)));
+											}
+											None => {}
+										}
+									}
+									None => {}
+								}
+							}
 							status = ParseStatus::Startline3;
 							skip_newline = true;
+						} else if c == b'\n' || (c == b' ' && kind == MessageKind::Request) {
+							return Some(ParseError::ue(malformed));
 						} else {
 							self.startline.2.push(c as char);
 						}
@@ -400,10 +997,18 @@ impl Message {
 									self.headers = Some(Headers::new());
 								}
 								let headersref = self.headers.as_mut().unwrap();
-								headersref.builder().append(
+								let appended = headersref.builder().append_limited(
 									&hkey.trim().to_ascii_lowercase(),
-									&hval.trim(),
+									trim_ows(hval.as_slice()),
+									cfg.max_header_values_per_name,
 								);
+								if !appended {
+									return Some(ParseError::ue(TOO_MANY_HEADER_VALUES));
+								}
+								headercount += 1;
+								if headercount > cfg.limits.max_header_count {
+									return Some(ParseError::uekind(TOO_MANY_HEADERS, ParseErrorKind::HeadersTooLarge));
+								}
 								hkey.clear();
 								hval.clear();
 								hkvsep = false;
@@ -415,7 +1020,7 @@ impl Message {
 								continue;
 							}
 						} else if hkvsep {
-							hval.push(c as char);
+							hval.push(c);
 						} else if c == b':' {
 							hkvsep = true;
 						} else {
@@ -427,11 +1032,42 @@ impl Message {
 					}
 				}
 			}
-			if status == ParseStatus::HeadersOK {
+			if status == ParseStatus::HeadersOK && !skip_newline {
 				break;
 			}
 		}
-		return self.read_body(stream).await;
+		return None;
+	}
+
+	pub fn expects_body(&self) -> bool {
+		return match self.headers.as_ref() {
+			Some(href) => {
+				href.content_length().unwrap_or(0) > 0 || href.is_chunked()
+			}
+			None => {
+				false
+			}
+		};
+	}
+
+	pub fn take_read_buffer(&mut self) -> Option<ReadBuffer> {
+		return match self.buf.take() {
+			Some(buf) => {
+				let rb = ReadBuffer { buf, size: self.bufsize, remains: self.bufremains };
+				self.bufsize = 0;
+				self.bufremains = 0;
+				Some(rb)
+			}
+			None => {
+				None
+			}
+		};
+	}
+
+	pub fn set_read_buffer(&mut self, rb: ReadBuffer) {
+		self.buf = Some(rb.buf);
+		self.bufsize = rb.size;
+		self.bufremains = rb.remains;
 	}
 
 	pub fn headers_builder(&mut self) -> headers::Builder {
@@ -441,3 +1077,529 @@ impl Message {
 		return self.headers.as_mut().unwrap().builder();
 	}
 }
+
+impl Drop for Message {
+	fn drop(&mut self) {
+		self.release_body();
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::pin::Pin;
+	use std::sync::Arc;
+	use bytes::BytesMut;
+	use std::task::{Context, Poll};
+	use tokio::io::{AsyncRead, AsyncWriteExt, ReadBuf};
+	use crate::h2tp::cfg::{Config, Limits, MESSAGE_BUFFER_SIZE};
+	use crate::h2tp::message::{Message, MessageKind, ParseError, ParseErrorKind};
+	use crate::h2tp::status_code::StatusCode;
+
+	const CHUNKED_HEAD: &[u8] = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n";
+
+	async fn parse(raw: &[u8]) -> (Message, Option<String>) {
+		let mut stream = raw;
+		let mut msg = Message::new();
+		let err = msg.from(&mut stream, &Config::new()).await.map(|e| format!("{:?}", e));
+		return (msg, err);
+	}
+
+	#[tokio::test]
+	async fn test_request_line() {
+		let (msg, err) = parse(b"GET / HTTP/1.1\r\n\r\n").await;
+		assert!(err.is_none());
+		assert_eq!(msg.startline, ("GET".to_string(), "/".to_string(), "HTTP/1.1".to_string()));
+
+		let (_, err) = parse(b"GET /\r\n\r\n").await;
+		assert_eq!(err.unwrap(), "malformed request line");
+
+		let (_, err) = parse(b"GET  / HTTP/1.1\r\n\r\n").await;
+		assert_eq!(err.unwrap(), "malformed request line");
+
+		let (_, err) = parse(b"GET / HTTP/1.1 \r\n\r\n").await;
+		assert_eq!(err.unwrap(), "malformed request line");
+
+		let (_, err) = parse(b"GET\r\n\r\n").await;
+		assert_eq!(err.unwrap(), "malformed request line");
+	}
+
+	#[tokio::test]
+	async fn test_method_length() {
+		let (msg, err) = parse(format!("{} / HTTP/1.1\r\n\r\n", "M".repeat(32)).as_bytes()).await;
+		assert!(err.is_none());
+		assert_eq!(msg.startline.0.len(), 32);
+
+		// nothing past the cap is kept
+		let raw = "M".repeat(1 << 20);
+		let (msg, err) = parse(raw.as_bytes()).await;
+		assert_eq!(err.unwrap(), "method too long");
+		assert_eq!(msg.startline.0.len(), 32);
+	}
+
+	#[tokio::test]
+	async fn test_chunked_terminator() {
+		let raw = [CHUNKED_HEAD, b"4\r\nspk!\r\n0\r\n\r\nGET"].concat();
+		let (msg, err) = parse(&raw).await;
+		assert!(err.is_none());
+		assert_eq!(msg.body.as_ref().unwrap().as_ref(), b"spk!");
+		// only the next request's bytes are left in the buffer
+		assert_eq!(msg.bufremains, 3);
+
+		let raw = [CHUNKED_HEAD, b"4\r\nspk!\r\n0\r\nExpires: 0\r\nX-Checksum: 1\r\n\r\nGET"].concat();
+		let (msg, err) = parse(&raw).await;
+		assert!(err.is_none());
+		assert_eq!(msg.body.as_ref().unwrap().as_ref(), b"spk!");
+		assert_eq!(msg.bufremains, 3);
+	}
+
+	#[tokio::test]
+	async fn test_utf8_header_value() {
+		let value = "Jos\u{e9} \u{4e16}\u{754c}";
+		let raw = format!("GET / HTTP/1.1\r\nX-User: {}\r\n\r\n", value);
+		let (msg, err) = parse(raw.as_bytes()).await;
+		assert!(err.is_none());
+		let href = msg.headers.as_ref().unwrap();
+		assert_eq!(href.get_bytes("x-user").unwrap(), value.as_bytes());
+		assert_eq!(href.get("x-user").unwrap(), value);
+	}
+
+	#[tokio::test]
+	async fn test_utf8_across_buffer_fill() {
+		let head = "GET / HTTP/1.1\r\nX-User: ";
+		// the character starts one, then two bytes before the end of the first buffer fill
+		for before_end in [1, 2] {
+			let pad = "a".repeat(MESSAGE_BUFFER_SIZE - head.len() - before_end);
+			let value = format!("{}\u{4e16}\u{754c}", pad);
+			let raw = format!("{}{}\r\n\r\n", head, value);
+			let mut stream = CountingReader { data: raw.as_bytes(), step: MESSAGE_BUFFER_SIZE, reads: 0 };
+			let mut msg = Message::new();
+			assert!(msg.from(&mut stream, &Config::new()).await.is_none());
+			assert!(stream.reads >= 2);
+			let href = msg.headers.as_ref().unwrap();
+			assert_eq!(href.get_bytes("x-user").unwrap(), value.as_bytes());
+			assert_eq!(href.get("x-user").unwrap(), value);
+		}
+	}
+
+	#[tokio::test]
+	async fn test_read_until_close() {
+		let (client, server) = tokio::io::duplex(16);
+		let (mut sr, _sw) = tokio::io::split(server);
+		let (_cr, mut cw) = tokio::io::split(client);
+		let writer = tokio::spawn(async move {
+			cw.write_all(b"HTTP/1.0 200 OK\r\nContent-Type: text/plain\r\n\r\n").await.unwrap();
+			for _ in 0..10 {
+				cw.write_all(b"0123456789").await.unwrap();
+			}
+			cw.shutdown().await.unwrap();
+		});
+
+		let mut msg = Message::new();
+		assert!(msg.read_headers_as(&mut sr, &Config::new(), MessageKind::Response).await.is_none());
+		assert!(msg.read_response_body(&mut sr, 1024).await.is_none());
+		writer.await.unwrap();
+		assert_eq!(msg.bodylen, Some(100));
+		assert_eq!(msg.body.as_ref().unwrap().as_ref(), b"0123456789".repeat(10).as_slice());
+
+		let mut stream: &[u8] = b"HTTP/1.0 200 OK\r\n\r\n0123456789";
+		let mut msg = Message::new();
+		assert!(msg.read_headers_as(&mut stream, &Config::new(), MessageKind::Response).await.is_none());
+		let err = msg.read_response_body(&mut stream, 8).await;
+		assert_eq!(format!("{:?}", err.unwrap()), "body too large");
+	}
+
+	#[test]
+	fn test_error_kind() {
+		let cases = [
+			(ParseErrorKind::BadRequest, StatusCode::BadRequest),
+			(ParseErrorKind::TooLarge, StatusCode::PayloadTooLarge),
+			(ParseErrorKind::UriTooLong, StatusCode::URITooLong),
+			(ParseErrorKind::HeadersTooLarge, StatusCode::RequestHeaderFieldsTooLarge),
+			(ParseErrorKind::UnsupportedMediaType, StatusCode::UnsupportedMediaType),
+			(ParseErrorKind::Timeout, StatusCode::RequestTimeout),
+			(ParseErrorKind::UnsupportedVersion, StatusCode::HTTPVersionNotSupported),
+			(ParseErrorKind::TruncatedBody, StatusCode::BadRequest),
+		];
+		for (kind, code) in cases {
+			let err = ParseError::uekind("test", kind);
+			assert!(err.is_protocol());
+			assert_eq!(err.kind(), kind);
+			assert!(err.statuscode() == code);
+		}
+
+		let err = ParseError::ue("malformed");
+		assert_eq!(err.kind(), ParseErrorKind::BadRequest);
+		let err = ParseError::ioe(std::io::Error::from(std::io::ErrorKind::UnexpectedEof));
+		assert_eq!(err.kind(), ParseErrorKind::Io);
+		assert!(!err.is_protocol());
+	}
+
+	// hands out at most `step` bytes per read and counts the reads
+	struct CountingReader<'a> {
+		data: &'a [u8],
+		step: usize,
+		reads: usize,
+	}
+
+	impl<'a> AsyncRead for CountingReader<'a> {
+		fn poll_read(mut self: Pin<&mut Self>, _: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+			self.reads += 1;
+			let size = self.data.len().min(self.step).min(buf.remaining());
+			buf.put_slice(&self.data[..size]);
+			self.data = &self.data[size..];
+			return Poll::Ready(Ok(()));
+		}
+	}
+
+	#[tokio::test]
+	async fn test_buffered_read_byte() {
+		let body = b"1\r\na\r\n2\r\nbc\r\n3\r\ndef\r\n4\r\nghij\r\n0\r\nX-Trailer: 1\r\n\r\n";
+		let raw = [CHUNKED_HEAD, body].concat();
+		let mut stream = CountingReader { data: raw.as_slice(), step: 16, reads: 0 };
+		let mut msg = Message::new();
+		assert!(msg.from(&mut stream, &Config::new()).await.is_none());
+		assert_eq!(msg.body.as_ref().unwrap().as_ref(), b"abcdefghij");
+		// one read per 16 bytes at most, not one per byte
+		assert!(stream.reads <= raw.len() / 16 + 2, "{} reads for {} bytes", stream.reads, raw.len());
+	}
+
+	#[tokio::test]
+	async fn test_trailer_limits() {
+		let mut limits = Limits::new();
+		limits.max_header_count(2).max_header_bytes(64);
+		let cases = [
+			("X-A: 1\r\nX-B: 2\r\n".to_string(), None),
+			("X-A: 1\r\nX-B: 2\r\nX-C: 3\r\n".to_string(), Some("too many headers")),
+			(format!("X-Long: {}\r\n", "a".repeat(64)), Some("headers too large")),
+		];
+		for (trailers, expected) in cases {
+			let raw = [CHUNKED_HEAD, format!("1\r\na\r\n0\r\n{}\r\n", trailers).as_bytes()].concat();
+			let mut stream = raw.as_slice();
+			let mut msg = Message::new();
+			msg.set_trailer_limits(&limits);
+			let err = msg.from(&mut stream, &Config::new()).await;
+			assert_eq!(err.as_ref().map(|e| format!("{:?}", e)).as_deref(), expected, "{}", trailers);
+			match err {
+				Some(e) => {
+					assert!(e.statuscode() == StatusCode::RequestHeaderFieldsTooLarge);
+				}
+				None => {}
+			}
+		}
+
+		// without a limit set, those of a head apply
+		let raw = [CHUNKED_HEAD, format!("0\r\n{}\r\n", "X-A: 1\r\n".repeat(200)).as_bytes()].concat();
+		let (_, err) = parse(&raw).await;
+		assert_eq!(err.unwrap(), "too many headers");
+	}
+
+	#[tokio::test]
+	async fn test_empty_chunked_body() {
+		let raw = [CHUNKED_HEAD, b"0\r\n\r\n"].concat();
+		let (msg, err) = parse(&raw).await;
+		assert!(err.is_none());
+		assert_eq!(msg.body.as_ref().unwrap().len(), 0);
+		assert_eq!(msg.bodylen, Some(0));
+		assert_eq!(msg.bufremains, 0);
+
+		// the same split over reads of every size
+		for step in 1..8 {
+			let mut stream = CountingReader { data: raw.as_slice(), step, reads: 0 };
+			let mut msg = Message::new();
+			assert!(msg.from(&mut stream, &Config::new()).await.is_none(), "step {}", step);
+			assert_eq!(msg.bodylen, Some(0));
+		}
+
+		// a missing size is not an empty chunk
+		let (_, err) = parse(&[CHUNKED_HEAD, b"\r\n\r\n"].concat()).await;
+		assert_eq!(err.unwrap(), "malformed chunk size");
+	}
+
+	#[tokio::test]
+	async fn test_header_values_per_name() {
+		let mut cfg = Config::new();
+		cfg.max_header_values_per_name = 3;
+		let mut raw = String::from("GET / HTTP/1.1\r\n");
+		for i in 0..3 {
+			raw.push_str(&format!("Cookie: c{}=1\r\nX-Other: {}\r\n", i, i));
+		}
+
+		let head = format!("{}\r\n", raw);
+		let mut stream = head.as_bytes();
+		let mut msg = Message::new();
+		assert!(msg.from(&mut stream, &cfg).await.is_none());
+		assert_eq!(msg.headers.as_ref().unwrap().get_all("cookie").unwrap().len(), 3);
+
+		// names are counted case-insensitively
+		let head = format!("{}cookie: c3=1\r\n\r\n", raw);
+		let mut stream = head.as_bytes();
+		let mut msg = Message::new();
+		let err = msg.from(&mut stream, &cfg).await.unwrap();
+		assert_eq!(format!("{:?}", err), "too many values for one header");
+		assert!(err.statuscode() == StatusCode::BadRequest);
+	}
+
+	#[tokio::test]
+	async fn test_request_version() {
+		for version in ["HTTP/1.1", "HTTP/1.0", "HTTP/1.9"] {
+			let (_, err) = parse(format!("GET / {}\r\n\r\n", version).as_bytes()).await;
+			assert!(err.is_none());
+		}
+		let cases = [
+			("HTTP/2.0", "unsupported http version", StatusCode::HTTPVersionNotSupported),
+			("HTTP/3.0", "unsupported http version", StatusCode::HTTPVersionNotSupported),
+			("HTTP/0.9", "unsupported http version", StatusCode::HTTPVersionNotSupported),
+			("HTTP/x.y", "malformed http version", StatusCode::BadRequest),
+			("HTTP/1", "malformed http version", StatusCode::BadRequest),
+			("HTTP/11.1", "malformed http version", StatusCode::BadRequest),
+			("http/1.1", "malformed http version", StatusCode::BadRequest),
+		];
+		for (version, msg, code) in cases {
+			let raw = format!("GET / {}\r\n\r\n", version);
+			let mut stream = raw.as_bytes();
+			let err = Message::new().from(&mut stream, &Config::new()).await.unwrap();
+			assert_eq!(format!("{:?}", err), msg);
+			assert!(err.statuscode() == code);
+		}
+	}
+
+	#[tokio::test]
+	async fn test_null_byte() {
+		for raw in [
+			&b"GET /a\0b HTTP/1.1\r\n\r\n"[..],
+			b"GET / HTTP/1.1\r\nX-User: a\0b\r\n\r\n",
+			b"GET / HTTP/1.1\r\nX-\0User: ab\r\n\r\n",
+			b"\0GET / HTTP/1.1\r\n\r\n",
+		] {
+			let (_, err) = parse(raw).await;
+			assert_eq!(err.unwrap(), "null byte");
+		}
+		// the body is not the head, it may carry anything
+		let (msg, err) = parse(b"POST / HTTP/1.1\r\nContent-Length: 3\r\n\r\na\0b").await;
+		assert!(err.is_none());
+		assert_eq!(msg.body.as_ref().unwrap().as_ref(), b"a\0b");
+	}
+
+	#[tokio::test]
+	async fn test_borrowed_buffer() {
+		let raw = b"POST /a HTTP/1.1\r\nContent-Length: 5\r\n\r\nHelloGET /b HTTP/1.1\r\n\r\n";
+		for capacity in [4096, 16] {
+			let mut buf = BytesMut::with_capacity(capacity);
+			let ptr = buf.as_ptr();
+			let mut stream: &[u8] = raw;
+
+			let mut msg = Message::new();
+			assert!(msg.read_with_buffer(&mut stream, &Config::new(), &mut buf).await.is_none());
+			assert_eq!(msg.startline.1, "/a");
+			assert_eq!(msg.body.as_ref().unwrap().as_ref(), b"Hello");
+			assert!(msg.buf.is_none());
+			// what was read past the message is left at the front of the buffer
+			if capacity == 4096 {
+				assert_eq!(buf.as_ref(), b"GET /b HTTP/1.1\r\n\r\n");
+			}
+
+			let mut msg = Message::new();
+			assert!(msg.read_with_buffer(&mut stream, &Config::new(), &mut buf).await.is_none());
+			assert_eq!(msg.startline.1, "/b");
+			assert!(buf.is_empty());
+			assert_eq!(buf.as_ptr(), ptr);
+			assert_eq!(buf.capacity(), capacity);
+		}
+	}
+
+	#[tokio::test]
+	async fn test_limits() {
+		let mut cfg = Config::new();
+		let mut limits = Limits::new();
+		limits.max_header_count(2).max_header_bytes(128).max_uri_length(16);
+		cfg.limits = limits;
+
+		let parse_with = |raw: String| {
+			let cfg = cfg.clone();
+			return async move {
+				let mut stream = raw.as_bytes();
+				let mut msg = Message::new();
+				return msg.from(&mut stream, &cfg).await;
+			};
+		};
+		assert!(parse_with("GET /0123456789abcde HTTP/1.1\r\nA: 1\r\nB: 2\r\n\r\n".to_string()).await.is_none());
+
+		let err = parse_with("GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\nC: 3\r\n\r\n".to_string()).await.unwrap();
+		assert_eq!(format!("{:?}", err), "too many headers");
+		assert!(err.statuscode() == StatusCode::RequestHeaderFieldsTooLarge);
+
+		let err = parse_with(format!("GET / HTTP/1.1\r\nA: {}\r\n\r\n", "a".repeat(128))).await.unwrap();
+		assert_eq!(format!("{:?}", err), "headers too large");
+		assert!(err.statuscode() == StatusCode::RequestHeaderFieldsTooLarge);
+
+		let err = parse_with("GET /0123456789abcdef HTTP/1.1\r\n\r\n".to_string()).await.unwrap();
+		assert_eq!(format!("{:?}", err), "uri too long");
+		assert!(err.statuscode() == StatusCode::URITooLong);
+	}
+
+	#[tokio::test]
+	async fn test_trim_ows() {
+		let raw = "GET / HTTP/1.1\r\nX-Nbsp: \t\u{a0}spk\u{a0} \t\r\nX-Empty: \t \r\nX-Vt: \x0bv\r\n\r\n";
+		let (msg, err) = parse(raw.as_bytes()).await;
+		assert!(err.is_none());
+		let href = msg.headers.as_ref().unwrap();
+		assert_eq!(href.get("x-nbsp").unwrap(), "\u{a0}spk\u{a0}");
+		assert_eq!(href.get("x-empty").unwrap(), "");
+		assert_eq!(href.get_bytes("x-vt").unwrap(), b"\x0bv");
+	}
+
+	#[tokio::test]
+	async fn test_truncated_body() {
+		let (msg, err) = parse(b"POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\nHello").await;
+		assert_eq!(err.unwrap(), "body shorter than announced");
+		assert_eq!(msg.body.as_ref().unwrap().as_ref(), b"Hello");
+
+		let mut stream: &[u8] = b"POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\n";
+		let mut msg = Message::new();
+		let err = msg.from(&mut stream, &Config::new()).await.unwrap();
+		assert_eq!(err.kind(), ParseErrorKind::TruncatedBody);
+		assert!(err.is_protocol() && !err.is_empty());
+
+		// a chunk cut short is just as truncated
+		let (_, err) = parse(&[CHUNKED_HEAD, b"a\r\nHello"].concat()).await;
+		assert_eq!(err.unwrap(), "body shorter than announced");
+	}
+
+	#[tokio::test]
+	async fn test_chunk_size_line() {
+		for line in [&b" a"[..], b"a ", b"\ta", b"+a", b"0x1", b"ffffffffffffffffff"] {
+			let raw = [CHUNKED_HEAD, line, b"\r\n0123456789\r\n0\r\n\r\n"].concat();
+			let (_, err) = parse(&raw).await;
+			assert_eq!(err.unwrap(), "malformed chunk size", "{:?}", String::from_utf8_lossy(line));
+		}
+		let (msg, err) = parse(&[CHUNKED_HEAD, b"A\r\n0123456789\r\n0\r\n\r\n"].concat()).await;
+		assert!(err.is_none());
+		assert_eq!(msg.bodylen, Some(10));
+
+		// leading zeros do not count, a size line is otherwise cut off after 16 digits
+		let raw = [CHUNKED_HEAD, &b"0".repeat(100)[..], b"a\r\n0123456789\r\n0000\r\n\r\n"].concat();
+		let (msg, err) = parse(&raw).await;
+		assert!(err.is_none());
+		assert_eq!(msg.bodylen, Some(10));
+		let raw = [CHUNKED_HEAD, &b"1".repeat(17)[..], b"\r\n"].concat();
+		let (_, err) = parse(&raw).await;
+		assert_eq!(err.unwrap(), "malformed chunk size");
+		let mut stream = CountingReader { data: &[CHUNKED_HEAD, &b"12345678".repeat(4096)[..]].concat(), step: usize::MAX, reads: 0 };
+		let mut msg = Message::new();
+		let err = msg.from(&mut stream, &Config::new()).await.unwrap();
+		assert_eq!(format!("{:?}", err), "malformed chunk size");
+		assert!(stream.data.len() > 8 * 4096 - 2 * MESSAGE_BUFFER_SIZE);
+
+		// a size that overflows once added to what was read is too large, not wrapped around
+		let raw = [CHUNKED_HEAD, b"1\r\na\r\nffffffffffffffff\r\n", &b"x".repeat(5000)[..]].concat();
+		let mut stream = raw.as_slice();
+		let mut msg = Message::new();
+		assert!(msg.read_headers(&mut stream, &Config::new()).await.is_none());
+		let err = msg.read_body_limited(&mut stream, 100).await.unwrap();
+		assert_eq!(format!("{:?}", err), "body too large");
+		assert!(err.statuscode() == StatusCode::PayloadTooLarge);
+		assert_eq!(msg.body.as_ref().unwrap().len(), 1);
+	}
+
+	#[tokio::test]
+	async fn test_start_line_check() {
+		let mut cfg = Config::new();
+		cfg.start_line_check = Some(Arc::new(|method, _, _| {
+			return if method == "TRACE" { Some(StatusCode::MethodNotAllowed) } else { None };
+		}));
+
+		let raw = format!("TRACE / HTTP/1.1\r\n{}\r\n", "X-Padding: 0123456789\r\n".repeat(100));
+		let mut stream = CountingReader { data: raw.as_bytes(), step: 32, reads: 0 };
+		let mut msg = Message::new();
+		let err = msg.from(&mut stream, &cfg).await.unwrap();
+		assert_eq!(err.kind(), ParseErrorKind::Rejected(StatusCode::MethodNotAllowed));
+		assert!(err.statuscode() == StatusCode::MethodNotAllowed);
+		// stopped within the first read, no header was looked at
+		assert_eq!(stream.reads, 1);
+		assert!(msg.headers.is_none());
+
+		let raw = raw.replacen("TRACE", "GET", 1);
+		let mut stream = raw.as_bytes();
+		assert!(Message::new().from(&mut stream, &cfg).await.is_none());
+	}
+
+	#[tokio::test]
+	async fn test_tiny_chunks() {
+		let mut raw = CHUNKED_HEAD.to_vec();
+		for i in 0..1000 {
+			raw.extend_from_slice(format!("1\r\n{}\r\n", i % 10).as_bytes());
+		}
+		raw.extend_from_slice(b"0\r\n\r\n");
+		let mut stream = CountingReader { data: raw.as_slice(), step: usize::MAX, reads: 0 };
+		let mut msg = Message::new();
+		assert!(msg.from(&mut stream, &Config::new()).await.is_none());
+		assert_eq!(msg.bodylen, Some(1000));
+		assert_eq!(&msg.body.as_ref().unwrap()[..12], b"012345678901");
+		// whole buffer fills, each holding hundreds of chunks
+		assert!(stream.reads <= raw.len() / MESSAGE_BUFFER_SIZE + 2, "{} reads for {} bytes", stream.reads, raw.len());
+	}
+
+	#[tokio::test]
+	async fn test_unknown_transfer_coding() {
+		for te in ["gzip", "gzip, chunked", "chunked, gzip", "chunked, chunked", "chunked\r\nTransfer-Encoding: gzip"] {
+			let raw = format!("POST / HTTP/1.1\r\nTransfer-Encoding: {}\r\n\r\n4\r\nspk!\r\n0\r\n\r\n", te);
+			let (_, err) = parse(raw.as_bytes()).await;
+			assert_eq!(err.unwrap(), "unknown transfer coding", "{}", te);
+		}
+		let (msg, err) = parse(&[b"POST / HTTP/1.1\r\nTransfer-Encoding: Chunked\r\n\r\n", &b"4\r\nspk!\r\n0\r\n\r\n"[..]].concat()).await;
+		assert!(err.is_none());
+		assert_eq!(msg.bodylen, Some(4));
+
+		// recognized codings ending in chunked are framed by it, the others are still rejected
+		for (te, ok) in [("gzip, chunked", true), ("GZIP,chunked", true), ("br, chunked", false), ("gzip, chunked, chunked", false), ("gzip", false)] {
+			let raw = format!("POST / HTTP/1.1\r\nTransfer-Encoding: {}\r\n\r\n4\r\nspk!\r\n0\r\n\r\n", te);
+			let mut stream = raw.as_bytes();
+			let mut msg = Message::new();
+			msg.set_transfer_codings(vec!["gzip".to_string()]);
+			assert!(msg.read_headers(&mut stream, &Config::new()).await.is_none());
+			let err = msg.read_body(&mut stream).await;
+			assert_eq!(err.is_none(), ok, "{}", te);
+			if ok {
+				assert_eq!(msg.body.as_ref().unwrap().as_ref(), b"spk!");
+			}
+		}
+
+		// neither framing wins over the other, the request may be smuggling one past a proxy
+		for te in ["chunked", "gzip, chunked"] {
+			let raw = format!("POST / HTTP/1.1\r\nTransfer-Encoding: {}\r\nContent-Length: 4\r\n\r\n4\r\nspk!\r\n0\r\n\r\n", te);
+			let mut stream = raw.as_bytes();
+			let mut msg = Message::new();
+			msg.set_transfer_codings(vec!["gzip".to_string()]);
+			assert!(msg.read_headers(&mut stream, &Config::new()).await.is_none());
+			let err = msg.read_body(&mut stream).await.unwrap();
+			assert_eq!(format!("{:?}", err), "content length with transfer encoding");
+			assert!(err.statuscode() == StatusCode::BadRequest);
+		}
+
+		// a response framed this way lasts until the close
+		let mut stream: &[u8] = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: gzip\r\nContent-Length: 1\r\n\r\nspk!";
+		let mut msg = Message::new();
+		let cfg = Config::new();
+		assert!(msg.read_headers_as(&mut stream, &cfg, MessageKind::Response).await.is_none());
+		assert!(msg.read_response_body(&mut stream, 1024).await.is_none());
+		assert_eq!(msg.body.as_ref().unwrap().as_ref(), b"spk!");
+	}
+
+	#[tokio::test]
+	async fn test_single_segment_request() {
+		let chunked = [CHUNKED_HEAD, b"5\r\nHello\r\n0\r\n\r\n"].concat();
+		let cases: [&[u8]; 3] = [
+			b"GET /index.html HTTP/1.1\r\nHost: spk.local\r\nAccept: */*\r\nUser-Agent: test\r\n\r\n",
+			b"POST /form HTTP/1.1\r\nHost: spk.local\r\nContent-Length: 5\r\n\r\nHello",
+			chunked.as_slice(),
+		];
+		for raw in cases {
+			let mut stream = CountingReader { data: raw, step: usize::MAX, reads: 0 };
+			let mut msg = Message::new();
+			assert!(msg.from(&mut stream, &Config::new()).await.is_none());
+			// everything was in the first read, nothing waits on a second one
+			assert_eq!(stream.reads, 1, "{}", String::from_utf8_lossy(raw));
+			assert_eq!(msg.bufremains, 0);
+		}
+	}
+}
\ No newline at end of file
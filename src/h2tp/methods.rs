@@ -0,0 +1,9 @@
+pub const GET: &str = "GET";
+pub const HEAD: &str = "HEAD";
+pub const POST: &str = "POST";
+pub const PUT: &str = "PUT";
+pub const DELETE: &str = "DELETE";
+pub const CONNECT: &str = "CONNECT";
+pub const OPTIONS: &str = "OPTIONS";
+pub const TRACE: &str = "TRACE";
+pub const PATCH: &str = "PATCH";
\ No newline at end of file
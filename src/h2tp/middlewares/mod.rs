@@ -0,0 +1,3 @@
+pub mod basic_auth;
+pub mod rate_limit;
+pub mod request_id;
\ No newline at end of file
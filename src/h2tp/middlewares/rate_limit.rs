@@ -0,0 +1,147 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use crate::h2tp::clock::{Clock, RealClock};
+use crate::h2tp::handler::{BoxedFuture, Handler};
+use crate::h2tp::headers::hns;
+use crate::h2tp::request::Request;
+use crate::h2tp::response::Response;
+use crate::h2tp::status_code::StatusCode;
+
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+struct Bucket {
+	tokens: f64,
+	updated: SystemTime,
+}
+
+struct Buckets {
+	m: HashMap<IpAddr, Bucket>,
+	pruned: SystemTime,
+}
+
+// Token bucket per client ip: `burst` requests at once, refilled by `rate` per second.
+// Requests over the limit are answered with 429 and a `Retry-After`.
+pub struct RateLimitMiddleware {
+	next: Arc<dyn Handler + Send + Sync>,
+	rate: f64,
+	burst: f64,
+	clock: Arc<dyn Clock>,
+	buckets: Mutex<Buckets>,
+}
+
+fn elapsed(from: SystemTime, to: SystemTime) -> f64 {
+	return to.duration_since(from).unwrap_or(Duration::ZERO).as_secs_f64();
+}
+
+impl RateLimitMiddleware {
+	pub fn new(next: Arc<dyn Handler + Send + Sync>, rate: f64, burst: u32) -> Self {
+		return Self {
+			next,
+			rate,
+			burst: burst.max(1) as f64,
+			clock: Arc::new(RealClock {}),
+			buckets: Mutex::new(Buckets { m: HashMap::new(), pruned: SystemTime::UNIX_EPOCH }),
+		};
+	}
+
+	pub fn clock(&mut self, clock: Arc<dyn Clock>) -> &mut Self {
+		self.clock = clock;
+		return self;
+	}
+
+	// takes a token of `ip`, or tells how many seconds until there is one
+	fn acquire(&self, ip: IpAddr) -> Result<(), u64> {
+		let now = self.clock.now();
+		let mut guard = self.buckets.lock().unwrap();
+		let buckets = &mut *guard;
+
+		// buckets that refilled completely are the same as missing ones
+		if elapsed(buckets.pruned, now) >= PRUNE_INTERVAL.as_secs_f64() {
+			let (rate, burst) = (self.rate, self.burst);
+			buckets.m.retain(|_, b| { return b.tokens + elapsed(b.updated, now) * rate < burst; });
+			buckets.pruned = now;
+		}
+
+		let bucket = buckets.m.entry(ip).or_insert(Bucket { tokens: self.burst, updated: now });
+		bucket.tokens = (bucket.tokens + elapsed(bucket.updated, now) * self.rate).min(self.burst);
+		bucket.updated = now;
+		if bucket.tokens >= 1.0 {
+			bucket.tokens -= 1.0;
+			return Ok(());
+		}
+		return Err((((1.0 - bucket.tokens) / self.rate).ceil() as u64).max(1));
+	}
+}
+
+impl Handler for RateLimitMiddleware {
+	fn handle(&self, req: Request) -> BoxedFuture {
+		match req.peer_addr() {
+			Some(addr) => {
+				match self.acquire(addr.ip()) {
+					Ok(_) => {}
+					Err(secs) => {
+						return Box::pin(async move {
+							let mut resp = Response::new();
+							resp.builder().status(StatusCode::TooManyRequests)
+								.headers().append(hns::RETRY_AFTER, secs.to_string().as_str());
+							return Ok(resp);
+						});
+					}
+				}
+			}
+			None => {}
+		}
+		return self.next.handle(req);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::Arc;
+	use std::time::{Duration, UNIX_EPOCH};
+	use crate::h2tp::clock::MockClock;
+	use crate::h2tp::FuncHandler;
+	use crate::h2tp::handler::Handler;
+	use crate::h2tp::middlewares::rate_limit::RateLimitMiddleware;
+	use crate::h2tp::request::Request;
+	use crate::h2tp::response::Response;
+
+	fn request(addr: &str) -> Request {
+		let mut req = Request::new();
+		req.set_peer_addr(addr.parse().unwrap());
+		return req;
+	}
+
+	#[tokio::test]
+	async fn test_rate_limit() {
+		let clock = Arc::new(MockClock::new(UNIX_EPOCH + Duration::from_secs(1000)));
+		let mut limiter = RateLimitMiddleware::new(Arc::new(FuncHandler::new(|_| {
+			Box::pin(async {
+				return Ok(Response::new());
+			})
+		})), 1.0, 2);
+		limiter.clock(clock.clone());
+
+		let mut codes = vec![];
+		for _ in 0..5 {
+			codes.push(limiter.handle(request("10.0.0.1:5000")).await.unwrap().statuscode());
+		}
+		assert_eq!(codes, vec![200, 200, 429, 429, 429]);
+
+		let resp = limiter.handle(request("10.0.0.1:5001")).await.unwrap();
+		assert_eq!(resp.headers().unwrap().get("retry-after").unwrap(), "1");
+		// keyed on the ip, not the port
+		assert_eq!(limiter.handle(request("10.0.0.2:5000")).await.unwrap().statuscode(), 200);
+
+		clock.advance(Duration::from_secs(1));
+		assert_eq!(limiter.handle(request("10.0.0.1:5000")).await.unwrap().statuscode(), 200);
+		assert_eq!(limiter.handle(request("10.0.0.1:5000")).await.unwrap().statuscode(), 429);
+
+		// idle clients are dropped once their bucket is full again
+		clock.advance(Duration::from_secs(120));
+		assert_eq!(limiter.handle(request("10.0.0.3:5000")).await.unwrap().statuscode(), 200);
+		assert_eq!(limiter.buckets.lock().unwrap().m.len(), 1);
+	}
+}
\ No newline at end of file
@@ -0,0 +1,137 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::Arc;
+use std::sync::atomic::AtomicU64;
+use crate::h2tp::cfg::ATOMIC_ORDERING;
+use crate::h2tp::handler::{BoxedFuture, Handler};
+use crate::h2tp::headers::hns;
+use crate::h2tp::request::Request;
+
+const MAX_INCOMING_ID_LENGTH: usize = 128;
+
+// The id of a request, found in its extensions.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RequestId(pub String);
+
+impl RequestId {
+	pub fn as_str(&self) -> &str {
+		return self.0.as_str();
+	}
+}
+
+// Gives every request an id and echoes it in the `X-Request-Id` of the response. Ids are a random
+// prefix picked once per middleware with a counter, an incoming `X-Request-Id` is kept instead
+// when `trust_incoming` is set.
+pub struct RequestIdMiddleware {
+	next: Arc<dyn Handler + Send + Sync>,
+	trust_incoming: bool,
+	prefix: u64,
+	counter: AtomicU64,
+}
+
+// ids from clients end up in logs, only short visible ASCII ones are taken over
+fn acceptable(v: &str) -> bool {
+	return !v.is_empty() && v.len() <= MAX_INCOMING_ID_LENGTH && v.bytes().all(|b| b.is_ascii_graphic());
+}
+
+impl RequestIdMiddleware {
+	pub fn new(next: Arc<dyn Handler + Send + Sync>) -> Self {
+		return Self {
+			next,
+			trust_incoming: false,
+			prefix: RandomState::new().build_hasher().finish(),
+			counter: AtomicU64::new(0),
+		};
+	}
+
+	// only for servers behind a proxy that sets or strips the header itself
+	pub fn trust_incoming(&mut self, v: bool) -> &mut Self {
+		self.trust_incoming = v;
+		return self;
+	}
+
+	pub fn generate(&self) -> RequestId {
+		let n = self.counter.fetch_add(1, ATOMIC_ORDERING);
+		return RequestId(format!("{:016x}-{:08x}", self.prefix, n));
+	}
+}
+
+impl Handler for RequestIdMiddleware {
+	fn handle(&self, mut req: Request) -> BoxedFuture {
+		let incoming = match req.headers().and_then(|h| h.get(hns::X_REQUEST_ID)) {
+			Some(v) if self.trust_incoming && acceptable(v) => {
+				Some(RequestId(v.to_string()))
+			}
+			_ => {
+				None
+			}
+		};
+		let id = incoming.unwrap_or_else(|| self.generate());
+		req.extensions_mut().insert(id.clone());
+		let fut = self.next.handle(req);
+		return Box::pin(async move {
+			let mut resp = fut.await?;
+			if !resp.has_header(hns::X_REQUEST_ID) {
+				resp.builder().headers().append(hns::X_REQUEST_ID, id.as_str());
+			}
+			return Ok(resp);
+		});
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::Arc;
+	use crate::h2tp::cfg::Config;
+	use crate::h2tp::FuncHandler;
+	use crate::h2tp::handler::Handler;
+	use crate::h2tp::middlewares::request_id::{RequestId, RequestIdMiddleware};
+	use crate::h2tp::request::Request;
+	use crate::h2tp::response::Response;
+
+	fn middleware() -> RequestIdMiddleware {
+		return RequestIdMiddleware::new(Arc::new(FuncHandler::new(|req| {
+			Box::pin(async move {
+				// the handler sees the id the response is sent with
+				let id = req.extensions().get::<RequestId>().unwrap().clone();
+				let mut resp = Response::new();
+				resp.builder().body(id.as_str().as_bytes());
+				return Ok(resp);
+			})
+		})));
+	}
+
+	async fn request(raw: &[u8]) -> Request {
+		let mut stream = raw;
+		let mut req = Request::new();
+		assert!(req.from(&mut stream, &Config::new()).await.is_none());
+		return req;
+	}
+
+	#[tokio::test]
+	async fn test_request_id() {
+		let mut mw = middleware();
+		let raw = b"GET / HTTP/1.1\r\nX-Request-Id: trace-42\r\n\r\n";
+
+		// not taken over unless trusted
+		let resp = mw.handle(request(raw).await).await.unwrap();
+		let generated = resp.headers().unwrap().get("x-request-id").unwrap().to_string();
+		assert_ne!(generated, "trace-42");
+		assert_eq!(resp.body().unwrap().as_ref(), generated.as_bytes());
+
+		mw.trust_incoming(true);
+		let resp = mw.handle(request(raw).await).await.unwrap();
+		assert_eq!(resp.headers().unwrap().get("x-request-id").unwrap(), "trace-42");
+		assert_eq!(resp.body().unwrap().as_ref(), b"trace-42");
+
+		// generated when absent, or when the incoming one is unfit for logs
+		let first = mw.handle(request(b"GET / HTTP/1.1\r\n\r\n").await).await.unwrap();
+		let first = first.headers().unwrap().get("x-request-id").unwrap().to_string();
+		let raw = format!("GET / HTTP/1.1\r\nX-Request-Id: {}\r\n\r\n", "a".repeat(129));
+		let second = mw.handle(request(raw.as_bytes()).await).await.unwrap();
+		let second = second.headers().unwrap().get("x-request-id").unwrap().to_string();
+		assert_eq!(first.len(), 25);
+		assert_ne!(first, second);
+		assert_eq!(first[..16], second[..16]);
+	}
+}
\ No newline at end of file
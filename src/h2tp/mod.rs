@@ -16,6 +16,18 @@ mod response;
 mod url;
 mod ctx;
 mod types;
+mod content_encoding;
+mod clock;
+mod middlewares;
+mod authorization;
+mod client;
+mod sse;
+mod multipart;
+mod router;
+mod vhost;
+mod static_handler;
+#[cfg(test)]
+mod testing;
 
 pub async fn shutdown(handler: &Arc<Mutex<server::ShutdownHandler>>, timout: u64) {
 	let mut guard = handler.lock().await;
@@ -30,6 +42,10 @@ pub fn server() -> server::Server {
 	return server::Server::new();
 }
 
+pub fn server_builder() -> server::ServerBuilder {
+	return server::ServerBuilder::new();
+}
+
 pub use handler::FuncHandler;
 
 #[macro_export]
@@ -0,0 +1,264 @@
+use crate::h2tp::cfg::Config;
+use crate::h2tp::headers::{Headers, hns, mime};
+use crate::h2tp::message::{ParseError, ParseErrorKind};
+use crate::h2tp::utils::quoted_string;
+
+const NOT_MULTIPART: &str = "not a multipart body";
+const MALFORMED_MULTIPART: &str = "malformed multipart body";
+const TOO_MANY_PARTS: &str = "too many multipart parts";
+const PART_TOO_LARGE: &str = "multipart part too large";
+const FILENAME_TOO_LONG: &str = "multipart filename too long";
+
+// One part of a `multipart/form-data` body (RFC 7578).
+pub struct Part {
+	headers: Headers,
+	name: Option<String>,
+	filename: Option<String>,
+	body: Vec<u8>,
+}
+
+impl Part {
+	pub fn headers(&self) -> &Headers {
+		return &self.headers;
+	}
+
+	// `name` of the part's Content-Disposition, the form field it belongs to
+	pub fn name(&self) -> Option<&str> {
+		return self.name.as_deref();
+	}
+
+	pub fn filename(&self) -> Option<&str> {
+		return self.filename.as_deref();
+	}
+
+	pub fn body(&self) -> &[u8] {
+		return self.body.as_slice();
+	}
+}
+
+// `boundary` parameter of a multipart Content-Type
+pub fn boundary(content_type: &str) -> Option<String> {
+	if !content_type.trim_start().to_ascii_lowercase().starts_with("multipart/") {
+		return None;
+	}
+	let (_, v) = quoted_string::params(content_type).into_iter().find(|(k, _)| k == "boundary")?;
+	return if v.is_empty() { None } else { Some(v) };
+}
+
+fn find(hay: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+	if from > hay.len() {
+		return None;
+	}
+	return hay[from..].windows(needle.len()).position(|w| w == needle).map(|idx| idx + from);
+}
+
+// `name` and `filename` parameters of a Content-Disposition value
+fn disposition(v: &str) -> (Option<String>, Option<String>) {
+	let mut name = None;
+	let mut filename = None;
+	for (k, v) in quoted_string::params(v) {
+		match k.as_str() {
+			"name" => {
+				name = Some(v);
+			}
+			"filename" => {
+				filename = Some(v);
+			}
+			_ => {}
+		}
+	}
+	return (name, filename);
+}
+
+// Splits a multipart body into its parts. Parse stops at the first part past
+// `Config::max_multipart_parts` or larger than `Config::max_multipart_part_size` with 413,
+// a filename longer than `Config::max_multipart_filename_length` is a 400.
+pub fn parse(body: &[u8], boundary: &str, cfg: &Config) -> Result<Vec<Part>, ParseError> {
+	let delimiter = format!("--{}", boundary).into_bytes();
+	let mut parts = vec![];
+	// the preamble before the first delimiter is ignored
+	let mut pos = match find(body, delimiter.as_slice(), 0) {
+		Some(idx) => {
+			idx + delimiter.len()
+		}
+		None => {
+			return Err(ParseError::ue(MALFORMED_MULTIPART));
+		}
+	};
+	let delimiter = [b"\r\n", delimiter.as_slice()].concat();
+	loop {
+		let rest = &body[pos..];
+		if rest.starts_with(b"--") {
+			return Ok(parts);
+		}
+		if !rest.starts_with(b"\r\n") {
+			return Err(ParseError::ue(MALFORMED_MULTIPART));
+		}
+		if parts.len() >= cfg.max_multipart_parts {
+			return Err(ParseError::uekind(TOO_MANY_PARTS, ParseErrorKind::TooLarge));
+		}
+		pos += 2;
+
+		let mut headers = Headers::new();
+		let mut name = None;
+		let mut filename = None;
+		loop {
+			let end = match find(body, b"\r\n", pos) {
+				Some(idx) => {
+					idx
+				}
+				None => {
+					return Err(ParseError::ue(MALFORMED_MULTIPART));
+				}
+			};
+			let line = &body[pos..end];
+			pos = end + 2;
+			if line.is_empty() {
+				break;
+			}
+			let line = String::from_utf8_lossy(line);
+			let (k, v) = match line.split_once(':') {
+				Some((k, v)) => {
+					(k.trim(), v.trim())
+				}
+				None => {
+					return Err(ParseError::ue(MALFORMED_MULTIPART));
+				}
+			};
+			if k.eq_ignore_ascii_case(hns::CONTENT_DISPOSITION) {
+				(name, filename) = disposition(v);
+			}
+			headers.builder().append(k, v);
+		}
+		match filename.as_ref() {
+			Some(v) if v.len() > cfg.max_multipart_filename_length => {
+				return Err(ParseError::ue(FILENAME_TOO_LONG));
+			}
+			_ => {}
+		}
+
+		// only as far as the size limit is searched for the end of a part
+		let window = body.len().min(pos.saturating_add(cfg.max_multipart_part_size).saturating_add(delimiter.len()));
+		let end = match find(&body[..window], delimiter.as_slice(), pos) {
+			Some(idx) => {
+				idx
+			}
+			None => {
+				if window < body.len() {
+					return Err(ParseError::uekind(PART_TOO_LARGE, ParseErrorKind::TooLarge));
+				}
+				return Err(ParseError::ue(MALFORMED_MULTIPART));
+			}
+		};
+		if end - pos > cfg.max_multipart_part_size {
+			return Err(ParseError::uekind(PART_TOO_LARGE, ParseErrorKind::TooLarge));
+		}
+		parts.push(Part { headers, name, filename, body: body[pos..end].to_vec() });
+		pos = end + delimiter.len();
+	}
+}
+
+// parts of a request body sent as `multipart/form-data`
+pub fn form_data(headers: Option<&Headers>, body: &[u8], cfg: &Config) -> Result<Vec<Part>, ParseError> {
+	let ct = headers.and_then(|h| h.content_type()).unwrap_or("");
+	if !ct.to_ascii_lowercase().starts_with(mime::MULTIPART_FORM) {
+		return Err(ParseError::uekind(NOT_MULTIPART, ParseErrorKind::UnsupportedMediaType));
+	}
+	return match boundary(ct) {
+		Some(b) => {
+			parse(body, &b, cfg)
+		}
+		None => {
+			Err(ParseError::ue(MALFORMED_MULTIPART))
+		}
+	};
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::h2tp::cfg::Config;
+	use crate::h2tp::message::ParseErrorKind;
+	use crate::h2tp::multipart::{boundary, parse};
+	use crate::h2tp::request::Request;
+	use crate::h2tp::status_code::StatusCode;
+
+	fn form(parts: &[(&str, Option<&str>, &str)]) -> String {
+		let mut body = String::from("preamble\r\n");
+		for (name, filename, data) in parts {
+			body.push_str("--spk\r\n");
+			match filename {
+				Some(f) => {
+					body.push_str(&format!("Content-Disposition: form-data; name=\"{}\"; filename=\"{}\"\r\n", name, f));
+					body.push_str("Content-Type: text/plain\r\n");
+				}
+				None => {
+					body.push_str(&format!("Content-Disposition: form-data; name=\"{}\"\r\n", name));
+				}
+			}
+			body.push_str(&format!("\r\n{}\r\n", data));
+		}
+		body.push_str("--spk--\r\n");
+		return body;
+	}
+
+	#[tokio::test]
+	async fn test_form_data() {
+		let body = form(&[("a", None, "1"), ("f", Some("x.txt"), "line\r\n--sp")]);
+		let raw = format!(
+			"POST / HTTP/1.1\r\nContent-Type: multipart/form-data; boundary=\"spk\"\r\nContent-Length: {}\r\n\r\n{}",
+			body.len(), body,
+		);
+		let mut stream = raw.as_bytes();
+		let mut req = Request::new();
+		let cfg = Config::new();
+		assert!(req.from(&mut stream, &cfg).await.is_none());
+		let parts = req.multipart(&cfg).unwrap();
+		assert_eq!(parts.len(), 2);
+		assert_eq!(parts[0].name(), Some("a"));
+		assert!(parts[0].filename().is_none());
+		assert_eq!(parts[0].body(), b"1");
+		assert_eq!(parts[1].filename(), Some("x.txt"));
+		assert_eq!(parts[1].headers().content_type(), Some("text/plain"));
+		assert_eq!(parts[1].body(), b"line\r\n--sp");
+
+		assert_eq!(boundary("multipart/form-data; charset=utf-8; boundary=b1"), Some("b1".to_string()));
+		assert_eq!(boundary("multipart/mixed; boundary=\"a;b\""), Some("a;b".to_string()));
+		assert!(boundary("text/plain; boundary=b1").is_none());
+	}
+
+	#[test]
+	fn test_part_count() {
+		let mut cfg = Config::new();
+		cfg.max_multipart_parts = 3;
+		let parts = vec![("a", None, "1"); 3];
+		assert_eq!(parse(form(&parts).as_bytes(), "spk", &cfg).unwrap().len(), 3);
+
+		let parts = vec![("a", None, "1"); 1000];
+		let err = parse(form(&parts).as_bytes(), "spk", &cfg).err().unwrap();
+		assert_eq!(format!("{:?}", err), "too many multipart parts");
+		assert!(err.statuscode() == StatusCode::PayloadTooLarge);
+	}
+
+	#[test]
+	fn test_part_size() {
+		let mut cfg = Config::new();
+		cfg.max_multipart_part_size = 16;
+		let data = "x".repeat(16);
+		assert!(parse(form(&[("a", None, data.as_str())]).as_bytes(), "spk", &cfg).is_ok());
+
+		let data = "x".repeat(1 << 16);
+		let err = parse(form(&[("a", None, "1"), ("b", None, data.as_str())]).as_bytes(), "spk", &cfg).err().unwrap();
+		assert_eq!(err.kind(), ParseErrorKind::TooLarge);
+		assert_eq!(format!("{:?}", err), "multipart part too large");
+
+		// a part never closed by a delimiter is malformed, not too large
+		let open = "--spk\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\n0123";
+		let err = parse(open.as_bytes(), "spk", &cfg).err().unwrap();
+		assert_eq!(err.kind(), ParseErrorKind::BadRequest);
+
+		cfg.max_multipart_filename_length = 8;
+		let err = parse(form(&[("f", Some("much-too-long.txt"), "1")]).as_bytes(), "spk", &cfg).err().unwrap();
+		assert_eq!(format!("{:?}", err), "multipart filename too long");
+		assert!(err.statuscode() == StatusCode::BadRequest);
+	}
+}
\ No newline at end of file
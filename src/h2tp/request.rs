@@ -1,12 +1,34 @@
+use std::any::Any;
 use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::Path;
+use std::sync::Arc;
 use bytes::BytesMut;
-use crate::h2tp::{headers, types};
-use crate::h2tp::headers::Headers;
-use crate::h2tp::message::{Message, ParseError};
+use tokio::io::AsyncWriteExt;
+use crate::h2tp::{content_encoding, headers, methods, multipart, types};
+use crate::h2tp::authorization::Authorization;
+use crate::h2tp::cfg::Config;
+use crate::h2tp::headers::{Headers, hns};
+use crate::h2tp::message::{Message, ParseError, ReadBuffer};
 use crate::h2tp::url::Url;
+use crate::h2tp::utils::extensions::Extensions;
+use crate::h2tp::utils::forwarded::{self, ProxyHeader};
+use crate::h2tp::utils::range::{self, ByteRange, RangeError};
+use crate::h2tp::utils::uri::Uri;
+
+const BODY_NOT_ALLOWED: &str = "body not allowed";
+const ABSOLUTE_FORM_NOT_ALLOWED: &str = "absolute-form target not allowed";
+const MISSING_HOST: &str = "missing or repeated host";
+const TRAILING_BYTES: &str = "bytes past the last request";
+// longer paths and header values are cut short in `Debug` output
+const DEBUG_VALUE_CHARS: usize = 32;
 
 pub struct Request {
 	msg: Message,
+	uri: Uri,
+	peer_addr: Option<SocketAddr>,
+	state: Option<types::State>,
+	extensions: Extensions,
 }
 
 pub struct Builder<'req> {
@@ -27,6 +49,7 @@ impl<'req> Builder<'req> {
 
 	pub fn rawpath(&mut self, path: &str) -> &mut Self {
 		self.req.msg.startline.1 = path.to_string();
+		self.req.uri = Uri::parse(path);
 		return self;
 	}
 
@@ -34,21 +57,63 @@ impl<'req> Builder<'req> {
 		let pathref = &mut self.req.msg.startline.1;
 		pathref.clear();
 		url.to(pathref).unwrap();
+		self.req.uri = Uri::parse(pathref);
+		return self;
+	}
+
+	pub fn version(&mut self, version: &str) -> &mut Self {
+		self.req.msg.startline.2 = version.to_string();
 		return self;
 	}
 
 	pub fn headers(&mut self) -> headers::Builder {
 		return self.req.msg.headers_builder();
 	}
+
+	pub fn body(&mut self, v: &[u8]) -> &mut Self {
+		self.req.msg.body = Some(BytesMut::from(v));
+		self.req.msg.headers_builder().content_length(v.len());
+		return self;
+	}
 }
 
 
+// `v` cut to its first `DEBUG_VALUE_CHARS` characters
+fn debug_value(v: &str) -> String {
+	return match v.char_indices().nth(DEBUG_VALUE_CHARS) {
+		Some((idx, _)) => {
+			format!("{}...", &v[..idx])
+		}
+		None => {
+			v.to_string()
+		}
+	};
+}
+
 impl fmt::Debug for Request {
 	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-		write!(f, "Request <{} {} {} @ {:p}>",
-			   self.method(), self.path(), self.version(),
-			   self,
-		)
+		write!(f, "Request <{} {} {}, headers: {{", self.method(), debug_value(self.path()), self.version())?;
+		let mut first = true;
+		let mut result = Ok(());
+		match self.headers() {
+			Some(href) => {
+				href.each_cased(|k, v| {
+					let sep = if first { "" } else { ", " };
+					first = false;
+					result = result.and_then(|_| write!(f, "{}{}: {}", sep, k, debug_value(&String::from_utf8_lossy(v.as_bytes()))));
+				});
+			}
+			None => {}
+		}
+		result?;
+		return match self.body_len() {
+			Some(n) => {
+				write!(f, "}}, body: {} bytes>", n)
+			}
+			None => {
+				write!(f, "}}, body: none>")
+			}
+		};
 	}
 }
 
@@ -56,15 +121,206 @@ impl Request {
 	pub fn new() -> Self {
 		return Self {
 			msg: Message::new(),
+			uri: Uri::new(),
+			peer_addr: None,
+			state: None,
+			extensions: Extensions::new(),
 		};
 	}
 
 	pub fn clear(&mut self) {
 		self.msg.clear();
+		self.uri = Uri::new();
+		self.extensions.clear();
+	}
+
+	pub async fn from<R: types::AsyncReader>(&mut self, stream: &mut R, cfg: &Config) -> Option<ParseError> {
+		match self.read_head(stream, cfg).await {
+			Some(e) => {
+				return Some(e);
+			}
+			None => {}
+		}
+		return self.read_body(stream, cfg).await;
+	}
+
+	// reads the request line and the headers, the body is left in the stream
+	pub async fn read_head<R: types::AsyncReader>(&mut self, stream: &mut R, cfg: &Config) -> Option<ParseError> {
+		match self.msg.read_headers(stream, cfg).await {
+			Some(e) => {
+				return Some(e);
+			}
+			None => {}
+		}
+		if self.method() == methods::CONNECT {
+			self.uri = Uri::from_authority(self.msg.startline.1.as_str());
+		} else {
+			self.uri = Uri::parse(self.msg.startline.1.as_str());
+			// absolute-form is meant for proxies
+			if self.uri.is_absolute() && !cfg.allow_absolute_form {
+				return Some(ParseError::ue(ABSOLUTE_FORM_NOT_ALLOWED));
+			}
+		}
+
+		if cfg.require_host && self.version() == "HTTP/1.1" {
+			let hosts = self.headers().and_then(|h| h.get_all(hns::HOST)).map_or(0, |v| v.len());
+			// an absolute-form target names the host itself, it may go without the header but
+			// not with two of them
+			if hosts > 1 || (hosts == 0 && !self.uri.is_absolute()) {
+				return Some(ParseError::ue(MISSING_HOST));
+			}
+		}
+
+		if !cfg.allow_body_on_get && self.msg.expects_body() {
+			let method = self.method();
+			if method == methods::GET || method == methods::HEAD {
+				return Some(ParseError::ue(BODY_NOT_ALLOWED));
+			}
+		}
+		return None;
 	}
 
-	pub async fn from<R: types::AsyncReader>(&mut self, stream: &mut R) -> Option<ParseError> {
-		return self.msg.from(stream).await;
+	// Reads over a body nobody is going to look at, so the next request on the connection starts
+	// at the right place. Bodies larger than `limit` are not worth it, the connection should be closed.
+	pub async fn drain_body<R: types::AsyncReader>(&mut self, stream: &mut R, limit: usize) -> Option<ParseError> {
+		let err = self.msg.read_body_limited(stream, limit).await;
+		self.msg.bodylen = None;
+		match self.msg.body.as_mut() {
+			Some(bodyref) => {
+				bodyref.clear();
+			}
+			None => {}
+		}
+		return err;
+	}
+
+	pub async fn read_body<R: types::AsyncReader>(&mut self, stream: &mut R, cfg: &Config) -> Option<ParseError> {
+		self.msg.set_body_pool(cfg.body_pool.clone());
+		self.msg.set_chunked_body_timeout(cfg.chunked_body_timeout.map(|d| (Arc::clone(&cfg.clock), d)));
+		self.msg.set_body_spill(cfg.spill_body_threshold.map(|n| (cfg.spill_dir.clone(), n)));
+		self.msg.set_transfer_codings(cfg.transfer_codings.clone());
+		self.msg.set_trailer_limits(&cfg.limits);
+		match self.msg.read_body_limited(stream, cfg.limits.max_body_bytes).await {
+			Some(e) => {
+				return Some(e);
+			}
+			None => {}
+		}
+
+		match self.undo_transfer_codings(cfg) {
+			Some(e) => {
+				return Some(e);
+			}
+			None => {}
+		}
+		if cfg.decode_request_body {
+			return self.decode_body(cfg);
+		}
+		return None;
+	}
+
+	// `read_body` for the last request of a connection: bytes past its declared length cannot be a
+	// next request, they may be one smuggled past a proxy and are rejected with 400.
+	pub async fn read_body_exact<R: types::AsyncReader>(&mut self, stream: &mut R, cfg: &Config) -> Option<ParseError> {
+		match self.read_body(stream, cfg).await {
+			Some(e) => {
+				return Some(e);
+			}
+			None => {}
+		}
+		// a client that keeps its side open is given `trailing_bytes_wait` to send more
+		let trailing = tokio::select! {
+			result = self.msg.has_trailing_bytes(stream) => {
+				result
+			}
+			_ = cfg.clock.sleep(cfg.trailing_bytes_wait) => {
+				Ok(false)
+			}
+		};
+		return match trailing {
+			Ok(true) => {
+				Some(ParseError::ue(TRAILING_BYTES))
+			}
+			Ok(false) => {
+				None
+			}
+			Err(e) => {
+				Some(e)
+			}
+		};
+	}
+
+	// the codings under a final chunked are hop-by-hop, the handler gets the body without them
+	fn undo_transfer_codings(&mut self, cfg: &Config) -> Option<ParseError> {
+		let codings = match self.headers() {
+			Some(href) => {
+				href.transfer_codings()
+			}
+			None => {
+				return None;
+			}
+		};
+		if codings.len() < 2 {
+			return None;
+		}
+		let body = match self.msg.body.as_ref() {
+			Some(v) => {
+				v
+			}
+			None => {
+				return None;
+			}
+		};
+		match content_encoding::decode_body(&codings[..codings.len() - 1].join(","), body, cfg.max_decoded_body_size) {
+			Ok(Some(decoded)) => {
+				self.msg.bodylen = Some(decoded.len());
+				self.msg.body = Some(decoded);
+				self.msg.headers_builder().reset(hns::TRANSFER_ENCODING, "chunked");
+			}
+			Ok(None) => {}
+			Err(e) => {
+				return Some(e);
+			}
+		}
+		return None;
+	}
+
+	fn decode_body(&mut self, cfg: &Config) -> Option<ParseError> {
+		let encoding = match self.headers() {
+			Some(href) => {
+				match href.get(hns::CONTENT_ENCODING) {
+					Some(v) => {
+						v.to_string()
+					}
+					None => {
+						return None;
+					}
+				}
+			}
+			None => {
+				return None;
+			}
+		};
+		let body = match self.msg.body.as_ref() {
+			Some(v) => {
+				v
+			}
+			None => {
+				return None;
+			}
+		};
+		match content_encoding::decode_body(encoding.as_str(), body, cfg.max_decoded_body_size) {
+			Ok(Some(decoded)) => {
+				self.msg.bodylen = Some(decoded.len());
+				self.msg.body = Some(decoded);
+				self.msg.headers_builder().remove(hns::CONTENT_ENCODING);
+			}
+			Ok(None) => {}
+			Err(e) => {
+				return Some(e);
+			}
+		}
+		return None;
 	}
 
 	pub fn method(&self) -> &str {
@@ -79,15 +335,544 @@ impl Request {
 		return self.msg.startline.2.as_str();
 	}
 
+	// the parsed request target, `path` is the raw one
+	pub fn uri(&self) -> &Uri {
+		return &self.uri;
+	}
+
 	pub fn headers(&self) -> Option<&Headers> {
 		return self.msg.headers.as_ref();
 	}
 
+	// `None` for a body that went to a file, see `body_path`
 	pub fn body(&self) -> Option<&BytesMut> {
 		return self.msg.body.as_ref();
 	}
 
+	// the file a body larger than `Config::spill_body_threshold` was written to, it is removed
+	// with the request
+	pub fn body_path(&self) -> Option<&Path> {
+		return self.msg.body_path();
+	}
+
+	// The host the request is for: the authority of an absolute-form target, which overrides any
+	// `Host` (RFC 7230 5.4), or else the `Host` header.
+	pub fn authority(&self) -> Option<&str> {
+		return match self.uri.authority() {
+			Some(v) => {
+				Some(v)
+			}
+			None => {
+				self.headers().and_then(|h| h.get(hns::HOST))
+			}
+		};
+	}
+
+	pub fn authorization(&self) -> Option<Authorization> {
+		return self.headers().and_then(|h| h.get(hns::AUTHORIZATION)).and_then(Authorization::parse);
+	}
+
+	// The `Range` of a GET or HEAD against a representation of `total_len` bytes. It means nothing
+	// for other methods (RFC 7233 3.1), they get `None` as if there was none.
+	pub fn ranges(&self, total_len: u64) -> Option<Result<Vec<ByteRange>, RangeError>> {
+		if self.method() != methods::GET && self.method() != methods::HEAD {
+			return None;
+		}
+		return self.headers().and_then(|h| h.get(hns::RANGE)).map(|v| range::parse_range(v, total_len));
+	}
+
+	// the parts of a `multipart/form-data` body, see `multipart::parse` for the limits applied
+	pub fn multipart(&self, cfg: &Config) -> Result<Vec<multipart::Part>, ParseError> {
+		let body = self.body().map_or(&b""[..], |b| b.as_ref());
+		return multipart::form_data(self.headers(), body, cfg);
+	}
+
+	// address of the client on the other end of the connection
+	pub fn peer_addr(&self) -> Option<SocketAddr> {
+		return self.peer_addr;
+	}
+
+	// The address of the client. Behind proxies `trust_proxy` takes the first hop of `Forwarded`
+	// or `X-Forwarded-For`, which anyone can send, see `client_ip_via` to trust known proxies only.
+	pub fn client_ip(&self, trust_proxy: bool) -> IpAddr {
+		let peer = self.peer_addr.map_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED), |addr| addr.ip());
+		if !trust_proxy {
+			return peer;
+		}
+		return match self.headers() {
+			Some(href) => {
+				let mut hops = forwarded::hops(href, ProxyHeader::Forwarded);
+				if hops.is_empty() {
+					hops = forwarded::hops(href, ProxyHeader::XForwardedFor);
+				}
+				hops.into_iter().flatten().next().unwrap_or(peer)
+			}
+			None => {
+				peer
+			}
+		};
+	}
+
+	// The closest hop not in `trusted`. Hops are taken from the end of `header`, the one the
+	// trusted proxies write, each one must have been added by a proxy in `trusted`, so what a
+	// client sends in front of them, or in the other header, can not win.
+	pub fn client_ip_via(&self, trusted: &[IpAddr], header: ProxyHeader) -> IpAddr {
+		let mut client = self.client_ip(false);
+		if !trusted.contains(&client) {
+			return client;
+		}
+		let hops = match self.headers() {
+			Some(href) => {
+				forwarded::hops(href, header)
+			}
+			None => {
+				vec![]
+			}
+		};
+		for hop in hops.into_iter().rev() {
+			match hop {
+				Some(ip) => {
+					client = ip;
+					if !trusted.contains(&ip) {
+						break;
+					}
+				}
+				None => {
+					break;
+				}
+			}
+		}
+		return client;
+	}
+
+	pub fn set_peer_addr(&mut self, addr: SocketAddr) {
+		self.peer_addr = Some(addr);
+	}
+
+	pub fn set_state(&mut self, state: Option<types::State>) {
+		self.state = state;
+	}
+
+	pub fn state<T: Any + Send + Sync>(&self) -> Option<Arc<T>> {
+		return match self.state.as_ref() {
+			Some(sref) => {
+				Arc::clone(sref).downcast::<T>().ok()
+			}
+			None => {
+				None
+			}
+		};
+	}
+
+	pub fn take_read_buffer(&mut self) -> Option<ReadBuffer> {
+		return self.msg.take_read_buffer();
+	}
+
+	pub fn set_read_buffer(&mut self, rb: ReadBuffer) {
+		self.msg.set_read_buffer(rb);
+	}
+
+	pub async fn fill_buffer<R: types::AsyncReader>(&mut self, stream: &mut R) -> Option<ParseError> {
+		return self.msg.fill_buffer(stream).await;
+	}
+
+	pub fn extensions(&self) -> &Extensions {
+		return &self.extensions;
+	}
+
+	pub fn extensions_mut(&mut self) -> &mut Extensions {
+		return &mut self.extensions;
+	}
+
+	// the received header block, only kept when `Config::keep_raw_headers` is set
+	pub fn raw_headers(&self) -> Option<&[u8]> {
+		return self.msg.rawheaders.as_deref();
+	}
+
+	pub fn body_len(&self) -> Option<usize> {
+		return self.msg.bodylen;
+	}
+
 	pub fn builder(&mut self) -> Builder {
 		return Builder::new(self);
 	}
+
+	// the request as a client sends it, HTTP/1.1 unless a version was set
+	pub async fn write_to<W: types::AsyncWriter>(&self, w: &mut W) -> std::io::Result<()> {
+		let version = if self.version().is_empty() { "HTTP/1.1" } else { self.version() };
+		let mut buf: Vec<u8> = Vec::with_capacity(256);
+		buf.extend_from_slice(format!("{} {} {}\r\n", self.method(), self.path(), version).as_bytes());
+		match self.headers() {
+			Some(href) => {
+				href.each_cased(|k, v| {
+					buf.extend_from_slice(k.as_bytes());
+					buf.extend_from_slice(b": ");
+					buf.extend_from_slice(v.as_bytes());
+					buf.extend_from_slice(b"\r\n");
+				});
+			}
+			None => {}
+		}
+		buf.extend_from_slice(b"\r\n");
+		match self.body() {
+			Some(bodyref) => {
+				buf.extend_from_slice(bodyref.as_ref());
+			}
+			None => {}
+		}
+		w.write_all(buf.as_slice()).await?;
+		return w.flush().await;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::net::IpAddr;
+	use std::sync::Arc;
+	use std::time::Duration;
+	use tokio::io::{AsyncReadExt, AsyncWriteExt};
+	use crate::h2tp::cfg::Config;
+	use crate::h2tp::request::Request;
+	use crate::h2tp::status_code::StatusCode;
+	use crate::h2tp::utils::buffer_pool::BufferPool;
+	use crate::h2tp::utils::forwarded::ProxyHeader;
+
+	#[tokio::test]
+	async fn test_chunked_body_len() {
+		let mut stream: &[u8] = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nHello\r\nc\r\n, Chunked Wo\r\n3\r\nrld\r\n0\r\n\r\n";
+		let mut req = Request::new();
+		assert!(req.from(&mut stream, &Config::new()).await.is_none());
+		assert_eq!(req.body_len(), Some(20));
+		assert_eq!(req.body().unwrap().as_ref(), b"Hello, Chunked World");
+	}
+
+	#[cfg(feature = "gzip")]
+	#[tokio::test]
+	async fn test_transfer_codings() {
+		use std::io::Write;
+		use crate::h2tp::headers::hns;
+
+		let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+		encoder.write_all(b"Hello Gzip").unwrap();
+		let gzipped = encoder.finish().unwrap();
+		let mut raw = format!("POST / HTTP/1.1\r\nTransfer-Encoding: gzip, chunked\r\n\r\n{:x}\r\n", gzipped.len()).into_bytes();
+		raw.extend_from_slice(&gzipped);
+		raw.extend_from_slice(b"\r\n0\r\n\r\n");
+
+		// undone even when Content-Encoding is left alone, it is the connection's business
+		let mut cfg = Config::new();
+		cfg.decode_request_body = false;
+		let mut stream = raw.as_slice();
+		let mut req = Request::new();
+		assert!(req.from(&mut stream, &cfg).await.is_none());
+		assert_eq!(req.body().unwrap().as_ref(), b"Hello Gzip");
+		assert_eq!(req.body_len(), Some(10));
+		assert_eq!(req.headers().unwrap().get(hns::TRANSFER_ENCODING), Some("chunked"));
+
+		// a coding the policy does not list is not framed at all
+		cfg.transfer_codings = vec![];
+		let mut stream = raw.as_slice();
+		let mut req = Request::new();
+		let err = req.from(&mut stream, &cfg).await.unwrap();
+		assert!(err.statuscode() == StatusCode::BadRequest);
+	}
+
+	#[tokio::test]
+	async fn test_chunked_body_timeout() {
+		let mut cfg = Config::new();
+		cfg.chunked_body_timeout = Some(Duration::from_millis(100));
+
+		let (mut client, mut server) = tokio::io::duplex(64);
+		let writer = tokio::spawn(async move {
+			client.write_all(b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n").await?;
+			// every chunk in time for any per-read timeout, the whole body not
+			for _ in 0..20 {
+				client.write_all(b"1\r\na\r\n").await?;
+				tokio::time::sleep(Duration::from_millis(20)).await;
+			}
+			return client.write_all(b"0\r\n\r\n").await;
+		});
+		let mut req = Request::new();
+		let err = req.from(&mut server, &cfg).await.unwrap();
+		assert_eq!(format!("{:?}", err), "chunked body timeout");
+		assert!(err.statuscode() == StatusCode::RequestTimeout);
+		drop(server);
+		let _ = writer.await;
+
+		let mut stream: &[u8] = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nHello\r\n0\r\n\r\n";
+		let mut req = Request::new();
+		assert!(req.from(&mut stream, &cfg).await.is_none());
+		assert_eq!(req.body().unwrap().as_ref(), b"Hello");
+	}
+
+	#[tokio::test]
+	async fn test_body_on_get() {
+		let raw: &[u8] = b"GET / HTTP/1.1\r\nContent-Length: 5\r\n\r\nHello";
+
+		let mut stream = raw;
+		let mut req = Request::new();
+		assert!(req.from(&mut stream, &Config::new()).await.is_none());
+		assert_eq!(req.body().unwrap().as_ref(), b"Hello");
+
+		let mut cfg = Config::new();
+		cfg.allow_body_on_get = false;
+		let mut stream = raw;
+		let mut req = Request::new();
+		let err = req.from(&mut stream, &cfg).await.unwrap();
+		assert!(err.is_protocol());
+		assert_eq!(format!("{:?}", err), "body not allowed");
+	}
+
+	#[tokio::test]
+	async fn test_raw_headers() {
+		let head: &[u8] = b"POST /upload HTTP/1.1\r\nHost: spk.local\r\nX-Odd :  value \r\nContent-Length: 4\r\n\r\n";
+		let raw = [head, b"body"].concat();
+
+		let mut stream = raw.as_slice();
+		let mut req = Request::new();
+		assert!(req.from(&mut stream, &Config::new()).await.is_none());
+		assert!(req.raw_headers().is_none());
+
+		let mut cfg = Config::new();
+		cfg.keep_raw_headers = true;
+		let mut stream = raw.as_slice();
+		let mut req = Request::new();
+		assert!(req.from(&mut stream, &cfg).await.is_none());
+		assert_eq!(req.raw_headers().unwrap(), head);
+		assert_eq!(req.body().unwrap().as_ref(), b"body");
+	}
+
+	#[tokio::test]
+	async fn test_uri() {
+		let mut stream: &[u8] = b"GET /files/a%20b?download=1 HTTP/1.1\r\n\r\n";
+		let mut req = Request::new();
+		assert!(req.from(&mut stream, &Config::new()).await.is_none());
+		assert_eq!(req.path(), "/files/a%20b?download=1");
+		assert_eq!(req.uri().path(), "/files/a%20b");
+		assert_eq!(req.uri().decoded_path().unwrap(), "/files/a b");
+		assert_eq!(req.uri().query(), Some("download=1"));
+
+		req.builder().rawpath("http://spk.local/");
+		assert_eq!(req.uri().authority(), Some("spk.local"));
+	}
+
+	#[tokio::test]
+	async fn test_absolute_form() {
+		let raw: &[u8] = b"GET http://spk.local/index.html HTTP/1.1\r\nHost: spk.local\r\n\r\n";
+
+		let mut stream = raw;
+		let mut req = Request::new();
+		let err = req.from(&mut stream, &Config::new()).await.unwrap();
+		assert!(err.is_protocol());
+		assert_eq!(format!("{:?}", err), "absolute-form target not allowed");
+
+		let mut cfg = Config::new();
+		cfg.allow_absolute_form = true;
+		let mut stream = raw;
+		let mut req = Request::new();
+		assert!(req.from(&mut stream, &cfg).await.is_none());
+		assert_eq!(req.uri().authority(), Some("spk.local"));
+		assert_eq!(req.uri().path(), "/index.html");
+
+		cfg.require_host = true;
+		let mut stream: &[u8] = b"GET http://spk.local/index.html HTTP/1.1\r\nHost: spk.local\r\nHost: other.local\r\n\r\n";
+		let mut req = Request::new();
+		let err = req.from(&mut stream, &cfg).await.unwrap();
+		assert_eq!(format!("{:?}", err), "missing or repeated host");
+		assert_eq!(err.statuscode(), StatusCode::BadRequest);
+
+		let mut stream: &[u8] = b"CONNECT spk.local:443 HTTP/1.1\r\n\r\n";
+		let mut req = Request::new();
+		assert!(req.from(&mut stream, &Config::new()).await.is_none());
+		assert_eq!(req.uri().authority(), Some("spk.local:443"));
+		assert!(req.uri().scheme().is_none());
+	}
+
+	#[tokio::test]
+	async fn test_require_host() {
+		let mut cfg = Config::new();
+		cfg.require_host = true;
+		cfg.allow_absolute_form = true;
+		let cases: [(&[u8], Option<&str>); 6] = [
+			(b"GET / HTTP/1.1\r\nHost: spk.local\r\n\r\n", Some("spk.local")),
+			(b"GET http://spk.local:8080/a HTTP/1.1\r\n\r\n", Some("spk.local:8080")),
+			// the target wins over the header
+			(b"GET http://spk.local/a HTTP/1.1\r\nHost: other.local\r\n\r\n", Some("spk.local")),
+			(b"GET / HTTP/1.0\r\n\r\n", None),
+			(b"GET / HTTP/1.1\r\n\r\n", None),
+			(b"GET / HTTP/1.1\r\nHost: a.local\r\nHost: b.local\r\n\r\n", None),
+		];
+		for (i, (raw, authority)) in cases.iter().enumerate() {
+			let mut stream = *raw;
+			let mut req = Request::new();
+			let err = req.from(&mut stream, &cfg).await;
+			if i < 4 {
+				assert!(err.is_none(), "{}", String::from_utf8_lossy(raw));
+				assert_eq!(req.authority(), *authority);
+			} else {
+				assert_eq!(format!("{:?}", err.unwrap()), "missing or repeated host");
+			}
+		}
+	}
+
+	#[tokio::test]
+	async fn test_read_body_exact() {
+		let head: &[u8] = b"POST / HTTP/1.1\r\nConnection: close\r\nContent-Length: 2\r\n\r\n{}";
+		let mut stream = head;
+		let mut req = Request::new();
+		assert!(req.read_head(&mut stream, &Config::new()).await.is_none());
+		assert!(req.read_body_exact(&mut stream, &Config::new()).await.is_none());
+
+		// extra bytes in the same read, and in a read of their own
+		let buffered = [head, b"GET /admin HTTP/1.1\r\n\r\n"].concat();
+		let mut stream = buffered.as_slice();
+		let mut req = Request::new();
+		assert!(req.read_head(&mut stream, &Config::new()).await.is_none());
+		let err = req.read_body_exact(&mut stream, &Config::new()).await.unwrap();
+		assert_eq!(format!("{:?}", err), "bytes past the last request");
+
+		let mut stream = head.chain(&b"GET /admin HTTP/1.1\r\n\r\n"[..]);
+		let mut req = Request::new();
+		assert!(req.read_head(&mut stream, &Config::new()).await.is_none());
+		let err = req.read_body_exact(&mut stream, &Config::new()).await.unwrap();
+		assert!(err.statuscode() == StatusCode::BadRequest);
+		assert_eq!(req.body().unwrap().as_ref(), b"{}");
+
+		// extra bytes a while after the body, within the wait
+		let mut cfg = Config::new();
+		cfg.trailing_bytes_wait = Duration::from_millis(500);
+		let (mut client, mut server) = tokio::io::duplex(1024);
+		client.write_all(head).await.unwrap();
+		let writer = tokio::spawn(async move {
+			tokio::time::sleep(Duration::from_millis(20)).await;
+			client.write_all(b"GET /admin HTTP/1.1\r\n\r\n").await.unwrap();
+			return client;
+		});
+		let mut req = Request::new();
+		assert!(req.read_head(&mut server, &cfg).await.is_none());
+		let err = req.read_body_exact(&mut server, &cfg).await.unwrap();
+		assert_eq!(format!("{:?}", err), "bytes past the last request");
+		drop(writer.await.unwrap());
+
+		// a client that keeps its side open and sends nothing more is let through once the wait is over
+		cfg.trailing_bytes_wait = Duration::from_millis(20);
+		let (mut client, mut server) = tokio::io::duplex(1024);
+		client.write_all(head).await.unwrap();
+		let mut req = Request::new();
+		assert!(req.read_head(&mut server, &cfg).await.is_none());
+		assert!(req.read_body_exact(&mut server, &cfg).await.is_none());
+		drop(client);
+	}
+
+	#[tokio::test]
+	async fn test_body_pool() {
+		let pool = Arc::new(BufferPool::new(2, 64 << 10));
+		let mut cfg = Config::new();
+		cfg.body_pool = Some(Arc::clone(&pool));
+		let read = |body: usize| {
+			let cfg = cfg.clone();
+			return async move {
+				let raw = format!("POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}", body, "x".repeat(body));
+				let mut stream = raw.as_bytes();
+				let mut req = Request::new();
+				assert!(req.from(&mut stream, &cfg).await.is_none());
+				return req;
+			};
+		};
+
+		let req = read(8000).await;
+		assert!(req.body().unwrap().capacity() >= 8000);
+		drop(req);
+		assert_eq!(pool.len(), 1);
+		// the next body is read into the buffer of the first one
+		let req = read(10).await;
+		assert!(req.body().unwrap().capacity() >= 8000);
+		assert_eq!(pool.len(), 0);
+		drop(req);
+
+		let reqs = vec![read(10).await, read(10).await, read(10).await];
+		drop(reqs);
+		assert_eq!(pool.len(), 2);
+
+		// huge buffers are not kept around
+		let mut req = read(100 << 10).await;
+		assert_eq!(pool.len(), 1);
+		req.clear();
+		assert_eq!(pool.len(), 1);
+		assert!(req.body().is_none_or(|b| b.is_empty()));
+	}
+
+	#[tokio::test]
+	async fn test_body_spill() {
+		let mut cfg = Config::new();
+		cfg.spill_body_threshold = Some(4096);
+		let body = "0123456789".repeat(1000);
+		let chunked = format!("{:x}\r\n{}\r\n{:x}\r\n{}\r\n0\r\n\r\n", 6000, &body[..6000], 4000, &body[6000..]);
+		for raw in [
+			format!("POST / HTTP/1.1\r\nContent-Length: 10000\r\n\r\n{}", body),
+			format!("POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n{}", chunked),
+		] {
+			let mut stream = raw.as_bytes();
+			let mut req = Request::new();
+			assert!(req.from(&mut stream, &cfg).await.is_none());
+			assert!(req.body().is_none());
+			assert_eq!(req.body_len(), Some(10000));
+			let path = req.body_path().unwrap().to_path_buf();
+			assert!(path.starts_with(&cfg.spill_dir));
+			assert_eq!(std::fs::read(&path).unwrap(), body.as_bytes());
+
+			drop(req);
+			assert!(!path.exists());
+		}
+
+		// small ones stay in memory
+		let mut stream: &[u8] = b"POST / HTTP/1.1\r\nContent-Length: 4\r\n\r\nping";
+		let mut req = Request::new();
+		assert!(req.from(&mut stream, &cfg).await.is_none());
+		assert!(req.body_path().is_none());
+		assert_eq!(req.body().unwrap().as_ref(), b"ping");
+	}
+
+	#[tokio::test]
+	async fn test_debug() {
+		let raw = format!("POST /upload HTTP/1.1\r\nHost: spk.local\r\nX-Long: {}\r\nContent-Length: 5\r\n\r\nHello", "é".repeat(100));
+		let mut stream = raw.as_bytes();
+		let mut req = Request::new();
+		assert!(req.from(&mut stream, &Config::new()).await.is_none());
+		let out = format!("{:?}", req);
+		assert!(out.starts_with("Request <POST /upload HTTP/1.1, headers: {"));
+		assert!(out.contains("Host: spk.local"));
+		assert!(out.ends_with("}, body: 5 bytes>"));
+		// long values are cut short
+		assert!(out.contains(&format!("X-Long: {}...", "é".repeat(32))));
+		assert!(!out.contains(&"é".repeat(33)));
+
+		assert_eq!(format!("{:?}", Request::new()), "Request <  , headers: {}, body: none>");
+	}
+
+	#[tokio::test]
+	async fn test_client_ip() {
+		let ip = |v: &str| { return v.parse::<IpAddr>().unwrap(); };
+		let mut stream: &[u8] = b"GET / HTTP/1.1\r\nX-Forwarded-For: 6.6.6.6, 203.0.113.7, 10.0.0.2\r\n\r\n";
+		let mut req = Request::new();
+		assert!(req.from(&mut stream, &Config::new()).await.is_none());
+		req.set_peer_addr("10.0.0.1:50000".parse().unwrap());
+
+		assert_eq!(req.client_ip(false), ip("10.0.0.1"));
+		assert_eq!(req.client_ip(true), ip("6.6.6.6"));
+		// 6.6.6.6 was made up by the client, the first proxy saw 203.0.113.7
+		let trusted = [ip("10.0.0.1"), ip("10.0.0.2")];
+		assert_eq!(req.client_ip_via(&trusted, ProxyHeader::XForwardedFor), ip("203.0.113.7"));
+		// headers from a peer that is no proxy of ours do not count
+		assert_eq!(req.client_ip_via(&[ip("10.0.0.9")], ProxyHeader::XForwardedFor), ip("10.0.0.1"));
+
+		// proxies writing `X-Forwarded-For` pass a `Forwarded` sent by the client on untouched
+		let mut stream: &[u8] = b"GET / HTTP/1.1\r\nForwarded: for=6.6.6.6\r\nX-Forwarded-For: 203.0.113.7, 10.0.0.2\r\n\r\n";
+		let mut req = Request::new();
+		assert!(req.from(&mut stream, &Config::new()).await.is_none());
+		req.set_peer_addr("10.0.0.1:50000".parse().unwrap());
+		assert_eq!(req.client_ip_via(&trusted, ProxyHeader::XForwardedFor), ip("203.0.113.7"));
+		// which is why the caller has to name the header its proxies write
+		assert_eq!(req.client_ip_via(&trusted, ProxyHeader::Forwarded), ip("6.6.6.6"));
+	}
 }
\ No newline at end of file
@@ -1,13 +1,1232 @@
-use crate::h2tp::message::Message;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use bytes::{Bytes, BytesMut};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+use crate::h2tp::{content_encoding, headers, types};
+use crate::h2tp::cfg::Config;
+use crate::h2tp::headers::{HeaderValue, Headers, hns, mime};
+use crate::h2tp::message::{Message, MessageKind, ParseError, ReadBuffer};
+use crate::h2tp::request::Request;
+use crate::h2tp::sse::EventStream;
+use crate::h2tp::status_code::StatusCode;
+use crate::h2tp::types::TunnelHandler;
+use crate::h2tp::utils::http_date;
+use crate::h2tp::utils::quoted_string::split_list;
+use crate::h2tp::utils::range::{ByteRange, RangeError};
+
+const MALFORMED_STATUS_LINE: &str = "malformed status line";
+// pieces a `ResponseWriter` may be ahead of the connection
+const STREAM_QUEUE_SIZE: usize = 16;
+
+// Writes until all of `buf` is out, picking up after partial writes. A writer that is not ready
+// returns Pending, a WouldBlock error is a failure like any other.
+async fn write_fully<W: types::AsyncWriter>(w: &mut W, buf: &[u8]) -> std::io::Result<()> {
+	let mut offset = 0;
+	while offset < buf.len() {
+		match w.write(&buf[offset..]).await {
+			Ok(0) => {
+				return Err(std::io::Error::from(std::io::ErrorKind::WriteZero));
+			}
+			Ok(size) => {
+				offset += size;
+			}
+			Err(e) => {
+				if e.kind() != std::io::ErrorKind::Interrupted {
+					return Err(e);
+				}
+			}
+		}
+	}
+	return Ok(());
+}
+
+// Sends `head` and `body` as they are, then the pieces of `rx` as they come in. Every piece is
+// flushed right away, the peer sees the body as it is produced. Without chunked framing nor
+// `sized`, the body ends where the connection does.
+async fn write_stream<W: types::AsyncWriter>(
+	w: &mut W, mut head: Vec<u8>, body: &[u8], rx: &mut mpsc::Receiver<Bytes>,
+	chunked: bool, sized: Option<usize>, trailers: Option<&Headers>,
+) -> std::io::Result<()> {
+	let mut written = 0;
+	let piece = |buf: &mut Vec<u8>, data: &[u8], written: &mut usize| -> std::io::Result<()> {
+		if data.is_empty() {
+			return Ok(());
+		}
+		if chunked {
+			buf.extend_from_slice(format!("{:x}\r\n", data.len()).as_bytes());
+			buf.extend_from_slice(data);
+			buf.extend_from_slice(b"\r\n");
+		} else {
+			if sized.is_some_and(|n| *written + data.len() > n) {
+				return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "body longer than Content-Length"));
+			}
+			buf.extend_from_slice(data);
+		}
+		*written += data.len();
+		return Ok(());
+	};
+	piece(&mut head, body, &mut written)?;
+	write_fully(w, head.as_slice()).await?;
+	w.flush().await?;
+
+	let mut buf = Vec::with_capacity(4096);
+	while let Some(data) = rx.recv().await {
+		buf.clear();
+		piece(&mut buf, data.as_ref(), &mut written)?;
+		write_fully(w, buf.as_slice()).await?;
+		w.flush().await?;
+	}
+
+	if !chunked {
+		if sized.is_some_and(|n| written != n) {
+			return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "body shorter than Content-Length"));
+		}
+		return Ok(());
+	}
+	buf.clear();
+	buf.extend_from_slice(b"0\r\n");
+	match trailers {
+		Some(t) => {
+			t.each_cased(|k, v| {
+				buf.extend_from_slice(k.as_bytes());
+				buf.extend_from_slice(b": ");
+				buf.extend_from_slice(v.as_bytes());
+				buf.extend_from_slice(b"\r\n");
+			});
+		}
+		None => {}
+	}
+	buf.extend_from_slice(b"\r\n");
+	write_fully(w, buf.as_slice()).await?;
+	return w.flush().await;
+}
+
+// a header the server adds itself, `name` in its canonical case
+fn push_header(buf: &mut Vec<u8>, name: &str, v: &str, lowercase: bool) {
+	if lowercase {
+		buf.extend_from_slice(name.to_ascii_lowercase().as_bytes());
+	} else {
+		buf.extend_from_slice(name.as_bytes());
+	}
+	buf.extend_from_slice(b": ");
+	buf.extend_from_slice(v.as_bytes());
+	buf.extend_from_slice(b"\r\n");
+}
+
+// What finalizing a response takes from the request it answers, see `Response::finalize`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ResponseContext {
+	// no chunked framing, see `Response::allow_chunked`
+	pub http10: bool,
+	// `TE: trailers` was sent
+	pub wants_trailers: bool,
+	// the connection is closed after the response, `Connection: close` tells the client so
+	pub closing: bool,
+}
+
+impl ResponseContext {
+	pub fn of(req: &Request, cfg: &Config) -> Self {
+		return Self {
+			http10: req.version() == "HTTP/1.0",
+			wants_trailers: req.headers().is_some_and(|h| h.wants_trailers()),
+			closing: !cfg.keep_alive,
+		};
+	}
+}
 
 pub struct Response {
 	msg: Message,
+	bodyless: bool,
+	head_only: bool,
+	trailers: Option<Headers>,
+	trailers_allowed: bool,
+	chunked_allowed: bool,
+	stream: Option<mpsc::Receiver<Bytes>>,
+	upgrade: Option<TunnelHandler>,
+}
+
+// The body of a response returned by `Response::streaming`, written piece by piece while the
+// connection sends them. The body ends when the writer is dropped.
+pub struct ResponseWriter {
+	tx: mpsc::Sender<Bytes>,
+}
+
+impl ResponseWriter {
+	// waits while the connection is behind, fails once it is gone
+	pub async fn write(&mut self, data: &[u8]) -> std::io::Result<()> {
+		if data.is_empty() {
+			return Ok(());
+		}
+		return match self.tx.send(Bytes::copy_from_slice(data)).await {
+			Ok(_) => {
+				Ok(())
+			}
+			Err(_) => {
+				Err(std::io::Error::from(std::io::ErrorKind::BrokenPipe))
+			}
+		};
+	}
+
+	pub fn finish(self) {}
+}
+
+pub struct Builder<'resp> {
+	resp: &'resp mut Response,
+}
+
+impl<'resp> Builder<'resp> {
+	fn new(v: &'resp mut Response) -> Self {
+		return Self {
+			resp: v,
+		};
+	}
+
+	pub fn version(&mut self, version: &str) -> &mut Self {
+		self.resp.msg.startline.0 = version.to_string();
+		return self;
+	}
+
+	pub fn status(&mut self, code: StatusCode) -> &mut Self {
+		self.resp.msg.startline.1 = (code as u16).to_string();
+		self.resp.msg.startline.2 = code.msg().to_string();
+		return self;
+	}
+
+	// Content-Length always follows the byte length of the body, not its char count.
+	pub fn body(&mut self, v: &[u8]) -> &mut Self {
+		match self.resp.msg.body.as_mut() {
+			Some(bodyref) => {
+				bodyref.clear();
+				bodyref.extend_from_slice(v);
+			}
+			None => {
+				self.resp.msg.body = Some(BytesMut::from(v));
+			}
+		}
+		self.resp.msg.headers_builder().content_length(v.len());
+		return self;
+	}
+
+	pub fn text(&mut self, v: &str) -> &mut Self {
+		self.body(v.as_bytes());
+		self.resp.msg.headers_builder().content_type("text/plain; charset=utf-8");
+		return self;
+	}
+
+	pub fn headers(&mut self) -> headers::Builder {
+		return self.resp.msg.headers_builder();
+	}
+
+	// `Accept-Ranges`, `bytes` for a resource `Range` works on or `none` to tell clients not to try
+	// (RFC 9110 14.3); `representation` sets `bytes` by itself
+	pub fn accept_ranges(&mut self, v: &str) -> &mut Self {
+		self.headers().reset(hns::ACCEPT_RANGES, v);
+		return self;
+	}
+
+	// A 206 with the given ranges of `data`. One range is sent as is with its `Content-Range`,
+	// several go into a multipart/byteranges body, one part per range.
+	pub fn ranges(&mut self, data: &[u8], content_type: &str, ranges: &[ByteRange]) -> &mut Self {
+		let total = data.len() as u64;
+		self.status(StatusCode::PartialContent);
+		if ranges.len() == 1 {
+			let range = ranges[0];
+			self.body(&data[range.start as usize..=range.end as usize]);
+			self.headers().content_type(content_type).reset(hns::CONTENT_RANGE, range.content_range(total).as_str());
+			return self;
+		}
+
+		let boundary = format!("spk-{:016x}", RandomState::new().build_hasher().finish());
+		let mut body: Vec<u8> = Vec::new();
+		for range in ranges {
+			body.extend_from_slice(format!(
+				"--{}\r\nContent-Type: {}\r\nContent-Range: {}\r\n\r\n",
+				boundary, content_type, range.content_range(total),
+			).as_bytes());
+			body.extend_from_slice(&data[range.start as usize..=range.end as usize]);
+			body.extend_from_slice(b"\r\n");
+		}
+		body.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+		self.body(body.as_slice());
+		self.headers().remove(hns::CONTENT_RANGE)
+			.content_type(format!("{}; boundary={}", mime::MULTIPART_BYTERANGES, boundary).as_str());
+		return self;
+	}
+
+	// `data` as the answer to `req`: the ranges it asks for, a 416 when none of them fits, or all of
+	// it when there is no usable `Range`, see `Request::ranges`.
+	pub fn representation(&mut self, req: &Request, data: &[u8], content_type: &str) -> &mut Self {
+		let total = data.len() as u64;
+		self.accept_ranges("bytes");
+		match req.ranges(total) {
+			Some(Ok(ranges)) => {
+				return self.ranges(data, content_type, ranges.as_slice());
+			}
+			Some(Err(RangeError::Unsatisfiable)) => {
+				self.status(RangeError::Unsatisfiable.statuscode()).body(b"");
+				self.headers().reset(hns::CONTENT_RANGE, RangeError::content_range(total).as_str());
+				return self;
+			}
+			_ => {}
+		}
+		self.status(StatusCode::OK).body(data);
+		self.headers().content_type(content_type);
+		return self;
+	}
+
+	// Sent after a chunked body when the client accepts trailers, dropped otherwise. Nothing
+	// that is needed to handle the response belongs here.
+	pub fn trailer(&mut self, k: &str, v: &str) -> &mut Self {
+		if self.resp.trailers.is_none() {
+			self.resp.trailers = Some(Headers::new());
+		}
+		self.resp.trailers.as_mut().unwrap().builder().append(k, v);
+		return self;
+	}
+
+	// Compresses the body with the best coding `accept_encoding` (the request's `Accept-Encoding`)
+	// and the server have in common, see `content_encoding::negotiate`. The response turns into a
+	// 406 when there is none and the request refuses identity too.
+	pub fn compress(&mut self, accept_encoding: Option<&str>) -> &mut Self {
+		// once, however many times the body is compressed
+		let varies = match self.resp.headers().and_then(|h| h.get_combined(hns::VARY)) {
+			Some(v) => {
+				split_list(&v, ',').into_iter().any(|name| name.eq_ignore_ascii_case("Accept-Encoding"))
+			}
+			None => {
+				false
+			}
+		};
+		if !varies {
+			self.headers().append(hns::VARY, "Accept-Encoding");
+		}
+		let already = self.resp.has_header(hns::CONTENT_ENCODING);
+		if already || self.resp.msg.body.as_ref().is_none_or(|b| b.is_empty()) {
+			return self;
+		}
+		let accept_encoding = match accept_encoding {
+			Some(v) => {
+				v
+			}
+			None => {
+				return self;
+			}
+		};
+		let coding = match content_encoding::negotiate(accept_encoding) {
+			Some(v) => {
+				v
+			}
+			None => {
+				if content_encoding::identity_refused(accept_encoding) {
+					self.status(StatusCode::NotAcceptable).body(b"");
+					self.headers().remove(hns::CONTENT_TYPE);
+				}
+				return self;
+			}
+		};
+		let encoded = content_encoding::encode(coding, self.resp.msg.body.as_ref().unwrap().as_ref());
+		self.body(encoded.as_slice());
+		self.headers().append(hns::CONTENT_ENCODING, coding);
+		return self;
+	}
+
+	// A 101 switching to `protocol`, after which `handler` gets the connection. Bytes the client
+	// sent past the request are in its `buffered`, they come before anything it reads.
+	pub fn upgrade(&mut self, protocol: &str, handler: TunnelHandler) -> &mut Self {
+		self.status(StatusCode::SwitchingProtocols);
+		self.headers().reset(hns::CONNECTION, "Upgrade").reset(hns::UPGRADE, protocol);
+		self.resp.upgrade = Some(handler);
+		return self;
+	}
+
+	// sends the head only, without a body and without framing headers
+	pub fn bodyless(&mut self) -> &mut Self {
+		self.resp.bodyless = true;
+		return self;
+	}
+
+	// the answer to `HEAD`: the head a `GET` would get, `Content-Length` of the body included,
+	// but not the body itself (RFC 9110 9.3.2)
+	pub fn head_only(&mut self) -> &mut Self {
+		self.resp.head_only = true;
+		return self;
+	}
 }
 
 impl Response {
 	pub fn new() -> Self {
+		let mut msg = Message::new();
+		msg.startline.0.push_str("HTTP/1.1");
+		msg.startline.1.push_str("200");
+		msg.startline.2.push_str(StatusCode::OK.msg());
 		return Response {
-			msg: Message::new(),
+			msg,
+			bodyless: false,
+			head_only: false,
+			trailers: None,
+			trailers_allowed: false,
+			chunked_allowed: true,
+			stream: None,
+			upgrade: None,
+		};
+	}
+
+	// A response whose body comes from the returned writer. It is sent chunked unless the handler
+	// sets a Content-Length, the written bytes must then add up to it or the connection is closed.
+	pub fn streaming() -> (Self, ResponseWriter) {
+		let (tx, rx) = mpsc::channel(STREAM_QUEUE_SIZE);
+		let mut resp = Self::new();
+		resp.stream = Some(rx);
+		return (resp, ResponseWriter { tx });
+	}
+
+	// Back to what `new` gives, keeping the memory of the headers and the body for the next one a
+	// caller builds in it. Connections do not reuse responses, each one a handler returns is its own.
+	pub fn clear(&mut self) {
+		self.msg.clear();
+		self.msg.startline.0.push_str("HTTP/1.1");
+		self.msg.startline.1.push_str("200");
+		self.msg.startline.2.push_str(StatusCode::OK.msg());
+		self.bodyless = false;
+		self.head_only = false;
+		self.trailers = None;
+		self.trailers_allowed = false;
+		self.chunked_allowed = true;
+		self.stream = None;
+		self.upgrade = None;
+	}
+
+	// the handler the connection goes to once this response is sent, see `Builder::upgrade`
+	pub fn take_upgrade(&mut self) -> Option<TunnelHandler> {
+		return self.upgrade.take();
+	}
+
+	pub fn is_streaming(&self) -> bool {
+		return self.stream.is_some();
+	}
+
+	// Reads a response with its body. The response to a HEAD request has no body whatever its
+	// headers say, use `read_head` for it.
+	pub async fn from<R: types::AsyncReader>(&mut self, stream: &mut R, cfg: &Config) -> Option<ParseError> {
+		match self.read_head(stream, cfg).await {
+			Some(e) => {
+				return Some(e);
+			}
+			None => {}
+		}
+		return self.read_body(stream, cfg).await;
+	}
+
+	// reads the status line and the headers
+	pub async fn read_head<R: types::AsyncReader>(&mut self, stream: &mut R, cfg: &Config) -> Option<ParseError> {
+		self.msg.clear();
+		match self.msg.read_headers_as(stream, cfg, MessageKind::Response).await {
+			Some(e) => {
+				return Some(e);
+			}
+			None => {}
+		}
+		let code = self.msg.startline.1.as_str();
+		if !self.version().starts_with("HTTP/") || code.len() != 3 || !code.bytes().all(|b| b.is_ascii_digit()) || code.starts_with('0') {
+			return Some(ParseError::ue(MALFORMED_STATUS_LINE));
+		}
+		return None;
+	}
+
+	// Bodies of responses without framing run until the connection closes, at most `max_body_bytes`.
+	pub async fn read_body<R: types::AsyncReader>(&mut self, stream: &mut R, cfg: &Config) -> Option<ParseError> {
+		if self.is_bodyless() {
+			return None;
+		}
+		return self.msg.read_response_body(stream, cfg.limits.max_body_bytes).await;
+	}
+
+	pub fn take_read_buffer(&mut self) -> Option<ReadBuffer> {
+		return self.msg.take_read_buffer();
+	}
+
+	pub fn set_read_buffer(&mut self, rb: ReadBuffer) {
+		self.msg.set_read_buffer(rb);
+	}
+
+	// `code` with its reason phrase as a plain text body
+	pub fn with_status(code: StatusCode) -> Self {
+		let mut resp = Self::new();
+		resp.builder().status(code).text(code.msg());
+		return resp;
+	}
+
+	pub fn ok() -> Self {
+		return Self::with_status(StatusCode::OK);
+	}
+
+	pub fn bad_request() -> Self {
+		return Self::with_status(StatusCode::BadRequest);
+	}
+
+	pub fn not_found() -> Self {
+		return Self::with_status(StatusCode::NotFound);
+	}
+
+	pub fn internal_error() -> Self {
+		return Self::with_status(StatusCode::InternalServerError);
+	}
+
+	// A Server-Sent Events response for a handler to return, its events are sent through the
+	// returned stream. Like any streaming response it is chunked unless the client is HTTP/1.0.
+	pub fn event_stream() -> (Self, EventStream) {
+		let (mut resp, w) = Self::streaming();
+		resp.builder().headers()
+			.content_type(mime::EVENT_STREAM)
+			.reset(hns::CACHE_CONTROL, "no-cache");
+		return (resp, EventStream::new(w));
+	}
+
+	// A status line with a reason phrase of its own, `200 Totally Fine`. Codes must have three
+	// digits and reasons no CR, LF or other control characters but HTAB (RFC 7230 3.1.2);
+	// nothing is changed when either is invalid.
+	pub fn status_with_reason(&mut self, code: u16, reason: &str) -> bool {
+		if !(100..1000).contains(&code) || reason.bytes().any(|b| (b < 0x20 && b != b'\t') || b == 0x7f) {
+			return false;
+		}
+		self.msg.startline.1 = code.to_string();
+		self.msg.startline.2 = reason.to_string();
+		return true;
+	}
+
+	pub fn version(&self) -> &str {
+		return self.msg.startline.0.as_str();
+	}
+
+	pub fn statuscode(&self) -> u16 {
+		return self.msg.startline.1.parse::<u16>().unwrap_or(0);
+	}
+
+	pub fn reason(&self) -> &str {
+		return self.msg.startline.2.as_str();
+	}
+
+	pub fn headers(&self) -> Option<&Headers> {
+		return self.msg.headers.as_ref();
+	}
+
+	// appends a set of headers, like the CORS ones shared by several handlers
+	pub fn headers_from<I: IntoIterator<Item=(String, String)>>(&mut self, iter: I) -> &mut Self {
+		if self.msg.headers.is_none() {
+			self.msg.headers = Some(Headers::new());
+		}
+		self.msg.headers.as_mut().unwrap().extend(iter);
+		return self;
+	}
+
+	pub fn has_header(&self, k: &str) -> bool {
+		return match self.headers() {
+			Some(href) => {
+				href.get(k).is_some()
+			}
+			None => {
+				false
+			}
+		};
+	}
+
+	pub fn body(&self) -> Option<&BytesMut> {
+		return self.msg.body.as_ref();
+	}
+
+	pub fn builder(&mut self) -> Builder {
+		return Builder::new(self);
+	}
+
+	pub fn trailers(&self) -> Option<&Headers> {
+		return self.trailers.as_ref();
+	}
+
+	// whether the request came with `TE: trailers`, see `Headers::wants_trailers`
+	pub fn allow_trailers(&mut self, v: bool) {
+		self.trailers_allowed = v;
+	}
+
+	// HTTP/1.0 clients know nothing of chunked framing, a stream of unknown length is sent
+	// as is and ends with the connection instead, trailers are dropped.
+	pub fn allow_chunked(&mut self, v: bool) {
+		self.chunked_allowed = v;
+	}
+
+	// true when only closing the connection can tell where the body ends, see `allow_chunked`
+	pub fn is_close_delimited(&self) -> bool {
+		return !self.chunked_allowed && self.stream.is_some() && !self.is_bodyless()
+			&& self.headers().and_then(|h| h.content_length()).is_none();
+	}
+
+	// 1xx, 204 and 304 responses must not carry a body, nor a Content-Length.
+	pub fn is_bodyless(&self) -> bool {
+		let code = self.statuscode();
+		return self.bodyless || (100..200).contains(&code) || code == 204 || code == 304;
+	}
+
+	// Sets the headers that follow from `req` and the response itself, those the handler did not
+	// set: `Server`, `Date`, `Connection` and the framing, see `finalize_with`.
+	pub fn finalize(&mut self, req: &Request, cfg: &Config) {
+		self.finalize_with(&ResponseContext::of(req, cfg), cfg);
+	}
+
+	// `finalize` for a request that is gone already, with what was kept of it. Bodies are framed
+	// with `Content-Length`, or with `Transfer-Encoding: chunked` when they are streamed or carry
+	// trailers the client takes; a stream to an HTTP/1.0 client runs until close instead.
+	pub fn finalize_with(&mut self, ctx: &ResponseContext, cfg: &Config) {
+		match cfg.server_header.as_ref() {
+			Some(v) if !self.has_header(hns::SERVER) => {
+				self.builder().headers().append(hns::SERVER, v);
+			}
+			_ => {}
+		}
+		if cfg.date_header && !self.has_header(hns::DATE) {
+			self.builder().headers().append(hns::DATE, http_date::format(cfg.clock.now()).as_str());
+		}
+		self.allow_trailers(ctx.wants_trailers);
+		self.allow_chunked(!ctx.http10);
+		if self.is_close_delimited() {
+			self.builder().headers().reset(hns::CONNECTION, "close");
+		} else if ctx.closing && !self.has_header(hns::CONNECTION) {
+			self.builder().headers().append(hns::CONNECTION, "close");
+		}
+
+		if self.is_bodyless() {
+			return;
+		}
+		let sized = self.headers().and_then(|h| h.content_length()).is_some();
+		let chunked = (self.trailers.is_some() && self.trailers_allowed && self.chunked_allowed)
+			|| (self.stream.is_some() && !sized && self.chunked_allowed);
+		if chunked {
+			self.builder().headers().remove(hns::CONTENT_LENGTH).reset(hns::TRANSFER_ENCODING, "chunked");
+		} else if self.stream.is_none() && !sized {
+			let len = self.body().map_or(0, |b| b.len());
+			self.builder().headers().content_length(len);
+		}
+	}
+
+	pub async fn write_to<W: types::AsyncWriter>(&self, w: &mut W, cfg: &Config) -> std::io::Result<()> {
+		return self.write_parts(w, cfg, None).await;
+	}
+
+	// `write_to`, followed by what the `ResponseWriter` of a streaming response sends
+	pub async fn write_stream_to<W: types::AsyncWriter>(&mut self, w: &mut W, cfg: &Config) -> std::io::Result<()> {
+		let mut rx = self.stream.take();
+		return self.write_parts(w, cfg, rx.as_mut()).await;
+	}
+
+	async fn write_parts<W: types::AsyncWriter>(&self, w: &mut W, cfg: &Config, rx: Option<&mut mpsc::Receiver<Bytes>>) -> std::io::Result<()> {
+		let bodyless = self.is_bodyless();
+		let rx = if bodyless || self.head_only { None } else { rx };
+		let sized = self.headers().and_then(|h| h.content_length());
+		// trailers need chunked framing
+		let trailers = self.trailers.as_ref().filter(|_| self.trailers_allowed && self.chunked_allowed && !bodyless && !self.head_only);
+		let chunked = trailers.is_some() || (rx.is_some() && sized.is_none() && self.chunked_allowed);
+		let mut buf: Vec<u8> = Vec::with_capacity(256);
+		buf.extend_from_slice(self.version().as_bytes());
+		buf.push(b' ');
+		buf.extend_from_slice(self.msg.startline.1.as_bytes());
+		buf.push(b' ');
+		if !cfg.empty_reason_phrase {
+			buf.extend_from_slice(self.reason().as_bytes());
+		}
+		buf.extend_from_slice(b"\r\n");
+
+		let mut has_content_length = false;
+		let mut write_header = |k: &str, v: &HeaderValue| {
+			if k.eq_ignore_ascii_case(hns::CONTENT_LENGTH) {
+				if bodyless || chunked {
+					return;
+				}
+				has_content_length = true;
+			}
+			// sent below, a finalized response has it already
+			if chunked && k.eq_ignore_ascii_case(hns::TRANSFER_ENCODING) {
+				return;
+			}
+			buf.extend_from_slice(k.as_bytes());
+			buf.extend_from_slice(b": ");
+			buf.extend_from_slice(v.as_bytes());
+			buf.extend_from_slice(b"\r\n");
 		};
+		match self.headers() {
+			Some(href) => {
+				if cfg.lowercase_header_names {
+					href.each(&mut write_header);
+				} else {
+					href.each_cased(&mut write_header);
+				}
+			}
+			None => {}
+		}
+
+		let body: &[u8] = match self.body() {
+			Some(bodyref) => {
+				bodyref.as_ref()
+			}
+			None => {
+				&[]
+			}
+		};
+
+		// a body without a type would be sniffed by browsers
+		if !bodyless && !body.is_empty() && !self.has_header(hns::CONTENT_TYPE) {
+			match cfg.default_content_type.as_ref() {
+				Some(v) => {
+					push_header(&mut buf, "Content-Type", v, cfg.lowercase_header_names);
+				}
+				None => {}
+			}
+		}
+		if cfg.nosniff && !self.has_header(hns::X_CONTENT_TYPE_OPTIONS) {
+			push_header(&mut buf, "X-Content-Type-Options", "nosniff", cfg.lowercase_header_names);
+		}
+
+		if chunked {
+			push_header(&mut buf, "Transfer-Encoding", "chunked", cfg.lowercase_header_names);
+			match trailers {
+				Some(t) => {
+					let mut names: Vec<String> = Vec::new();
+					t.each_cased(|k, _| {
+						if !names.iter().any(|n| n == k) {
+							names.push(k.to_string());
+						}
+					});
+					push_header(&mut buf, "Trailer", names.join(", ").as_str(), cfg.lowercase_header_names);
+				}
+				None => {}
+			}
+		} else if !bodyless {
+			if rx.is_some() {
+				// the handler announced the length of what it streams, or it runs until close
+			} else if has_content_length {
+				debug_assert_eq!(
+					self.headers().unwrap().content_length(), Some(body.len()),
+					"Content-Length does not match the body",
+				);
+			} else {
+				push_header(&mut buf, "Content-Length", body.len().to_string().as_str(), cfg.lowercase_header_names);
+			}
+		}
+		buf.extend_from_slice(b"\r\n");
+		match rx {
+			Some(rx) => {
+				return write_stream(w, buf, body, rx, chunked, sized, trailers).await;
+			}
+			None => {}
+		}
+		if chunked {
+			if !body.is_empty() {
+				buf.extend_from_slice(format!("{:x}\r\n", body.len()).as_bytes());
+				buf.extend_from_slice(body);
+				buf.extend_from_slice(b"\r\n");
+			}
+			buf.extend_from_slice(b"0\r\n");
+			trailers.unwrap().each_cased(|k, v| {
+				buf.extend_from_slice(k.as_bytes());
+				buf.extend_from_slice(b": ");
+				buf.extend_from_slice(v.as_bytes());
+				buf.extend_from_slice(b"\r\n");
+			});
+			buf.extend_from_slice(b"\r\n");
+		} else if !bodyless && !self.head_only {
+			buf.extend_from_slice(body);
+		}
+
+		write_fully(w, buf.as_slice()).await?;
+		return w.flush().await;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io;
+	use std::pin::Pin;
+	use std::task::{Context, Poll};
+	use tokio::io::AsyncWrite;
+	use crate::h2tp::cfg::Config;
+	use crate::h2tp::headers::hns;
+	use crate::h2tp::request::Request;
+	use crate::h2tp::response::Response;
+	use crate::h2tp::status_code::StatusCode;
+	use crate::h2tp::utils::range::ByteRange;
+
+	#[tokio::test]
+	async fn test_bodyless_status() {
+		for code in [StatusCode::NoContent, StatusCode::NotModified] {
+			let mut resp = Response::new();
+			resp.builder().status(code).body(b"should not be sent");
+			resp.builder().headers().content_length(18).content_type("text/plain");
+
+			let mut out: Vec<u8> = vec![];
+			resp.write_to(&mut out, &Config::new()).await.unwrap();
+			let expected = format!("HTTP/1.1 {} {}\r\nContent-Type: text/plain\r\n\r\n", code as u16, code.msg());
+			assert_eq!(String::from_utf8(out).unwrap(), expected);
+		}
+	}
+
+	#[tokio::test]
+	async fn test_write_body() {
+		let mut resp = Response::new();
+		resp.builder().body(b"Hello World");
+
+		let mut out: Vec<u8> = vec![];
+		resp.write_to(&mut out, &Config::new()).await.unwrap();
+		assert_eq!(out.as_slice(), b"HTTP/1.1 200 OK\r\nContent-Length: 11\r\n\r\nHello World");
+	}
+
+	#[tokio::test]
+	async fn test_multibyte_content_length() {
+		let text = "Hello 😄 世界";
+		let mut resp = Response::new();
+		resp.builder().text(text);
+		assert_eq!(text.len(), 17);
+		assert_eq!(resp.headers().unwrap().content_length(), Some(17));
+
+		let mut out: Vec<u8> = vec![];
+		resp.write_to(&mut out, &Config::new()).await.unwrap();
+		let out = String::from_utf8(out).unwrap();
+		assert!(out.contains("\r\nContent-Length: 17\r\n"));
+		assert!(out.ends_with(text));
+	}
+
+	#[tokio::test]
+	async fn test_header_case() {
+		let mut resp = Response::new();
+		resp.builder().body(b"{}").headers().append("Content-Type", "application/json").append("x-trace-ID", "1");
+
+		let mut out: Vec<u8> = vec![];
+		resp.write_to(&mut out, &Config::new()).await.unwrap();
+		assert_eq!(
+			String::from_utf8(out).unwrap(),
+			"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nContent-Type: application/json\r\nx-trace-ID: 1\r\n\r\n{}",
+		);
+
+		let mut cfg = Config::new();
+		cfg.lowercase_header_names = true;
+		let mut out: Vec<u8> = vec![];
+		resp.write_to(&mut out, &cfg).await.unwrap();
+		assert_eq!(
+			String::from_utf8(out).unwrap(),
+			"HTTP/1.1 200 OK\r\ncontent-length: 2\r\ncontent-type: application/json\r\nx-trace-id: 1\r\n\r\n{}",
+		);
+	}
+
+	#[tokio::test]
+	async fn test_header_order() {
+		let mut resp = Response::new();
+		let mut builder = resp.builder();
+		let mut headers = builder.headers();
+		for i in 0..20 {
+			headers.append(&format!("X-H{}", 19 - i), &i.to_string());
+		}
+		headers.append("Vary", "Accept").append("X-H19", "again").content_length(0);
+
+		let mut out: Vec<u8> = vec![];
+		resp.write_to(&mut out, &Config::new()).await.unwrap();
+		let mut expected = String::from("HTTP/1.1 200 OK\r\n");
+		for i in 0..20 {
+			expected.push_str(&format!("X-H{}: {}\r\n", 19 - i, i));
+			if i == 0 {
+				expected.push_str("X-H19: again\r\n");
+			}
+		}
+		expected.push_str("Vary: Accept\r\nContent-Length: 0\r\n\r\n");
+		assert_eq!(String::from_utf8(out).unwrap(), expected);
+	}
+
+	#[tokio::test]
+	async fn test_empty_reason_phrase() {
+		let mut cfg = Config::new();
+		cfg.empty_reason_phrase = true;
+		let mut resp = Response::new();
+		resp.status_with_reason(404, "Gone Fishing");
+		resp.builder().body(b"gone");
+		let mut out: Vec<u8> = vec![];
+		resp.write_to(&mut out, &cfg).await.unwrap();
+		assert_eq!(String::from_utf8(out.clone()).unwrap(), "HTTP/1.1 404 \r\nContent-Length: 4\r\n\r\ngone");
+
+		// still a response our own parser takes
+		let mut stream = out.as_slice();
+		let mut parsed = Response::new();
+		assert!(parsed.from(&mut stream, &Config::new()).await.is_none());
+		assert_eq!(parsed.statuscode(), 404);
+		assert_eq!(parsed.reason(), "");
+		assert_eq!(parsed.body().unwrap().as_ref(), b"gone");
+	}
+
+	#[tokio::test]
+	async fn test_clear() {
+		let mut cfg = Config::new();
+		cfg.default_content_type = None;
+		let mut resp = Response::new();
+		assert!(resp.status_with_reason(404, "Gone Fishing"));
+		resp.builder().text("x".repeat(1000).as_str()).trailer("X-Checksum", "1").bodyless().headers().append("X-First", "1");
+		resp.allow_trailers(true);
+		let mut out: Vec<u8> = vec![];
+		resp.write_to(&mut out, &cfg).await.unwrap();
+
+		resp.clear();
+		assert!(resp.msg.body.as_ref().unwrap().capacity() >= 1000);
+		resp.builder().body(b"second");
+		let mut out: Vec<u8> = vec![];
+		resp.write_to(&mut out, &cfg).await.unwrap();
+		assert_eq!(String::from_utf8(out).unwrap(), "HTTP/1.1 200 OK\r\nContent-Length: 6\r\n\r\nsecond");
+		assert!(resp.msg.body.as_ref().unwrap().capacity() >= 1000);
+	}
+
+	#[test]
+	fn test_ranges() {
+		let data = b"0123456789abcdefghij";
+		let mut resp = Response::new();
+		resp.builder().ranges(data, "text/plain", &[ByteRange { start: 2, end: 5 }]);
+		assert_eq!(resp.statuscode(), 206);
+		assert_eq!(resp.body().unwrap().as_ref(), b"2345");
+		assert_eq!(resp.headers().unwrap().get(hns::CONTENT_RANGE).unwrap(), "bytes 2-5/20");
+		assert_eq!(resp.headers().unwrap().content_type().unwrap(), "text/plain");
+
+		let mut resp = Response::new();
+		resp.builder().ranges(data, "text/plain", &[ByteRange { start: 0, end: 1 }, ByteRange { start: 18, end: 19 }]);
+		assert_eq!(resp.statuscode(), 206);
+		let headers = resp.headers().unwrap();
+		assert!(headers.get(hns::CONTENT_RANGE).is_none());
+		let ct = headers.content_type().unwrap();
+		let boundary = ct.strip_prefix("multipart/byteranges; boundary=").unwrap();
+		assert!(!boundary.is_empty());
+		let expected = format!(
+			"--{b}\r\nContent-Type: text/plain\r\nContent-Range: bytes 0-1/20\r\n\r\n01\r\n\
+			--{b}\r\nContent-Type: text/plain\r\nContent-Range: bytes 18-19/20\r\n\r\nij\r\n\
+			--{b}--\r\n",
+			b = boundary,
+		);
+		assert_eq!(std::str::from_utf8(resp.body().unwrap()).unwrap(), expected);
+		assert_eq!(headers.content_length(), Some(expected.len()));
+	}
+
+	// takes at most `max` bytes per write, every other call is interrupted or not ready
+	struct TrickleWriter {
+		out: Vec<u8>,
+		max: usize,
+		calls: usize,
+	}
+
+	impl AsyncWrite for TrickleWriter {
+		fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+			self.calls += 1;
+			match self.calls % 4 {
+				1 => {
+					return Poll::Ready(Err(io::Error::from(io::ErrorKind::Interrupted)));
+				}
+				3 => {
+					cx.waker().wake_by_ref();
+					return Poll::Pending;
+				}
+				_ => {}
+			}
+			let size = buf.len().min(self.max);
+			self.out.extend_from_slice(&buf[..size]);
+			return Poll::Ready(Ok(size));
+		}
+
+		fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+			return Poll::Ready(Ok(()));
+		}
+
+		fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+			return Poll::Ready(Ok(()));
+		}
+	}
+
+	#[tokio::test]
+	async fn test_partial_writes() {
+		let mut resp = Response::new();
+		resp.builder().text("a body longer than a few bytes");
+
+		let mut w = TrickleWriter { out: vec![], max: 3, calls: 0 };
+		resp.write_to(&mut w, &Config::new()).await.unwrap();
+		let expected = "HTTP/1.1 200 OK\r\nContent-Length: 30\r\nContent-Type: text/plain; charset=utf-8\r\n\r\na body longer than a few bytes";
+		assert_eq!(String::from_utf8(w.out).unwrap(), expected);
+		assert!(w.calls > expected.len() / 3);
+
+		// WouldBlock is not retried, a writer that is not ready says so with Pending
+		let mut w = BlockedWriter {};
+		let err = resp.write_to(&mut w, &Config::new()).await.unwrap_err();
+		assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+	}
+
+	struct BlockedWriter {}
+
+	impl AsyncWrite for BlockedWriter {
+		fn poll_write(self: Pin<&mut Self>, _: &mut Context<'_>, _: &[u8]) -> Poll<io::Result<usize>> {
+			return Poll::Ready(Err(io::Error::from(io::ErrorKind::WouldBlock)));
+		}
+
+		fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+			return Poll::Ready(Ok(()));
+		}
+
+		fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+			return Poll::Ready(Ok(()));
+		}
+	}
+
+	#[tokio::test]
+	async fn test_default_content_type() {
+		let mut resp = Response::new();
+		resp.builder().body(b"<b>raw</b>");
+
+		let mut cfg = Config::new();
+		cfg.default_content_type = Some("application/octet-stream".to_string());
+		cfg.nosniff = true;
+		let mut out: Vec<u8> = vec![];
+		resp.write_to(&mut out, &cfg).await.unwrap();
+		assert_eq!(
+			String::from_utf8(out).unwrap(),
+			"HTTP/1.1 200 OK\r\nContent-Length: 10\r\nContent-Type: application/octet-stream\r\nX-Content-Type-Options: nosniff\r\n\r\n<b>raw</b>",
+		);
+
+		// a type set by the handler is kept, an empty body gets none
+		resp.builder().headers().content_type("text/html");
+		let mut out: Vec<u8> = vec![];
+		resp.write_to(&mut out, &cfg).await.unwrap();
+		assert_eq!(String::from_utf8(out).unwrap().matches("\r\nContent-Type: ").count(), 1);
+		let mut out: Vec<u8> = vec![];
+		Response::new().write_to(&mut out, &cfg).await.unwrap();
+		assert!(!String::from_utf8(out).unwrap().contains("\r\nContent-Type: "));
+	}
+
+	#[test]
+	fn test_status_constructors() {
+		let cases = [
+			(Response::ok(), StatusCode::OK),
+			(Response::bad_request(), StatusCode::BadRequest),
+			(Response::not_found(), StatusCode::NotFound),
+			(Response::internal_error(), StatusCode::InternalServerError),
+		];
+		for (resp, code) in cases {
+			assert_eq!(resp.statuscode(), code as u16);
+			assert_eq!(resp.reason(), code.msg());
+			assert_eq!(resp.body().unwrap().as_ref(), code.msg().as_bytes());
+			let headers = resp.headers().unwrap();
+			assert_eq!(headers.content_type().unwrap(), "text/plain; charset=utf-8");
+			assert_eq!(headers.content_length(), Some(code.msg().len()));
+		}
+
+		// still a regular response to build on
+		let mut resp = Response::not_found();
+		resp.builder().text("no such user");
+		assert_eq!(resp.statuscode(), 404);
+		assert_eq!(resp.body().unwrap().as_ref(), b"no such user");
+	}
+
+	#[tokio::test]
+	async fn test_trailers() {
+		let mut resp = Response::new();
+		resp.builder().body(b"Hello World").trailer("Server-Timing", "db;dur=53").trailer("X-Checksum", "1");
+
+		let mut out: Vec<u8> = vec![];
+		resp.write_to(&mut out, &Config::new()).await.unwrap();
+		assert_eq!(out.as_slice(), b"HTTP/1.1 200 OK\r\nContent-Length: 11\r\n\r\nHello World");
+
+		resp.allow_trailers(true);
+		let mut out: Vec<u8> = vec![];
+		resp.write_to(&mut out, &Config::new()).await.unwrap();
+		assert_eq!(
+			String::from_utf8(out).unwrap(),
+			"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nTrailer: Server-Timing, X-Checksum\r\n\r\n\
+			b\r\nHello World\r\n0\r\nServer-Timing: db;dur=53\r\nX-Checksum: 1\r\n\r\n",
+		);
+	}
+
+	#[tokio::test]
+	async fn test_custom_reason() {
+		let mut resp = Response::new();
+		assert!(resp.status_with_reason(200, "Totally Fine"));
+		resp.builder().body(b"ok");
+
+		let mut out: Vec<u8> = vec![];
+		resp.write_to(&mut out, &Config::new()).await.unwrap();
+		assert!(String::from_utf8(out).unwrap().starts_with("HTTP/1.1 200 Totally Fine\r\n"));
+
+		assert!(!resp.status_with_reason(404, "Gone\r\nX-Injected: 1"));
+		assert!(!resp.status_with_reason(404, "Gone\n"));
+		assert!(!resp.status_with_reason(42, "Short"));
+		assert_eq!(resp.statuscode(), 200);
+		assert_eq!(resp.reason(), "Totally Fine");
+	}
+
+	#[tokio::test]
+	async fn test_parse() {
+		let cfg = Config::new();
+		let mut stream: &[u8] = b"HTTP/1.1 404 Not Found\r\nContent-Type: text/plain\r\nContent-Length: 4\r\n\r\nnopeHTTP/1.1 204 No Content\r\nContent-Length: 4\r\n\r\n";
+		let mut resp = Response::new();
+		assert!(resp.from(&mut stream, &cfg).await.is_none());
+		assert_eq!((resp.version(), resp.statuscode(), resp.reason()), ("HTTP/1.1", 404, "Not Found"));
+		assert_eq!(resp.headers().unwrap().content_type().unwrap(), "text/plain");
+		assert_eq!(resp.body().unwrap().as_ref(), b"nope");
+
+		// the next response on the same buffer, a 204 has no body whatever it announces
+		let rb = resp.take_read_buffer().unwrap();
+		let mut resp = Response::new();
+		resp.set_read_buffer(rb);
+		assert!(resp.from(&mut stream, &cfg).await.is_none());
+		assert_eq!(resp.statuscode(), 204);
+		assert!(resp.body().is_none());
+
+		// unframed, the body runs until close
+		let mut stream: &[u8] = b"HTTP/1.0 200 OK\r\n\r\nall of it";
+		let mut resp = Response::new();
+		assert!(resp.from(&mut stream, &cfg).await.is_none());
+		assert_eq!(resp.body().unwrap().as_ref(), b"all of it");
+
+		for raw in [&b"HTTP/1.1 2000 OK\r\n\r\n"[..], b"HTTP/1.1 abc OK\r\n\r\n", b"GET / HTTP/1.1\r\n\r\n"] {
+			let mut stream = raw;
+			let err = Response::new().from(&mut stream, &cfg).await.unwrap();
+			assert_eq!(format!("{:?}", err), "malformed status line");
+		}
+	}
+
+	#[tokio::test]
+	async fn test_finalize() {
+		let mut cfg = Config::new();
+		let mut stream: &[u8] = b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n";
+		let mut req = Request::new();
+		assert!(req.from(&mut stream, &cfg).await.is_none());
+
+		// keep-alive, nothing to say about the connection
+		let mut resp = Response::new();
+		resp.builder().text("Hello World");
+		resp.finalize(&req, &cfg);
+		let headers = resp.headers().unwrap();
+		assert_eq!(headers.content_length(), Some(11));
+		assert!(headers.get(hns::DATE).is_some());
+		assert!(headers.get(hns::SERVER).is_some());
+		assert!(headers.get(hns::CONNECTION).is_none());
+
+		// headers set by the handler are kept
+		let mut resp = Response::new();
+		resp.builder().headers().append(hns::SERVER, "mine");
+		resp.finalize(&req, &cfg);
+		assert_eq!(resp.headers().unwrap().get(hns::SERVER), Some("mine"));
+		assert_eq!(resp.headers().unwrap().content_length(), Some(0));
+
+		let (mut resp, _writer) = Response::streaming();
+		resp.finalize(&req, &cfg);
+		assert_eq!(resp.headers().unwrap().get(hns::TRANSFER_ENCODING), Some("chunked"));
+		assert!(resp.headers().unwrap().content_length().is_none());
+
+		cfg.keep_alive = false;
+		cfg.date_header = false;
+		let mut resp = Response::new();
+		resp.finalize(&req, &cfg);
+		assert_eq!(resp.headers().unwrap().get(hns::CONNECTION), Some("close"));
+		assert!(resp.headers().unwrap().get(hns::DATE).is_none());
+
+		// an HTTP/1.0 stream runs until close
+		let mut stream: &[u8] = b"GET / HTTP/1.0\r\n\r\n";
+		let mut req = Request::new();
+		assert!(req.from(&mut stream, &Config::new()).await.is_none());
+		let (mut resp, _writer) = Response::streaming();
+		resp.finalize(&req, &Config::new());
+		assert!(resp.headers().unwrap().get(hns::TRANSFER_ENCODING).is_none());
+		assert_eq!(resp.headers().unwrap().get(hns::CONNECTION), Some("close"));
+	}
+
+	#[cfg(feature = "brotli")]
+	#[test]
+	fn test_compress() {
+		use bytes::BytesMut;
+		use crate::h2tp::content_encoding::decode_body;
+
+		let text = "compress me ".repeat(50);
+		let mut resp = Response::new();
+		resp.builder().text(text.as_str()).compress(Some("br, gzip"));
+		let headers = resp.headers().unwrap();
+		assert_eq!(headers.get(hns::CONTENT_ENCODING).unwrap(), "br");
+		assert_eq!(headers.get(hns::VARY).unwrap(), "Accept-Encoding");
+		let body = resp.body().unwrap();
+		assert_eq!(headers.content_length(), Some(body.len()));
+		let decoded = decode_body("br", &BytesMut::from(body.as_ref()), 4096).unwrap().unwrap();
+		assert_eq!(decoded.as_ref(), text.as_bytes());
+
+		let mut resp = Response::new();
+		resp.builder().text(text.as_str()).compress(None);
+		assert!(resp.headers().unwrap().get(hns::CONTENT_ENCODING).is_none());
+		assert_eq!(resp.body().unwrap().as_ref(), text.as_bytes());
+
+		let mut resp = Response::new();
+		resp.builder().text(text.as_str()).compress(Some("*;q=0.5, gzip;q=0.1"));
+		assert_eq!(resp.headers().unwrap().get(hns::CONTENT_ENCODING).unwrap(), "br");
+
+		// Vary names Accept-Encoding once, next to what the handler listed
+		let mut resp = Response::new();
+		resp.builder().text(text.as_str()).headers().append(hns::VARY, "Origin, accept-encoding");
+		resp.builder().compress(Some("gzip")).compress(Some("gzip")).compress(None);
+		assert_eq!(resp.headers().unwrap().get_combined(hns::VARY).unwrap(), "Origin, accept-encoding");
+		let mut resp = Response::new();
+		resp.builder().compress(None).compress(Some("gzip"));
+		assert_eq!(resp.headers().unwrap().get_all(hns::VARY).unwrap().len(), 1);
+	}
+
+	#[test]
+	fn test_compress_not_acceptable() {
+		for accept in ["identity;q=0", "identity;q=0, *;q=0", "compress, *;q=0"] {
+			let mut resp = Response::new();
+			resp.builder().text("must be encoded").compress(Some(accept));
+			assert_eq!(resp.statuscode(), 406, "{}", accept);
+			let headers = resp.headers().unwrap();
+			assert!(headers.get(hns::CONTENT_ENCODING).is_none());
+			assert_eq!(headers.content_length(), Some(0));
+			assert!(resp.body().unwrap().is_empty());
+		}
+
+		// nothing to encode, nothing refused
+		let mut resp = Response::new();
+		resp.builder().status(StatusCode::NoContent).compress(Some("identity;q=0"));
+		assert_eq!(resp.statuscode(), 204);
+
+		let mut resp = Response::new();
+		resp.builder().text("plain").compress(Some("identity, *;q=0"));
+		assert_eq!(resp.statuscode(), 200);
+		assert_eq!(resp.body().unwrap().as_ref(), b"plain");
+	}
+
+	#[tokio::test]
+	async fn test_representation() {
+		let data = b"0123456789abcdefghij";
+		let cases: [(&[u8], u16, &[u8]); 4] = [
+			(b"GET / HTTP/1.1\r\nRange: bytes=2-5\r\n\r\n", 206, b"2345"),
+			(b"HEAD / HTTP/1.1\r\nRange: bytes=-3\r\n\r\n", 206, b"hij"),
+			(b"GET / HTTP/1.1\r\nRange: bytes=50-\r\n\r\n", 416, b""),
+			// not a range request whatever it says
+			(b"POST / HTTP/1.1\r\nRange: bytes=2-5\r\nContent-Length: 0\r\n\r\n", 200, data),
+		];
+		for (raw, code, body) in cases {
+			let mut stream = raw;
+			let mut req = Request::new();
+			assert!(req.from(&mut stream, &Config::new()).await.is_none());
+			let mut resp = Response::new();
+			resp.builder().representation(&req, data, "text/plain");
+			assert_eq!(resp.statuscode(), code, "{}", String::from_utf8_lossy(raw));
+			assert_eq!(resp.body().unwrap().as_ref(), body);
+			assert_eq!(resp.headers().unwrap().get(hns::ACCEPT_RANGES), Some("bytes"));
+			if code == 200 {
+				assert!(resp.headers().unwrap().get(hns::CONTENT_RANGE).is_none());
+			}
+		}
+
+		// ranges asking for the same bytes over and over get them once, too many get a plain 200
+		for (count, code, body) in [(3, 206, &data[..]), (1000, 200, &data[..])] {
+			let raw = format!("GET / HTTP/1.1\r\nRange: bytes={}\r\n\r\n", vec!["0-"; count].join(","));
+			let mut stream = raw.as_bytes();
+			let mut req = Request::new();
+			assert!(req.from(&mut stream, &Config::new()).await.is_none());
+			let mut resp = Response::new();
+			resp.builder().representation(&req, data, "text/plain");
+			assert_eq!(resp.statuscode(), code);
+			assert_eq!(resp.body().unwrap().as_ref(), body);
+		}
 	}
 }
\ No newline at end of file
@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use crate::h2tp::handler::{BoxedFuture, Handler};
+use crate::h2tp::headers::hns;
+use crate::h2tp::request::Request;
+use crate::h2tp::response::Response;
+use crate::h2tp::status_code::StatusCode;
+
+// The pattern of the route a request matched, `/users/:id` for `/users/42`. Logs and metrics
+// should group by it rather than by the concrete path.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MatchedRoute(pub String);
+
+// Values of the `:name` segments of the matched route.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RouteParams(pub HashMap<String, String>);
+
+impl RouteParams {
+	pub fn get(&self, name: &str) -> Option<&str> {
+		return self.0.get(name).map(|v| v.as_str());
+	}
+}
+
+struct Route {
+	method: String,
+	pattern: String,
+	segments: Vec<String>,
+	handler: Arc<dyn Handler + Send + Sync>,
+}
+
+impl Route {
+	// the params of `path` if it matches, segments starting with `:` match any one segment
+	fn matches(&self, path: &str) -> Option<HashMap<String, String>> {
+		let mut params = HashMap::new();
+		let mut parts = path.trim_start_matches('/').split('/');
+		for seg in self.segments.iter() {
+			let part = parts.next()?;
+			match seg.strip_prefix(':') {
+				Some(name) => {
+					if part.is_empty() {
+						return None;
+					}
+					params.insert(name.to_string(), part.to_string());
+				}
+				None => {
+					if seg != part {
+						return None;
+					}
+				}
+			}
+		}
+		if parts.next().is_some() {
+			return None;
+		}
+		return Some(params);
+	}
+}
+
+// Dispatches on method and path, the first route added that matches wins. Paths no route
+// matches are answered with 404, known paths with another method with 405.
+pub struct Router {
+	routes: Vec<Route>,
+}
+
+impl Router {
+	pub fn new() -> Self {
+		return Self { routes: vec![] };
+	}
+
+	pub fn route(&mut self, method: &str, pattern: &str, handler: Arc<dyn Handler + Send + Sync>) -> &mut Self {
+		self.routes.push(Route {
+			method: method.to_string(),
+			pattern: pattern.to_string(),
+			segments: pattern.trim_start_matches('/').split('/').map(|s| s.to_string()).collect(),
+			handler,
+		});
+		return self;
+	}
+}
+
+impl Handler for Router {
+	fn handle(&self, mut req: Request) -> BoxedFuture {
+		let path = req.uri().path().to_string();
+		let mut allowed: Vec<&str> = vec![];
+		for route in self.routes.iter() {
+			let params = match route.matches(path.as_str()) {
+				Some(v) => {
+					v
+				}
+				None => {
+					continue;
+				}
+			};
+			if route.method != req.method() {
+				if !allowed.contains(&route.method.as_str()) {
+					allowed.push(route.method.as_str());
+				}
+				continue;
+			}
+			req.extensions_mut().insert(MatchedRoute(route.pattern.clone()));
+			req.extensions_mut().insert(RouteParams(params));
+			return route.handler.handle(req);
+		}
+
+		let mut resp = Response::with_status(if allowed.is_empty() { StatusCode::NotFound } else { StatusCode::MethodNotAllowed });
+		if !allowed.is_empty() {
+			resp.builder().headers().append(hns::ALLOW, allowed.join(", ").as_str());
+		}
+		return Box::pin(async move {
+			return Ok(resp);
+		});
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::Arc;
+	use crate::h2tp::cfg::Config;
+	use crate::h2tp::FuncHandler;
+	use crate::h2tp::handler::Handler;
+	use crate::h2tp::request::Request;
+	use crate::h2tp::response::Response;
+	use crate::h2tp::router::{MatchedRoute, RouteParams, Router};
+
+	async fn request(raw: &[u8]) -> Request {
+		let mut stream = raw;
+		let mut req = Request::new();
+		assert!(req.from(&mut stream, &Config::new()).await.is_none());
+		return req;
+	}
+
+	#[tokio::test]
+	async fn test_matched_route() {
+		let echo = Arc::new(FuncHandler::new(|req| {
+			Box::pin(async move {
+				let route = req.extensions().get::<MatchedRoute>().unwrap();
+				let params = req.extensions().get::<RouteParams>().unwrap();
+				let mut resp = Response::new();
+				resp.builder().body(format!("{} id={}", route.0, params.get("id").unwrap_or("-")).as_bytes());
+				return Ok(resp);
+			})
+		}));
+		let mut router = Router::new();
+		router.route("GET", "/users", echo.clone())
+			.route("GET", "/users/:id", echo.clone())
+			.route("DELETE", "/users/:id", echo);
+
+		let resp = router.handle(request(b"GET /users/42?full=1 HTTP/1.1\r\n\r\n").await).await.unwrap();
+		assert_eq!(resp.body().unwrap().as_ref(), b"/users/:id id=42");
+		let resp = router.handle(request(b"GET /users HTTP/1.1\r\n\r\n").await).await.unwrap();
+		assert_eq!(resp.body().unwrap().as_ref(), b"/users id=-");
+
+		let resp = router.handle(request(b"GET /users/42/posts HTTP/1.1\r\n\r\n").await).await.unwrap();
+		assert_eq!(resp.statuscode(), 404);
+		let resp = router.handle(request(b"PUT /users/42 HTTP/1.1\r\n\r\n").await).await.unwrap();
+		assert_eq!(resp.statuscode(), 405);
+		assert_eq!(resp.headers().unwrap().get("allow").unwrap(), "GET, DELETE");
+	}
+}
\ No newline at end of file
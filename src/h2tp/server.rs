@@ -1,21 +1,26 @@
 use core::fmt;
+use std::any::Any;
 use std::fs::File;
 use std::io::BufReader;
+use std::net::SocketAddr;
 use std::path::{Path};
 use std::sync::{Arc};
-use std::sync::atomic::{AtomicBool, AtomicU64};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize};
+use std::task::Poll;
 use std::time::Duration;
-use tokio::net::{TcpListener};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpSocket, TcpStream};
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel};
 use tokio::sync::Mutex;
 use tokio::time::sleep;
 use tokio_rustls::{TlsAcceptor};
 use tokio_rustls::rustls::{Certificate, PrivateKey, ServerConfig};
-use crate::h2tp::conn::Conn;
-use crate::h2tp::cfg::ATOMIC_ORDERING;
+use crate::h2tp::conn::{Conn, Connect, Tunnel};
+use crate::h2tp::cfg::{ATOMIC_ORDERING, Config, Limits};
 use crate::h2tp::FuncHandler;
 use crate::h2tp::handler::Handler;
 use crate::h2tp::response::Response;
+use crate::h2tp::types::{BoxedReader, BoxedWriter, State};
 
 struct Tls {
 	cert: String,
@@ -51,14 +56,117 @@ impl Tls {
 }
 
 pub struct Server {
-	listener: Option<TcpListener>,
+	listeners: Vec<TcpListener>,
 	tls: Option<Tls>,
 	shutdown_signal_receiver: UnboundedReceiver<()>,
 	shutdown_done_sender: UnboundedSender<()>,
 	shutdownhandler: Arc<Mutex<ShutdownHandler>>,
+	state: Option<State>,
+	cfg: Config,
 }
 
 
+// Collects the configuration of a `Server`, `build` checks it before handing out the server.
+pub struct ServerBuilder {
+	cfg: Config,
+	tls: Option<Tls>,
+	state: Option<State>,
+}
+
+impl ServerBuilder {
+	pub fn new() -> Self {
+		return Self {
+			cfg: Config::new(),
+			tls: None,
+			state: None,
+		};
+	}
+
+	pub fn max_body_bytes(&mut self, n: usize) -> &mut Self {
+		self.cfg.limits.max_body_bytes = n;
+		return self;
+	}
+
+	pub fn read_timeout(&mut self, d: Duration) -> &mut Self {
+		self.cfg.limits.read_timeout = Some(d);
+		return self;
+	}
+
+	pub fn limits(&mut self, limits: Limits) -> &mut Self {
+		self.cfg.limits = limits;
+		return self;
+	}
+
+	pub fn write_timeout(&mut self, d: Duration) -> &mut Self {
+		self.cfg.write_timeout = Some(d);
+		return self;
+	}
+
+	pub fn keep_alive(&mut self, v: bool) -> &mut Self {
+		self.cfg.keep_alive = v;
+		return self;
+	}
+
+	pub fn server_header(&mut self, v: Option<&str>) -> &mut Self {
+		self.cfg.server_header = v.map(str::to_string);
+		return self;
+	}
+
+	pub fn max_concurrent_requests(&mut self, n: usize) -> &mut Self {
+		self.cfg.max_concurrent_requests = Some(n);
+		return self;
+	}
+
+	pub fn worker_threads(&mut self, n: usize) -> &mut Self {
+		self.cfg.worker_threads = Some(n);
+		return self;
+	}
+
+	pub fn listen_backlog(&mut self, n: u32) -> &mut Self {
+		self.cfg.listen_backlog = n;
+		return self;
+	}
+
+	pub fn tls(&mut self, cert: &str, key: &str) -> &mut Self {
+		self.tls = Some(Tls { cert: cert.to_string(), key: key.to_string() });
+		return self;
+	}
+
+	pub fn state<T: Any + Send + Sync>(&mut self, v: Arc<T>) -> &mut Self {
+		self.state = Some(v);
+		return self;
+	}
+
+	// the remaining options, for everything without a setter of its own
+	pub fn config(&mut self) -> &mut Config {
+		return &mut self.cfg;
+	}
+
+	pub fn build(&mut self) -> Result<Server, String> {
+		let zero = Duration::from_secs(0);
+		if self.cfg.limits.timeouts().contains(&zero) || self.cfg.write_timeout == Some(zero) {
+			return Err("timeouts must not be zero".to_string());
+		}
+		if self.cfg.max_concurrent_requests == Some(0) {
+			return Err("max_concurrent_requests must not be zero".to_string());
+		}
+		if self.cfg.listen_backlog == 0 {
+			return Err("listen_backlog must not be zero".to_string());
+		}
+		if self.cfg.worker_threads == Some(0) {
+			return Err("worker_threads must not be zero".to_string());
+		}
+		if self.cfg.limits.max_pipelined_requests == Some(0) {
+			return Err("limits.max_pipelined_requests must not be zero".to_string());
+		}
+		let mut server = Server::new();
+		server.cfg = self.cfg.clone();
+		server.tls = self.tls.take();
+		server.state = self.state.take();
+		return Ok(server);
+	}
+}
+
 pub struct ShutdownHandler {
 	signal_sender: UnboundedSender<()>,
 	done_receiver: UnboundedReceiver<()>,
@@ -87,17 +195,89 @@ pub trait PrintableToSocketAddrs: tokio::net::ToSocketAddrs + fmt::Display + Cop
 
 impl<T> PrintableToSocketAddrs for T where T: tokio::net::ToSocketAddrs + fmt::Display + Copy {}
 
+// Like `TcpListener::bind`, with the accept queue length of the socket given explicitly.
+async fn bind<Addr: tokio::net::ToSocketAddrs>(addr: Addr, backlog: u32) -> std::io::Result<TcpListener> {
+	let mut last_err: Option<std::io::Error> = None;
+	for a in tokio::net::lookup_host(addr).await? {
+		let socket = if a.is_ipv4() { TcpSocket::new_v4()? } else { TcpSocket::new_v6()? };
+		#[cfg(unix)]
+		socket.set_reuseaddr(true)?;
+		match socket.bind(a) {
+			Ok(_) => {}
+			Err(e) => {
+				last_err = Some(e);
+				continue;
+			}
+		}
+		match socket.listen(backlog) {
+			Ok(listener) => {
+				return Ok(listener);
+			}
+			Err(e) => {
+				last_err = Some(e);
+			}
+		}
+	}
+	return Err(last_err.unwrap_or_else(|| {
+		std::io::Error::new(std::io::ErrorKind::InvalidInput, "could not resolve to any address")
+	}));
+}
+
+async fn run_tunnel<R, W>(cfg: &Config, conn: Conn<R, W>, mut connect: Connect)
+	where R: AsyncRead + Send + Unpin + 'static, W: AsyncWrite + Send + Unpin + 'static {
+	let upgrade = connect.take_upgrade();
+	let tunnel = conn.into_tunnel(connect);
+	let handler = match upgrade.as_ref() {
+		Some(h) => {
+			Some(h)
+		}
+		None => {
+			if tunnel.request.is_some() { cfg.h2c_handler.as_ref() } else { cfg.tunnel_handler.as_ref() }
+		}
+	};
+	match handler {
+		Some(h) => {
+			h(Tunnel {
+				authority: tunnel.authority,
+				buffered: tunnel.buffered,
+				request: tunnel.request,
+				r: Box::new(tunnel.r) as BoxedReader,
+				w: Box::new(tunnel.w) as BoxedWriter,
+			}).await;
+		}
+		None => {}
+	}
+}
+
+// The first connection waiting on any of `listeners`, looking at them from `first` on so that
+// a busy one does not keep the others waiting.
+async fn accept_any(listeners: &[TcpListener], first: usize) -> std::io::Result<(TcpStream, SocketAddr)> {
+	return std::future::poll_fn(|cx| {
+		for i in 0..listeners.len() {
+			match listeners[(first + i) % listeners.len()].poll_accept(cx) {
+				Poll::Ready(result) => {
+					return Poll::Ready(result);
+				}
+				Poll::Pending => {}
+			}
+		}
+		return Poll::Pending;
+	}).await;
+}
+
 impl Server {
 	pub fn new() -> Self {
 		let (stx, srx) = unbounded_channel();
 		let (dtx, drx) = unbounded_channel();
 
 		return Self {
-			listener: None,
+			listeners: vec![],
 			tls: None,
 			shutdown_signal_receiver: srx,
 			shutdown_done_sender: dtx,
 			shutdownhandler: Arc::new(Mutex::new(ShutdownHandler { signal_sender: stx, done_receiver: drx })),
+			state: None,
+			cfg: Config::new(),
 		};
 	}
 
@@ -105,13 +285,63 @@ impl Server {
 		self.tls = Some(Tls { cert: cert.to_string(), key: key.to_string() });
 	}
 
+	pub fn state<T: Any + Send + Sync>(&mut self, v: Arc<T>) {
+		self.state = Some(v);
+	}
+
+	pub fn config(&mut self) -> &mut Config {
+		return &mut self.cfg;
+	}
+
 	pub fn shutdownhandler(&self) -> Arc<Mutex<ShutdownHandler>> {
 		return self.shutdownhandler.clone();
 	}
 
 	pub async fn listen<'a, Addr: PrintableToSocketAddrs>(&mut self, addr: Addr, h: Option<Arc<dyn Handler + Send + Sync>>) {
-		self.listener = Some(TcpListener::bind(addr).await.unwrap());
+		self.bind(addr).await.unwrap();
+		println!("Listening @ {}...", addr);
+		self.serve(h).await;
+	}
 
+	// Serves on a multi-threaded runtime of its own until the shutdown signal, for callers
+	// that do not run one. Blocks the calling thread, which must not be a runtime thread.
+	pub fn run_blocking<Addr: PrintableToSocketAddrs>(self, addr: Addr, h: Option<Arc<dyn Handler + Send + Sync>>) -> std::io::Result<()> {
+		let mut builder = tokio::runtime::Builder::new_multi_thread();
+		match self.cfg.worker_threads {
+			Some(n) => {
+				builder.worker_threads(n);
+			}
+			None => {}
+		}
+		return self.block_on(builder.enable_all().build()?, addr, h);
+	}
+
+	fn block_on<Addr: PrintableToSocketAddrs>(mut self, rt: tokio::runtime::Runtime, addr: Addr, h: Option<Arc<dyn Handler + Send + Sync>>) -> std::io::Result<()> {
+		return rt.block_on(async move {
+			self.bind(addr).await?;
+			println!("Listening @ {}...", addr);
+			self.serve(h).await;
+			return Ok(());
+		});
+	}
+
+	// Binds a listening socket, the returned address has the actual port when binding to port 0.
+	// Every call adds one, `serve` accepts on all of them.
+	pub async fn bind<Addr: tokio::net::ToSocketAddrs>(&mut self, addr: Addr) -> std::io::Result<SocketAddr> {
+		let listener = bind(addr, self.cfg.listen_backlog).await?;
+		let local = listener.local_addr()?;
+		self.listeners.push(listener);
+		return Ok(local);
+	}
+
+	// a socket bound elsewhere, served like the ones of `bind`
+	pub fn add_listener(&mut self, listener: TcpListener) {
+		self.listeners.push(listener);
+	}
+
+	// Accepts connections on the sockets of `bind` until the shutdown signal. Connections of every
+	// socket share the handler, the limits and the shutdown.
+	pub async fn serve(&mut self, h: Option<Arc<dyn Handler + Send + Sync>>) {
 		let mut tls_acceptor: Option<TlsAcceptor> = None;
 		match self.tls.as_ref() {
 			Some(tls) => {
@@ -122,11 +352,12 @@ impl Server {
 			None => {}
 		}
 
-		println!("Listening @ {}...", addr);
-
+		let cfg = Arc::new(self.cfg.clone());
 		let alive_conn_count = Arc::new(AtomicU64::new(0));
+		let inflight = Arc::new(AtomicUsize::new(0));
 		let closing = Arc::new(AtomicBool::new(false));
-		let lref = self.listener.as_ref().unwrap();
+		assert!(!self.listeners.is_empty(), "serve needs a bound socket");
+		let mut first = 0;
 
 		let handler = match h {
 			Some(v) => {
@@ -135,7 +366,9 @@ impl Server {
 			None => {
 				Arc::new(FuncHandler::new(|_| {
 					Box::pin(async {
-						return Ok(Response::new());
+						let mut resp = Response::new();
+						resp.builder().body(b"Hello World");
+						return Ok(resp);
 					})
 				})) as _
 			}
@@ -143,12 +376,16 @@ impl Server {
 
 		loop {
 			tokio::select! {
-				result = lref.accept() => {
+				result = accept_any(self.listeners.as_slice(), first) => {
+					first = (first + 1) % self.listeners.len();
 					match result {
-						Ok((mut stream, addr)) => {
+						Ok((stream, addr)) => {
 							let accc = Arc::clone(&alive_conn_count);
 							let cc = Arc::clone(&closing);
 							let hc = Arc::clone(&handler);
+							let sc = self.state.clone();
+							let cfgc = Arc::clone(&cfg);
+							let ic = Arc::clone(&inflight);
 
 							match tls_acceptor.as_ref() {
 								Some(tls)=>{
@@ -160,8 +397,14 @@ impl Server {
 												// https://github.com/rustls/rustls/issues/288
 												// https://github.com/tokio-rs/tokio/issues/1108
 												let (r, w) = tokio::io::split(tls_stream);
-												let mut conn = Conn::new(addr, r, w, cc);
-												conn.as_server(hc).await;
+												let mut conn = Conn::new(addr, r, w, cc, sc, Arc::clone(&cfgc));
+												conn.inflight(ic);
+												match conn.as_server(hc).await {
+													Some(connect) => {
+														run_tunnel(&cfgc, conn, connect).await;
+													}
+													None => {}
+												}
 												accc.fetch_sub(1, ATOMIC_ORDERING);
 											}
 											Err(_) => {}
@@ -171,9 +414,15 @@ impl Server {
 								None=>{
 									tokio::spawn(async move {
 										accc.fetch_add(1, ATOMIC_ORDERING);
-										let (r, w) = stream.split();
-										let mut conn = Conn::new(addr, r, w, cc);
-										conn.as_server(hc).await;
+										let (r, w) = stream.into_split();
+										let mut conn = Conn::new(addr, r, w, cc, sc, Arc::clone(&cfgc));
+										conn.inflight(ic);
+										match conn.as_server(hc).await {
+											Some(connect) => {
+												run_tunnel(&cfgc, conn, connect).await;
+											}
+											None => {}
+										}
 										accc.fetch_sub(1, ATOMIC_ORDERING);
 									});
 								}
@@ -201,3 +450,111 @@ impl Server {
 		self.shutdown_done_sender.send(()).err();
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use tokio::io::{AsyncReadExt, AsyncWriteExt};
+	use tokio::net::TcpStream;
+	use std::time::Duration;
+	use crate::h2tp::cfg::Limits;
+	use crate::h2tp::client::Client;
+	use crate::h2tp::server::{Server, ServerBuilder, bind};
+
+	#[tokio::test]
+	async fn test_bind_backlog() {
+		let listener = bind("127.0.0.1:0", 8).await.unwrap();
+		let addr = listener.local_addr().unwrap();
+		let accepting = tokio::spawn(async move {
+			let (mut stream, _) = listener.accept().await.unwrap();
+			stream.write_all(b"ok").await.unwrap();
+		});
+
+		let mut client = TcpStream::connect(addr).await.unwrap();
+		let mut buf = [0u8; 2];
+		client.read_exact(&mut buf).await.unwrap();
+		assert_eq!(&buf, b"ok");
+		accepting.await.unwrap();
+
+		assert!(bind("256.0.0.1:0", 8).await.is_err());
+	}
+
+	#[test]
+	fn test_builder() {
+		let mut server = ServerBuilder::new()
+			.max_body_bytes(1 << 20)
+			.read_timeout(Duration::from_secs(5))
+			.keep_alive(false)
+			.server_header(None)
+			.max_concurrent_requests(64)
+			.build()
+			.unwrap();
+		let cfg = server.config();
+		assert_eq!(cfg.limits.max_body_bytes, 1 << 20);
+		assert_eq!(cfg.limits.read_timeout, Some(Duration::from_secs(5)));
+		assert!(!cfg.keep_alive);
+		assert!(cfg.server_header.is_none());
+		assert_eq!(cfg.max_concurrent_requests, Some(64));
+		// untouched options keep their defaults
+		assert_eq!(cfg.write_timeout, Some(Duration::from_secs(30)));
+
+		assert!(ServerBuilder::new().read_timeout(Duration::from_secs(0)).build().is_err());
+		let mut limits = Limits::new();
+		limits.keep_alive_timeout(Some(Duration::from_secs(0)));
+		assert!(ServerBuilder::new().limits(limits).build().is_err());
+		let mut limits = Limits::new();
+		limits.max_pipelined_requests(Some(0));
+		assert!(ServerBuilder::new().limits(limits).build().is_err());
+		assert!(ServerBuilder::new().listen_backlog(0).build().is_err());
+	}
+
+	#[test]
+	fn test_run_blocking() {
+		// a free port, run_blocking binds by itself
+		let addr = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+		let server = Server::new();
+		let shutdownhandler = server.shutdownhandler();
+		let serving = std::thread::spawn(move || {
+			let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+			return server.block_on(rt, addr, None);
+		});
+
+		let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+		rt.block_on(async {
+			let mut client = loop {
+				match Client::connect(addr).await {
+					Ok(c) => {
+						break c;
+					}
+					Err(_) => {
+						tokio::time::sleep(Duration::from_millis(10)).await;
+					}
+				}
+			};
+			let resp = client.request("GET", "/", &[("Connection", "close")], b"").await.unwrap();
+			assert_eq!(resp.body().unwrap().as_ref(), b"Hello World");
+			drop(client);
+			assert!(shutdownhandler.lock().await.shutdown(5000).await);
+		});
+		assert!(serving.join().unwrap().is_ok());
+	}
+
+	#[tokio::test]
+	async fn test_multiple_listeners() {
+		let mut server = Server::new();
+		let v4 = server.bind("127.0.0.1:0").await.unwrap();
+		let other = server.bind("127.0.0.1:0").await.unwrap();
+		assert_ne!(v4.port(), other.port());
+		let shutdownhandler = server.shutdownhandler();
+		let serving = tokio::spawn(async move {
+			server.serve(None).await;
+		});
+
+		for addr in [v4, other, v4] {
+			let mut client = Client::connect(addr).await.unwrap();
+			let resp = client.request("GET", "/", &[("Connection", "close")], b"").await.unwrap();
+			assert_eq!(resp.body().unwrap().as_ref(), b"Hello World");
+		}
+		assert!(shutdownhandler.lock().await.shutdown(5000).await);
+		serving.await.unwrap();
+	}
+}
\ No newline at end of file
@@ -0,0 +1,91 @@
+use crate::h2tp::response::ResponseWriter;
+
+// Server-Sent Events over a streaming response, see `Response::event_stream`: the stream of
+// events is the body and ends when this is dropped. Every event goes out as soon as it is sent.
+pub struct EventStream {
+	w: ResponseWriter,
+}
+
+fn invalid(msg: &'static str) -> std::io::Error {
+	return std::io::Error::new(std::io::ErrorKind::InvalidInput, msg);
+}
+
+impl EventStream {
+	pub fn new(w: ResponseWriter) -> Self {
+		return Self { w };
+	}
+
+	// `data` may span lines, each goes out as a `data:` line of the same event. CR LF, CR and LF
+	// all end a line (HTML 9.2.5), a lone CR must not start a field of its own.
+	pub async fn send(&mut self, event: Option<&str>, id: Option<&str>, data: &str) -> std::io::Result<()> {
+		let mut buf = String::with_capacity(data.len() + 32);
+		match event {
+			Some(v) => {
+				if v.contains(['\r', '\n']) {
+					return Err(invalid("event name with a line break"));
+				}
+				buf.push_str("event: ");
+				buf.push_str(v);
+				buf.push('\n');
+			}
+			None => {}
+		}
+		match id {
+			Some(v) => {
+				if v.contains(['\r', '\n', '\0']) {
+					return Err(invalid("event id with a line break or NUL"));
+				}
+				buf.push_str("id: ");
+				buf.push_str(v);
+				buf.push('\n');
+			}
+			None => {}
+		}
+		for line in data.replace("\r\n", "\n").split(['\r', '\n']) {
+			buf.push_str("data: ");
+			buf.push_str(line);
+			buf.push('\n');
+		}
+		buf.push('\n');
+		return self.w.write(buf.as_bytes()).await;
+	}
+
+	// a comment line, keeps idle connections from being dropped by proxies
+	pub async fn heartbeat(&mut self) -> std::io::Result<()> {
+		return self.w.write(b": ping\n\n").await;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::h2tp::cfg::Config;
+	use crate::h2tp::response::{Response, ResponseContext};
+
+	#[tokio::test]
+	async fn test_event_stream() {
+		let mut cfg = Config::new();
+		cfg.date_header = false;
+		cfg.server_header = None;
+		let (mut resp, mut stream) = Response::event_stream();
+		stream.send(None, None, "hello").await.unwrap();
+		stream.heartbeat().await.unwrap();
+		stream.send(Some("update"), Some("42"), "line 1\nline 2\r\nline 3").await.unwrap();
+		// a CR alone ends the line too, the rest stays data
+		stream.send(None, None, "a\rretry: 1").await.unwrap();
+		assert!(stream.send(Some("bad\nname"), None, "x").await.is_err());
+		drop(stream);
+
+		let ctx = ResponseContext { http10: true, ..ResponseContext::default() };
+		resp.finalize_with(&ctx, &cfg);
+		let mut out: Vec<u8> = vec![];
+		resp.write_stream_to(&mut out, &cfg).await.unwrap();
+		assert_eq!(
+			String::from_utf8(out).unwrap(),
+			"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n\
+			data: hello\n\n\
+			: ping\n\n\
+			event: update\nid: 42\ndata: line 1\ndata: line 2\ndata: line 3\n\n\
+			data: a\ndata: retry: 1\n\n",
+		);
+	}
+}
\ No newline at end of file
@@ -0,0 +1,114 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hasher;
+use crate::h2tp::handler::{BoxedFuture, Handler};
+use crate::h2tp::headers::hns;
+use crate::h2tp::methods;
+use crate::h2tp::request::Request;
+use crate::h2tp::response::Response;
+use crate::h2tp::status_code::StatusCode;
+
+// whether `If-None-Match` lists `etag` or is `*`, compared weakly (RFC 9110 13.1.2)
+fn none_match(if_none_match: &str, etag: &str) -> bool {
+	return if_none_match.split(',').any(|v| {
+		let v = v.trim();
+		return v == "*" || v.strip_prefix("W/").unwrap_or(v) == etag;
+	});
+}
+
+// One asset held in memory, a favicon or a health page. `GET` and `HEAD` get it with its
+// `Content-Type` and an `ETag` computed once, or the byte ranges they ask for; a matching
+// `If-None-Match` gets 304 and anything else 405.
+pub struct StaticHandler {
+	content_type: String,
+	body: Vec<u8>,
+	etag: String,
+}
+
+impl StaticHandler {
+	pub fn new(content_type: &str, body: &[u8]) -> Self {
+		let mut hasher = DefaultHasher::new();
+		hasher.write(body);
+		return Self {
+			content_type: content_type.to_string(),
+			body: body.to_vec(),
+			etag: format!("\"{:016x}-{:x}\"", hasher.finish(), body.len()),
+		};
+	}
+
+	pub fn etag(&self) -> &str {
+		return self.etag.as_str();
+	}
+}
+
+impl Handler for StaticHandler {
+	fn handle(&self, req: Request) -> BoxedFuture {
+		let mut resp = Response::new();
+		let method = req.method();
+		if method != methods::GET && method != methods::HEAD {
+			resp.builder().status(StatusCode::MethodNotAllowed).headers().append(hns::ALLOW, "GET, HEAD");
+			return Box::pin(async move { return Ok(resp); });
+		}
+		let fresh = req.headers().and_then(|h| h.get_combined(hns::IF_NONE_MATCH)).is_some_and(|v| none_match(&v, &self.etag));
+		if fresh {
+			resp.builder().status(StatusCode::NotModified).headers().append(hns::E_TAG, self.etag.as_str());
+			return Box::pin(async move { return Ok(resp); });
+		}
+		resp.builder().representation(&req, &self.body, self.content_type.as_str())
+			.headers().append(hns::E_TAG, self.etag.as_str());
+		if method == methods::HEAD {
+			resp.builder().head_only();
+		}
+		return Box::pin(async move { return Ok(resp); });
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::h2tp::cfg::Config;
+	use crate::h2tp::handler::Handler;
+	use crate::h2tp::headers::mime;
+	use crate::h2tp::request::Request;
+	use crate::h2tp::static_handler::StaticHandler;
+
+	async fn fetch(handler: &StaticHandler, raw: &str) -> String {
+		let mut stream = raw.as_bytes();
+		let mut req = Request::new();
+		assert!(req.from(&mut stream, &Config::new()).await.is_none());
+		let mut resp = handler.handle(req).await.unwrap();
+		let mut cfg = Config::new();
+		cfg.server_header = None;
+		cfg.date_header = false;
+		let mut out: Vec<u8> = vec![];
+		resp.write_stream_to(&mut out, &cfg).await.unwrap();
+		return String::from_utf8(out).unwrap();
+	}
+
+	#[tokio::test]
+	async fn test_static_handler() {
+		let handler = StaticHandler::new(mime::TEXT, b"OK");
+		let etag = handler.etag().to_string();
+		assert_eq!(StaticHandler::new(mime::TEXT, b"OK").etag(), etag);
+		assert_ne!(StaticHandler::new(mime::TEXT, b"KO").etag(), etag);
+
+		let head = format!("Accept-Ranges: bytes\r\nContent-Length: 2\r\nContent-Type: text/plain\r\nEtag: {}\r\n\r\n", etag);
+		let out = fetch(&handler, "GET /health HTTP/1.1\r\n\r\n").await;
+		assert_eq!(out, format!("HTTP/1.1 200 OK\r\n{}OK", head));
+		// the same head, no body
+		let out = fetch(&handler, "HEAD /health HTTP/1.1\r\n\r\n").await;
+		assert_eq!(out, format!("HTTP/1.1 200 OK\r\n{}", head));
+
+		let out = fetch(&handler, &format!("GET / HTTP/1.1\r\nIf-None-Match: \"other\", W/{}\r\n\r\n", etag)).await;
+		assert_eq!(out, format!("HTTP/1.1 304 Not Modified\r\nEtag: {}\r\n\r\n", etag));
+		let out = fetch(&handler, "GET / HTTP/1.1\r\nIf-None-Match: \"other\"\r\n\r\n").await;
+		assert!(out.starts_with("HTTP/1.1 200 OK\r\n"));
+
+		let out = fetch(&handler, "GET / HTTP/1.1\r\nRange: bytes=1-\r\n\r\n").await;
+		assert!(out.starts_with("HTTP/1.1 206 Partial Content\r\nAccept-Ranges: bytes\r\n"));
+		assert!(out.contains("Content-Range: bytes 1-1/2\r\n"));
+		assert!(out.ends_with("\r\n\r\nK"));
+
+		let out = fetch(&handler, "POST / HTTP/1.1\r\nContent-Length: 0\r\n\r\n").await;
+		assert!(out.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+		assert!(out.contains("Allow: GET, HEAD\r\n"));
+	}
+}
\ No newline at end of file
@@ -0,0 +1,85 @@
+// A real server on an ephemeral port of 127.0.0.1, for tests that go through TCP from accept to close.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+use crate::h2tp::handler::Handler;
+use crate::h2tp::server::{Server, ShutdownHandler};
+
+pub struct TestServer {
+	addr: SocketAddr,
+	shutdownhandler: Arc<Mutex<ShutdownHandler>>,
+	task: JoinHandle<()>,
+}
+
+impl TestServer {
+	pub async fn start(handler: Arc<dyn Handler + Send + Sync>) -> Self {
+		return Self::start_with(Server::new(), handler).await;
+	}
+
+	// serves with a server configured by the caller
+	pub async fn start_with(mut server: Server, handler: Arc<dyn Handler + Send + Sync>) -> Self {
+		let addr = server.bind("127.0.0.1:0").await.unwrap();
+		let shutdownhandler = server.shutdownhandler();
+		let task = tokio::spawn(async move {
+			server.serve(Some(handler)).await;
+		});
+		return Self { addr, shutdownhandler, task };
+	}
+
+	pub fn addr(&self) -> SocketAddr {
+		return self.addr;
+	}
+
+	// sends `raw` on a new connection and returns everything received until the server closes it
+	pub async fn roundtrip(&self, raw: &[u8]) -> String {
+		let mut stream = TcpStream::connect(self.addr).await.unwrap();
+		stream.write_all(raw).await.unwrap();
+		stream.shutdown().await.unwrap();
+		let mut out = vec![];
+		stream.read_to_end(&mut out).await.unwrap();
+		return String::from_utf8_lossy(&out).to_string();
+	}
+
+	pub async fn get(&self, path: &str) -> String {
+		return self.roundtrip(format!("GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", path, self.addr).as_bytes()).await;
+	}
+
+	// waits for the server to finish its connections and stop
+	pub async fn stop(self) {
+		assert!(self.shutdownhandler.lock().await.shutdown(5000).await);
+		self.task.await.unwrap();
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::Arc;
+	use crate::h2tp::FuncHandler;
+	use crate::h2tp::response::Response;
+	use crate::h2tp::testing::TestServer;
+
+	#[tokio::test]
+	async fn test_harness() {
+		let server = TestServer::start(Arc::new(FuncHandler::new(|req| {
+			Box::pin(async move {
+				let mut resp = Response::new();
+				resp.builder().text(format!("path={}", req.path()).as_str());
+				return Ok(resp);
+			})
+		}))).await;
+		assert_ne!(server.addr().port(), 0);
+
+		let out = server.get("/hello").await;
+		assert!(out.starts_with("HTTP/1.1 200 OK\r\n"));
+		assert!(out.ends_with("\r\n\r\npath=/hello"));
+
+		// keep-alive requests on one connection
+		let out = server.roundtrip(b"GET /a HTTP/1.1\r\n\r\nGET /b HTTP/1.1\r\n\r\n").await;
+		assert!(out.contains("path=/a") && out.ends_with("path=/b"));
+		server.stop().await;
+	}
+}
\ No newline at end of file
@@ -1,3 +1,11 @@
+use std::any::Any;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use crate::h2tp::conn::{ConnMetrics, Tunnel};
+use crate::h2tp::request::Request;
+use crate::h2tp::status_code::StatusCode;
+
 pub trait AsyncReader: tokio::io::AsyncRead + Unpin {}
 
 impl<T> AsyncReader for T where T: tokio::io::AsyncRead + Unpin {}
@@ -5,3 +13,26 @@ impl<T> AsyncReader for T where T: tokio::io::AsyncRead + Unpin {}
 pub trait AsyncWriter: tokio::io::AsyncWrite + Unpin {}
 
 impl<T> AsyncWriter for T where T: tokio::io::AsyncWrite + Unpin {}
+
+
+pub type State = Arc<dyn Any + Send + Sync>;
+
+// Looks at a request before its body is read, a returned status is answered instead of calling the handler.
+pub type PreBodyCheck = Arc<dyn Fn(&Request) -> Option<StatusCode> + Send + Sync>;
+
+// Looks at the method, target and version of a request line before any header is read.
+pub type StartLineCheck = Arc<dyn Fn(&str, &str, &str) -> Option<StatusCode> + Send + Sync>;
+
+// Told about every connection once it is done.
+pub type ConnObserver = Arc<dyn Fn(&ConnMetrics) + Send + Sync>;
+
+pub type BoxedReader = Box<dyn tokio::io::AsyncRead + Send + Unpin>;
+
+pub type BoxedWriter = Box<dyn tokio::io::AsyncWrite + Send + Unpin>;
+
+// The connection as a tunnel or upgrade handler gets it, with the bytes the client sent past the request.
+pub type Upgraded = Tunnel<BoxedReader, BoxedWriter>;
+
+// Takes over the connection after a `CONNECT` request was answered with 2xx, an h2c upgrade with
+// 101, or a response with `response::Builder::upgrade` was sent.
+pub type TunnelHandler = Arc<dyn Fn(Upgraded) -> Pin<Box<dyn Future<Output=()> + Send>> + Send + Sync>;
\ No newline at end of file
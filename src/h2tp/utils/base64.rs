@@ -0,0 +1,72 @@
+const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn value(c: u8) -> Option<u32> {
+	return match c {
+		b'A'..=b'Z' => Some((c - b'A') as u32),
+		b'a'..=b'z' => Some((c - b'a' + 26) as u32),
+		b'0'..=b'9' => Some((c - b'0' + 52) as u32),
+		b'+' => Some(62),
+		b'/' => Some(63),
+		_ => None,
+	};
+}
+
+pub fn encode(src: &[u8]) -> String {
+	let mut dist = String::with_capacity(src.len().div_ceil(3) * 4);
+	for chunk in src.chunks(3) {
+		let n = chunk.iter().enumerate().fold(0u32, |n, (i, b)| { return n | ((*b as u32) << (16 - 8 * i)); });
+		for i in 0..4 {
+			if i <= chunk.len() {
+				dist.push(ALPHABET[((n >> (18 - 6 * i)) & 63) as usize] as char);
+			} else {
+				dist.push('=');
+			}
+		}
+	}
+	return dist;
+}
+
+// Standard alphabet with padding (RFC 4648 4), `None` on anything else.
+pub fn decode(src: &str) -> Option<Vec<u8>> {
+	let bytes = src.as_bytes();
+	if !bytes.len().is_multiple_of(4) {
+		return None;
+	}
+	let mut dist = Vec::with_capacity(bytes.len() / 4 * 3);
+	for (idx, chunk) in bytes.chunks(4).enumerate() {
+		let last = idx == bytes.len() / 4 - 1;
+		let pad = chunk.iter().rev().take_while(|c| { return **c == b'='; }).count();
+		if pad > 2 || (pad > 0 && !last) {
+			return None;
+		}
+		let mut n: u32 = 0;
+		for c in &chunk[..4 - pad] {
+			n = (n << 6) | value(*c)?;
+		}
+		n <<= 6 * pad;
+		dist.push((n >> 16) as u8);
+		if pad < 2 {
+			dist.push((n >> 8) as u8);
+		}
+		if pad < 1 {
+			dist.push(n as u8);
+		}
+	}
+	return Some(dist);
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::h2tp::utils::base64;
+
+	#[test]
+	fn test_base64() {
+		for (raw, encoded) in [("", ""), ("f", "Zg=="), ("fo", "Zm8="), ("foo", "Zm9v"), ("foob", "Zm9vYg=="), ("user:pass", "dXNlcjpwYXNz")] {
+			assert_eq!(base64::encode(raw.as_bytes()), encoded);
+			assert_eq!(base64::decode(encoded).unwrap(), raw.as_bytes());
+		}
+		assert!(base64::decode("Zg=").is_none());
+		assert!(base64::decode("Zg==Zg==").is_none());
+		assert!(base64::decode("Z!==").is_none());
+	}
+}
\ No newline at end of file
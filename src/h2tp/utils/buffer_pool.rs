@@ -0,0 +1,50 @@
+use std::sync::Mutex;
+use bytes::BytesMut;
+
+// Body buffers kept for the next requests instead of freed with their request. At most
+// `max_buffers` are kept, larger ones than `max_capacity` are freed as usual.
+pub struct BufferPool {
+	buffers: Mutex<Vec<BytesMut>>,
+	max_buffers: usize,
+	max_capacity: usize,
+}
+
+impl BufferPool {
+	pub fn new(max_buffers: usize, max_capacity: usize) -> Self {
+		return Self {
+			buffers: Mutex::new(Vec::with_capacity(max_buffers)),
+			max_buffers,
+			max_capacity,
+		};
+	}
+
+	// an empty buffer with room for `capacity` bytes
+	pub fn take(&self, capacity: usize) -> BytesMut {
+		let mut buf = match self.buffers.lock().unwrap().pop() {
+			Some(v) => {
+				v
+			}
+			None => {
+				return BytesMut::with_capacity(capacity);
+			}
+		};
+		buf.reserve(capacity);
+		return buf;
+	}
+
+	pub fn put(&self, mut buf: BytesMut) {
+		if buf.capacity() > self.max_capacity {
+			return;
+		}
+		buf.clear();
+		let mut buffers = self.buffers.lock().unwrap();
+		if buffers.len() < self.max_buffers {
+			buffers.push(buf);
+		}
+	}
+
+	// count of buffers waiting to be taken
+	pub fn len(&self) -> usize {
+		return self.buffers.lock().unwrap().len();
+	}
+}
\ No newline at end of file
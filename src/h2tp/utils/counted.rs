@@ -0,0 +1,86 @@
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+// A reader or writer counting the bytes that went through it.
+pub struct Counted<T> {
+	inner: T,
+	count: u64,
+}
+
+impl<T> Counted<T> {
+	pub fn new(inner: T) -> Self {
+		return Self { inner, count: 0 };
+	}
+
+	pub fn count(&self) -> u64 {
+		return self.count;
+	}
+
+	pub fn get_ref(&self) -> &T {
+		return &self.inner;
+	}
+
+	pub fn into_inner(self) -> T {
+		return self.inner;
+	}
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for Counted<T> {
+	fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+		let before = buf.filled().len();
+		let result = Pin::new(&mut self.inner).poll_read(cx, buf);
+		match &result {
+			Poll::Ready(Ok(_)) => {
+				self.count += (buf.filled().len() - before) as u64;
+			}
+			_ => {}
+		}
+		return result;
+	}
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for Counted<T> {
+	fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+		let result = Pin::new(&mut self.inner).poll_write(cx, buf);
+		match &result {
+			Poll::Ready(Ok(size)) => {
+				self.count += *size as u64;
+			}
+			_ => {}
+		}
+		return result;
+	}
+
+	fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		return Pin::new(&mut self.inner).poll_flush(cx);
+	}
+
+	fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		return Pin::new(&mut self.inner).poll_shutdown(cx);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use tokio::io::{AsyncReadExt, AsyncWriteExt};
+	use crate::h2tp::utils::counted::Counted;
+
+	#[tokio::test]
+	async fn test_counted() {
+		let mut r = Counted::new(&b"0123456789"[..]);
+		let mut buf = [0u8; 4];
+		r.read_exact(&mut buf).await.unwrap();
+		assert_eq!(r.count(), 4);
+		let mut rest = vec![];
+		r.read_to_end(&mut rest).await.unwrap();
+		assert_eq!(r.count(), 10);
+
+		let mut w = Counted::new(Vec::new());
+		w.write_all(b"Hello").await.unwrap();
+		w.write_all(b", World").await.unwrap();
+		assert_eq!(w.count(), 12);
+		assert_eq!(w.into_inner(), b"Hello, World");
+	}
+}
\ No newline at end of file
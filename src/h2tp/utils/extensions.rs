@@ -0,0 +1,72 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+
+type AnyBox = Box<dyn Any + Send + Sync>;
+
+pub struct Extensions {
+	map: Option<HashMap<TypeId, AnyBox>>,
+}
+
+impl Extensions {
+	pub fn new() -> Self {
+		return Self {
+			map: None,
+		};
+	}
+
+	pub fn insert<T: Any + Send + Sync>(&mut self, v: T) -> Option<T> {
+		if self.map.is_none() {
+			self.map = Some(HashMap::new());
+		}
+		return match self.map.as_mut().unwrap().insert(TypeId::of::<T>(), Box::new(v)) {
+			Some(prev) => {
+				prev.downcast::<T>().ok().map(|b| *b)
+			}
+			None => {
+				None
+			}
+		};
+	}
+
+	pub fn get<T: Any + Send + Sync>(&self) -> Option<&T> {
+		return match self.map.as_ref() {
+			Some(mapref) => {
+				mapref.get(&TypeId::of::<T>()).and_then(|b| b.downcast_ref::<T>())
+			}
+			None => {
+				None
+			}
+		};
+	}
+
+	pub fn get_mut<T: Any + Send + Sync>(&mut self) -> Option<&mut T> {
+		return match self.map.as_mut() {
+			Some(mapref) => {
+				mapref.get_mut(&TypeId::of::<T>()).and_then(|b| b.downcast_mut::<T>())
+			}
+			None => {
+				None
+			}
+		};
+	}
+
+	pub fn remove<T: Any + Send + Sync>(&mut self) -> Option<T> {
+		return match self.map.as_mut() {
+			Some(mapref) => {
+				mapref.remove(&TypeId::of::<T>()).and_then(|b| b.downcast::<T>().ok()).map(|b| *b)
+			}
+			None => {
+				None
+			}
+		};
+	}
+
+	pub fn clear(&mut self) {
+		match self.map.as_mut() {
+			Some(mapref) => {
+				mapref.clear();
+			}
+			None => {}
+		}
+	}
+}
\ No newline at end of file
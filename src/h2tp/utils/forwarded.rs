@@ -0,0 +1,90 @@
+use std::net::{IpAddr, SocketAddr};
+use crate::h2tp::headers::{Headers, hns};
+use crate::h2tp::utils::quoted_string::split_list;
+
+// The header the proxies in front of the server write their hops to. Only that one may be
+// read when the hops are checked against a list of trusted proxies: a proxy appends to the
+// header it knows and passes the other one on as the client sent it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProxyHeader {
+	Forwarded,
+	XForwardedFor,
+}
+
+// `192.0.2.1`, `192.0.2.1:80`, `[2001:db8::1]:80` or `"[2001:db8::1]"`
+fn parse_node(v: &str) -> Option<IpAddr> {
+	let v = v.trim().trim_matches('"');
+	match v.parse::<IpAddr>() {
+		Ok(ip) => {
+			return Some(ip);
+		}
+		Err(_) => {}
+	}
+	match v.parse::<SocketAddr>() {
+		Ok(addr) => {
+			return Some(addr.ip());
+		}
+		Err(_) => {}
+	}
+	return v.strip_prefix('[').and_then(|v| v.strip_suffix(']')).and_then(|v| v.parse::<IpAddr>().ok());
+}
+
+// The addresses a request was forwarded for according to `header`, client first and the last
+// proxy at the end. `None` stands for a hop given as `unknown`, an obfuscated name or garbage.
+pub fn hops(headers: &Headers, header: ProxyHeader) -> Vec<Option<IpAddr>> {
+	return match header {
+		ProxyHeader::Forwarded => {
+			match headers.get_combined(hns::FORWARDED) {
+				Some(v) => {
+					// RFC 7239: quoted values may hold `,` and `;`
+					split_list(&v, ',').into_iter().map(|element| {
+						let node = split_list(element, ';').into_iter().find_map(|pair| {
+							let (k, v) = pair.split_once('=')?;
+							return if k.trim().eq_ignore_ascii_case("for") { Some(v) } else { None };
+						});
+						return node.and_then(parse_node);
+					}).collect()
+				}
+				None => {
+					vec![]
+				}
+			}
+		}
+		ProxyHeader::XForwardedFor => {
+			match headers.get_combined(hns::X_FORWARDED_FOR) {
+				Some(v) => {
+					v.split(',').map(parse_node).collect()
+				}
+				None => {
+					vec![]
+				}
+			}
+		}
+	};
+}
+
+#[cfg(test)]
+mod tests {
+	use std::net::IpAddr;
+	use crate::h2tp::headers::Headers;
+	use crate::h2tp::utils::forwarded::{hops, ProxyHeader};
+
+	#[test]
+	fn test_hops() {
+		let ip = |v: &str| { return Some(v.parse::<IpAddr>().unwrap()); };
+		let mut headers = Headers::new();
+		headers.builder().append("X-Forwarded-For", "203.0.113.7, 10.0.0.1:8080,unknown");
+		assert_eq!(hops(&headers, ProxyHeader::XForwardedFor), vec![ip("203.0.113.7"), ip("10.0.0.1"), None]);
+		assert_eq!(hops(&headers, ProxyHeader::Forwarded), vec![]);
+
+		headers.builder().append("Forwarded", "for=192.0.2.60;proto=http;by=203.0.113.43, For=\"[2001:db8:cafe::17]:4711\"");
+		assert_eq!(hops(&headers, ProxyHeader::Forwarded), vec![ip("192.0.2.60"), ip("2001:db8:cafe::17")]);
+		// the other header is left alone
+		assert_eq!(hops(&headers, ProxyHeader::XForwardedFor), vec![ip("203.0.113.7"), ip("10.0.0.1"), None]);
+
+		// a comma or semicolon inside quotes does not start a new element
+		let mut headers = Headers::new();
+		headers.builder().append("Forwarded", "by=\"a,b;c\";for=192.0.2.1, for=\"_x,y\", for=10.0.0.1");
+		assert_eq!(hops(&headers, ProxyHeader::Forwarded), vec![ip("192.0.2.1"), None, ip("10.0.0.1")]);
+	}
+}
\ No newline at end of file
@@ -0,0 +1,164 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+const LONG_WEEKDAYS: [&str; 7] = ["Sunday", "Monday", "Tuesday", "Wednesday", "Thursday", "Friday", "Saturday"];
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+// days since 1970-01-01 -> (year, month 1..=12, day 1..=31)
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+	let z = days + 719468;
+	let era = if z >= 0 { z } else { z - 146096 } / 146097;
+	let doe = z - era * 146097;
+	let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+	let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+	let mp = (5 * doy + 2) / 153;
+	let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+	let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+	let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+	return (year, month, day);
+}
+
+// (year, month 1..=12, day 1..=31) -> days since 1970-01-01
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+	let year = if month <= 2 { year - 1 } else { year };
+	let era = if year >= 0 { year } else { year - 399 } / 400;
+	let yoe = year - era * 400;
+	let mp = if month > 2 { month - 3 } else { month + 9 } as i64;
+	let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+	let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+	return era * 146097 + doe - 719468;
+}
+
+fn month(s: &str) -> Option<u32> {
+	return MONTHS.iter().position(|m| *m == s).map(|i| i as u32 + 1);
+}
+
+// "08:49:37" -> seconds since midnight
+fn time_of_day(s: &str) -> Option<i64> {
+	let mut parts = s.split(':');
+	let mut secs = 0;
+	for limit in [24, 60, 61] {
+		let part = parts.next()?;
+		if part.len() != 2 || !part.bytes().all(|b| b.is_ascii_digit()) {
+			return None;
+		}
+		let v: i64 = part.parse().ok()?;
+		if v >= limit {
+			return None;
+		}
+		secs = secs * 60 + v;
+	}
+	if parts.next().is_some() {
+		return None;
+	}
+	return Some(secs);
+}
+
+fn number(s: &str, digits: std::ops::RangeInclusive<usize>) -> Option<i64> {
+	if !digits.contains(&s.len()) || !s.bytes().all(|b| b.is_ascii_digit()) {
+		return None;
+	}
+	return s.parse().ok();
+}
+
+fn to_time(year: i64, month: u32, day: i64, secs: i64) -> Option<SystemTime> {
+	if year < 1970 || !(1..=31).contains(&day) {
+		return None;
+	}
+	let days = days_from_civil(year, month, day as u32);
+	// 31 Feb and such would roll over into the next month
+	if civil_from_days(days) != (year, month, day as u32) {
+		return None;
+	}
+	return Some(UNIX_EPOCH + Duration::from_secs((days * 86400 + secs) as u64));
+}
+
+// Parses any of the three date formats of RFC 7231 7.1.1.1, `None` for anything else:
+// IMF-fixdate "Sun, 06 Nov 1994 08:49:37 GMT", RFC 850 "Sunday, 06-Nov-94 08:49:37 GMT" and
+// asctime "Sun Nov  6 08:49:37 1994". Two digit years past 69 are taken as 19xx.
+pub fn parse(s: &str) -> Option<SystemTime> {
+	let s = s.trim();
+	let parts: Vec<&str> = s.split_ascii_whitespace().collect();
+	match parts.as_slice() {
+		[wday, day, mon, year, time, "GMT"] => {
+			// IMF-fixdate
+			if wday.strip_suffix(',').is_none_or(|w| !WEEKDAYS.contains(&w)) {
+				return None;
+			}
+			return to_time(number(year, 4..=4)?, month(mon)?, number(day, 2..=2)?, time_of_day(time)?);
+		}
+		[wday, date, time, "GMT"] => {
+			// RFC 850
+			let wday = wday.strip_suffix(',')?;
+			if !LONG_WEEKDAYS.contains(&wday) {
+				return None;
+			}
+			let mut fields = date.split('-');
+			let day = number(fields.next()?, 2..=2)?;
+			let mon = month(fields.next()?)?;
+			let year = number(fields.next()?, 2..=2)?;
+			if fields.next().is_some() {
+				return None;
+			}
+			let year = if year >= 70 { 1900 + year } else { 2000 + year };
+			return to_time(year, mon, day, time_of_day(time)?);
+		}
+		[wday, mon, day, time, year] => {
+			// asctime
+			if !WEEKDAYS.contains(wday) {
+				return None;
+			}
+			return to_time(number(year, 4..=4)?, month(mon)?, number(day, 1..=2)?, time_of_day(time)?);
+		}
+		_ => {
+			return None;
+		}
+	}
+}
+
+// formats as IMF-fixdate, e.g. "Sun, 06 Nov 1994 08:49:37 GMT"
+pub fn format(t: SystemTime) -> String {
+	let secs = t.duration_since(UNIX_EPOCH).unwrap_or(Duration::ZERO).as_secs() as i64;
+	let days = secs / 86400;
+	let daysecs = secs % 86400;
+	let (year, month, day) = civil_from_days(days);
+	return format!(
+		"{}, {:02} {} {:04} {:02}:{:02}:{:02} GMT",
+		WEEKDAYS[((days + 4) % 7) as usize], day, MONTHS[(month - 1) as usize], year,
+		daysecs / 3600, (daysecs % 3600) / 60, daysecs % 60,
+	);
+}
+
+#[cfg(test)]
+mod tests {
+	use std::time::{Duration, UNIX_EPOCH};
+	use crate::h2tp::utils::http_date;
+
+	#[test]
+	fn test_format() {
+		assert_eq!(http_date::format(UNIX_EPOCH), "Thu, 01 Jan 1970 00:00:00 GMT");
+		assert_eq!(http_date::format(UNIX_EPOCH + Duration::from_secs(784111777)), "Sun, 06 Nov 1994 08:49:37 GMT");
+		assert_eq!(http_date::format(UNIX_EPOCH + Duration::from_secs(951782400)), "Tue, 29 Feb 2000 00:00:00 GMT");
+	}
+
+	#[test]
+	fn test_parse() {
+		let t = UNIX_EPOCH + Duration::from_secs(784111777);
+		for s in ["Sun, 06 Nov 1994 08:49:37 GMT", "Sunday, 06-Nov-94 08:49:37 GMT", "Sun Nov  6 08:49:37 1994"] {
+			let parsed = http_date::parse(s);
+			assert_eq!(parsed, Some(t), "{}", s);
+			assert_eq!(http_date::format(parsed.unwrap()), "Sun, 06 Nov 1994 08:49:37 GMT");
+		}
+		assert_eq!(http_date::parse("Tue, 29 Feb 2000 00:00:00 GMT"), Some(UNIX_EPOCH + Duration::from_secs(951782400)));
+		assert_eq!(http_date::parse("Thursday, 01-Jan-70 00:00:00 GMT"), Some(UNIX_EPOCH));
+		assert_eq!(http_date::parse("Monday, 01-Jan-24 00:00:00 GMT"), http_date::parse("Mon, 01 Jan 2024 00:00:00 GMT"));
+
+		for s in [
+			"", "Sun, 06 Nov 1994 08:49:37 UTC", "Sun, 6 Nov 1994 08:49:37 GMT", "Sun, 06 Foo 1994 08:49:37 GMT",
+			"Sun, 31 Feb 1994 08:49:37 GMT", "Sun, 06 Nov 1994 24:00:00 GMT", "Sun, 06 Nov 1994 08:49 GMT",
+			"Sun 06 Nov 1994 08:49:37 GMT", "Sunday, 06-Nov-1994 08:49:37 GMT", "Sun Nov  6 08:49:37 94",
+		] {
+			assert!(http_date::parse(s).is_none(), "{}", s);
+		}
+	}
+}
\ No newline at end of file
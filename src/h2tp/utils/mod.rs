@@ -1,3 +1,14 @@
+pub mod base64;
+pub mod buffer_pool;
+pub mod counted;
+pub mod extensions;
+pub mod forwarded;
+pub mod http_date;
 pub mod multi_map;
+pub mod peekable;
+pub mod quoted_string;
+pub mod range;
+pub mod temp_file;
+pub mod uri;
 pub mod uricoding;
 mod uricoding_excepts;
@@ -1,17 +1,13 @@
 use std::collections::HashMap;
 
-type Values = Vec<String>;
+type Values<V> = Vec<V>;
 
-fn values(v: &str) -> Values {
-	return Values::from(vec![v.to_string()]);
-}
-
-struct AryMap {
+struct AryMap<V> {
 	keys: Vec<String>,
-	vals: Vec<Values>,
+	vals: Vec<Values<V>>,
 }
 
-impl AryMap {
+impl<V> AryMap<V> {
 	fn new() -> Self {
 		return Self {
 			keys: vec![],
@@ -23,183 +19,134 @@ impl AryMap {
 		return self.keys.iter().position(|e| { return e == k; });
 	}
 
-	fn append(&mut self, k: &str, v: &str) {
-		match self.idx(k) {
-			Some(idx) => {
-				let vals = &mut self.vals[idx];
-				vals.push(v.to_string());
-			}
-			None => {
-				self.keys.push(k.to_string());
-				self.vals.push(values(v));
-			}
-		}
-	}
-
 	fn clear(&mut self) {
 		self.keys.clear();
 		self.vals.clear();
 	}
-
-	fn remove(&mut self, k: &str) {
-		match self.idx(k) {
-			Some(idx) => {
-				self.keys.remove(idx);
-				self.vals.remove(idx);
-			}
-			None => {}
-		}
-	}
-
-	fn reset(&mut self, k: &str, v: &str) {
-		match self.idx(k) {
-			Some(idx) => {
-				let vals = &mut self.vals[idx];
-				vals.clear();
-				vals.push(v.to_string());
-			}
-			None => {}
-		}
-	}
-
-	fn get(&self, k: &str) -> Option<&Vec<String>> {
-		match self.idx(k) {
-			Some(idx) => {
-				Some(&self.vals[idx])
-			}
-			None => {
-				None
-			}
-		}
-	}
 }
 
-pub struct MultiMap {
-	ary: Option<AryMap>,
-	map: Option<HashMap<String, Values>>,
+// Keys keep their insertion order, every value of a key stays at the position the key was first
+// appended at. Once there are many keys, lookups go through a hash index into the ordered storage.
+pub struct MultiMap<V = String> {
+	ary: Option<AryMap<V>>,
+	index: Option<HashMap<String, usize>>,
 }
 
-impl MultiMap {
+impl<V> MultiMap<V> {
 	pub fn new() -> Self {
 		return Self {
 			ary: None,
-			map: None,
+			index: None,
 		};
 	}
 
-	pub fn append(&mut self, k: &str, v: &str) {
-		match self.map.as_mut() {
-			Some(mapref) => {
-				let vals = mapref.get_mut(k);
-				match vals {
-					Some(valsref) => {
-						valsref.push(v.to_string());
+	fn idx(&self, k: &str) -> Option<usize> {
+		return match self.index.as_ref() {
+			Some(indexref) => {
+				indexref.get(k).copied()
+			}
+			None => {
+				match self.ary.as_ref() {
+					Some(aryref) => {
+						aryref.idx(k)
 					}
 					None => {
-						mapref.insert(k.to_string(), values(v));
+						None
 					}
 				}
 			}
-			None => {
-				if self.ary.is_none() {
-					self.ary = Some(AryMap::new());
-				}
+		};
+	}
 
-				let aryref = self.ary.as_mut().unwrap();
-				if aryref.keys.len() >= 12 {
-					self.swap_ary_to_hashmap()
-				} else {
-					aryref.append(k, v);
+	fn push(&mut self, k: &str, v: V) {
+		if self.ary.is_none() {
+			self.ary = Some(AryMap::new());
+		}
+		let aryref = self.ary.as_mut().unwrap();
+		aryref.keys.push(k.to_string());
+		aryref.vals.push(vec![v]);
+		let size = aryref.keys.len();
+		match self.index.as_mut() {
+			Some(indexref) => {
+				indexref.insert(k.to_string(), size - 1);
+			}
+			None => {
+				if size > 12 {
+					self.build_index();
 				}
 			}
 		}
 	}
 
-	fn swap_ary_to_hashmap(&mut self) {
-		let mut ary = self.ary.take().unwrap();
-		let mut map = HashMap::with_capacity(ary.keys.len());
-		while !ary.keys.is_empty() {
-			map.insert(ary.keys.pop().unwrap(), ary.vals.pop().unwrap());
+	fn build_index(&mut self) {
+		let aryref = self.ary.as_ref().unwrap();
+		let mut index = HashMap::with_capacity(aryref.keys.len());
+		for (i, k) in aryref.keys.iter().enumerate() {
+			index.insert(k.clone(), i);
 		}
-		self.map = Some(map);
+		self.index = Some(index);
 	}
 
-	pub fn clear(&mut self) {
-		match self.map.as_mut() {
-			Some(mapref) => {
-				mapref.clear();
+	pub fn append(&mut self, k: &str, v: V) {
+		match self.idx(k) {
+			Some(idx) => {
+				self.ary.as_mut().unwrap().vals[idx].push(v);
 			}
 			None => {
-				match self.ary.as_mut() {
-					Some(aryref) => {
-						aryref.clear();
-					}
-					None => {}
-				}
+				self.push(k, v);
 			}
 		}
 	}
 
-	pub fn remove(&mut self, k: &str) {
-		match self.map.as_mut() {
-			Some(mapref) => {
-				mapref.remove(k);
+	pub fn clear(&mut self) {
+		match self.ary.as_mut() {
+			Some(aryref) => {
+				aryref.clear();
 			}
-			None => {
-				match self.ary.as_mut() {
-					Some(aryref) => {
-						aryref.remove(k);
-					}
-					None => {}
+			None => {}
+		}
+		self.index = None;
+	}
+
+	pub fn remove(&mut self, k: &str) {
+		match self.idx(k) {
+			Some(idx) => {
+				let aryref = self.ary.as_mut().unwrap();
+				aryref.keys.remove(idx);
+				aryref.vals.remove(idx);
+				if self.index.is_some() {
+					self.build_index();
 				}
 			}
+			None => {}
 		}
 	}
 
-	pub fn reset(&mut self, k: &str, v: &str) {
-		match self.map.as_mut() {
-			Some(mapref) => {
-				let vals = mapref.get_mut(k);
-				match vals {
-					Some(vals) => {
-						vals.clear();
-						vals.push(v.to_string());
-					}
-					None => {
-						mapref.insert(k.to_string(), values(v));
-					}
-				}
+	pub fn reset(&mut self, k: &str, v: V) {
+		match self.idx(k) {
+			Some(idx) => {
+				let vals = &mut self.ary.as_mut().unwrap().vals[idx];
+				vals.clear();
+				vals.push(v);
 			}
 			None => {
-				match self.ary.as_mut() {
-					Some(aryref) => {
-						aryref.reset(k, v);
-					}
-					None => {}
-				}
+				self.push(k, v);
 			}
 		}
 	}
 
-	pub fn get(&self, k: &str) -> Option<&Vec<String>> {
-		return match self.map.as_ref() {
-			Some(mapref) => {
-				mapref.get(k)
+	pub fn get(&self, k: &str) -> Option<&Vec<V>> {
+		return match self.idx(k) {
+			Some(idx) => {
+				Some(&self.ary.as_ref().unwrap().vals[idx])
 			}
 			None => {
-				match self.ary.as_ref() {
-					Some(aryref) => {
-						aryref.get(k)
-					}
-					None => {
-						None
-					}
-				}
+				None
 			}
 		};
 	}
 
-	pub fn getone(&self, k: &str) -> Option<&String> {
+	pub fn getone(&self, k: &str) -> Option<&V> {
 		return match self.get(k) {
 			Some(vals) => {
 				vals.first()
@@ -210,29 +157,18 @@ impl MultiMap {
 		};
 	}
 
-	pub fn each(&self, func: fn(k: &str, v: &str)) {
-		match self.map.as_ref() {
-			Some(mapref) => {
-				for (k, valsref) in mapref.iter() {
+	pub fn each<F: FnMut(&str, &V)>(&self, mut func: F) {
+		match self.ary.as_ref() {
+			Some(aryref) => {
+				for i in 0..aryref.keys.len() {
+					let k = &aryref.keys[i];
+					let valsref = &aryref.vals[i];
 					for v in valsref.iter() {
 						func(k, v);
 					}
 				}
 			}
-			None => {
-				match self.ary.as_ref() {
-					Some(aryref) => {
-						for i in 0..aryref.keys.len() {
-							let k = &aryref.keys[i];
-							let valsref = &aryref.vals[i];
-							for v in valsref.iter() {
-								func(k, v);
-							}
-						}
-					}
-					None => {}
-				}
-			}
+			None => {}
 		}
 	}
 }
@@ -246,11 +182,11 @@ mod tests {
 	#[test]
 	fn test_mm() {
 		let mut mm = MultiMap::new();
-		mm.append("a", "1");
+		mm.append("a", "1".to_string());
 		mm.clear();
 		mm.remove("a");
-		mm.append("a", "2");
-		mm.append("a", "4");
+		mm.append("a", "2".to_string());
+		mm.append("a", "4".to_string());
 	}
 
 	#[test]
@@ -258,8 +194,20 @@ mod tests {
 		let mut mm = MultiMap::new();
 
 		for i in 0..40 {
-			mm.append(&format!("k{}", i), &format!("v{}", i));
+			mm.append(&format!("k{}", i), format!("v{}", i));
 		}
+		mm.append("k3", "again".to_string());
+		mm.remove("k0");
+		mm.reset("k39", "last".to_string());
+
+		let mut pairs = vec![];
+		mm.each(|k, v| { pairs.push(format!("{}={}", k, v)); });
+		assert_eq!(pairs.len(), 40);
+		assert_eq!(pairs[0], "k1=v1");
+		assert_eq!(pairs[2..4], ["k3=v3".to_string(), "k3=again".to_string()]);
+		assert_eq!(pairs[39], "k39=last");
+		assert_eq!(mm.getone("k20").unwrap(), "v20");
+		assert!(mm.get("k0").is_none());
 	}
 
 	struct Obj {
@@ -0,0 +1,107 @@
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, ReadBuf};
+
+// A reader whose first bytes can be looked at before anything reads them, to tell protocols
+// sharing a port apart before a `Conn` is made.
+pub struct Peekable<T> {
+	inner: T,
+	peeked: Vec<u8>,
+	// how much of `peeked` was read already
+	pos: usize,
+}
+
+impl<T> Peekable<T> {
+	pub fn new(inner: T) -> Self {
+		return Self { inner, peeked: Vec::new(), pos: 0 };
+	}
+
+	pub fn get_ref(&self) -> &T {
+		return &self.inner;
+	}
+
+	// the peeked bytes not read yet come before the ones of `inner`, they are lost here
+	pub fn into_inner(self) -> T {
+		return self.inner;
+	}
+}
+
+impl<T: AsyncRead + Unpin> Peekable<T> {
+	// The next `n` bytes without consuming them, fewer when the stream ends before that.
+	pub async fn peek(&mut self, n: usize) -> io::Result<&[u8]> {
+		let mut buf = [0u8; 512];
+		while self.peeked.len() - self.pos < n {
+			let want = (n - (self.peeked.len() - self.pos)).min(buf.len());
+			let size = self.inner.read(&mut buf[..want]).await?;
+			if size == 0 {
+				break;
+			}
+			self.peeked.extend_from_slice(&buf[..size]);
+		}
+		let end = self.peeked.len().min(self.pos + n);
+		return Ok(&self.peeked[self.pos..end]);
+	}
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for Peekable<T> {
+	fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+		if self.pos < self.peeked.len() {
+			let size = (self.peeked.len() - self.pos).min(buf.remaining());
+			let start = self.pos;
+			buf.put_slice(&self.peeked[start..start + size]);
+			self.pos += size;
+			if self.pos == self.peeked.len() {
+				self.peeked.clear();
+				self.pos = 0;
+			}
+			return Poll::Ready(Ok(()));
+		}
+		return Pin::new(&mut self.inner).poll_read(cx, buf);
+	}
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for Peekable<T> {
+	fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+		return Pin::new(&mut self.inner).poll_write(cx, buf);
+	}
+
+	fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		return Pin::new(&mut self.inner).poll_flush(cx);
+	}
+
+	fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		return Pin::new(&mut self.inner).poll_shutdown(cx);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use tokio::io::AsyncReadExt;
+	use crate::h2tp::cfg::Config;
+	use crate::h2tp::message::Message;
+	use crate::h2tp::utils::peekable::Peekable;
+
+	#[tokio::test]
+	async fn test_peek() {
+		let mut r = Peekable::new(&b"GET /peek HTTP/1.1\r\nHost: spk\r\n\r\n"[..]);
+		assert_eq!(r.peek(3).await.unwrap(), b"GET");
+		// peeking again sees the same bytes, and more of them
+		assert_eq!(r.peek(5).await.unwrap(), b"GET /");
+
+		let mut msg = Message::new();
+		assert!(msg.from(&mut r, &Config::new()).await.is_none());
+		assert_eq!(msg.startline.1, "/peek");
+		assert_eq!(msg.headers.as_ref().unwrap().get("host").unwrap(), "spk");
+	}
+
+	#[tokio::test]
+	async fn test_peek_past_end() {
+		let mut r = Peekable::new(&b"\x16\x03"[..]);
+		assert_eq!(r.peek(8).await.unwrap(), b"\x16\x03");
+		let mut rest = vec![];
+		r.read_to_end(&mut rest).await.unwrap();
+		assert_eq!(rest, b"\x16\x03");
+		assert!(r.peek(1).await.unwrap().is_empty());
+	}
+}
\ No newline at end of file
@@ -0,0 +1,125 @@
+// `quoted-string` (RFC 9110 5.6.4) at the start of `input`: its unescaped value and what follows
+// the closing quote. `None` when `input` does not start with a quote or the quote is not closed.
+pub fn parse_quoted_string(input: &str) -> Option<(String, &str)> {
+	let rest = input.strip_prefix('"')?;
+	let mut value = String::new();
+	let mut chars = rest.char_indices();
+	loop {
+		match chars.next()? {
+			(idx, '"') => {
+				return Some((value, &rest[idx + 1..]));
+			}
+			(_, '\\') => {
+				value.push(chars.next()?.1);
+			}
+			(_, c) => {
+				value.push(c);
+			}
+		}
+	}
+}
+
+// The elements of a list like `Accept` or the `;` parameters of `Content-Type`, split on `sep`
+// but not on one inside a quoted string. Elements are trimmed of whitespace, empty ones dropped
+// (RFC 9110 5.6.1); an unterminated quote runs to the end of `input`.
+pub fn split_list(input: &str, sep: char) -> Vec<&str> {
+	let mut items = vec![];
+	let mut quoted = false;
+	let mut escaped = false;
+	let mut start = 0;
+	for (idx, c) in input.char_indices() {
+		if escaped {
+			escaped = false;
+		} else if quoted && c == '\\' {
+			escaped = true;
+		} else if c == '"' {
+			quoted = !quoted;
+		} else if c == sep && !quoted {
+			items.push(input[start..idx].trim());
+			start = idx + c.len_utf8();
+		}
+	}
+	items.push(input[start..].trim());
+	items.retain(|v| !v.is_empty());
+	return items;
+}
+
+// `; name=value` parameters following the first element of a header value, as in
+// `Content-Type` or `Content-Disposition`. Names are lowercased, quoted values unquoted; a
+// `;` inside quotes does not end the value. Parsing stops at a malformed parameter.
+pub fn params(v: &str) -> Vec<(String, String)> {
+	let mut params = vec![];
+	let rest = match v.find(';') {
+		Some(idx) => {
+			&v[idx + 1..]
+		}
+		None => {
+			return params;
+		}
+	};
+	for param in split_list(rest, ';') {
+		let (name, value) = match param.split_once('=') {
+			Some((k, v)) => {
+				(k.trim(), v.trim())
+			}
+			None => {
+				return params;
+			}
+		};
+		let value = if value.starts_with('"') {
+			match parse_quoted_string(value) {
+				Some((v, r)) if r.trim().is_empty() => {
+					v
+				}
+				_ => {
+					return params;
+				}
+			}
+		} else {
+			value.to_string()
+		};
+		if !name.is_empty() {
+			params.push((name.to_ascii_lowercase(), value));
+		}
+	}
+	return params;
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{params, parse_quoted_string, split_list};
+
+	#[test]
+	fn test_parse_quoted_string() {
+		assert_eq!(parse_quoted_string("\"hello world\"; rest"), Some(("hello world".to_string(), "; rest")));
+		assert_eq!(parse_quoted_string("\"\""), Some((String::new(), "")));
+		assert_eq!(parse_quoted_string(r#""say \"hi\" \\ bye""#), Some((r#"say "hi" \ bye"#.to_string(), "")));
+		assert_eq!(parse_quoted_string(r#""\a""#), Some(("a".to_string(), "")));
+
+		assert!(parse_quoted_string("\"unterminated").is_none());
+		assert!(parse_quoted_string(r#""escaped at the end\""#).is_none());
+		assert!(parse_quoted_string("bare").is_none());
+	}
+
+	#[test]
+	fn test_split_list() {
+		assert_eq!(split_list("a, \"b,c\", d", ','), vec!["a", "\"b,c\"", "d"]);
+		assert_eq!(split_list(r#"a;b="x;\";y";c"#, ';'), vec!["a", r#"b="x;\";y""#, "c"]);
+		assert_eq!(split_list(" , a,, b ,", ','), vec!["a", "b"]);
+		assert_eq!(split_list("a, \"b, c", ','), vec!["a", "\"b, c"]);
+		assert!(split_list("", ',').is_empty());
+	}
+
+	#[test]
+	fn test_params() {
+		let p = params(r#"form-data; name="a;b"; filename="x \"1\".txt"; Size=3"#);
+		assert_eq!(p, vec![
+			("name".to_string(), "a;b".to_string()),
+			("filename".to_string(), "x \"1\".txt".to_string()),
+			("size".to_string(), "3".to_string()),
+		]);
+		assert!(params("text/plain").is_empty());
+		assert_eq!(params("text/plain; a=1; b=\"open").len(), 1);
+		assert_eq!(params("text/plain; junk; a=1").len(), 0);
+	}
+}
\ No newline at end of file
@@ -0,0 +1,184 @@
+use crate::h2tp::status_code::StatusCode;
+
+// ranges a `Range` header may ask for, past it the full representation is sent (RFC 9110 14.3)
+pub const MAX_RANGES: usize = 32;
+
+// An inclusive byte range of a representation, "bytes=0-499" -> 0..=499.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ByteRange {
+	pub start: u64,
+	pub end: u64,
+}
+
+impl ByteRange {
+	pub fn len(&self) -> u64 {
+		return self.end - self.start + 1;
+	}
+
+	// value of the `Content-Range` header of a 206 response
+	pub fn content_range(&self, total_len: u64) -> String {
+		return format!("bytes {}-{}/{}", self.start, self.end, total_len);
+	}
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum RangeError {
+	// not a valid byte range header, it should be ignored and the full representation sent
+	Malformed,
+	// none of the ranges overlaps the representation, answered with 416
+	Unsatisfiable,
+}
+
+impl RangeError {
+	pub fn statuscode(&self) -> StatusCode {
+		return match self {
+			RangeError::Malformed => StatusCode::OK,
+			RangeError::Unsatisfiable => StatusCode::RangeNotSatisfiable,
+		};
+	}
+
+	// value of the `Content-Range` header of a 416 response
+	pub fn content_range(total_len: u64) -> String {
+		return format!("bytes */{}", total_len);
+	}
+}
+
+fn parse_num(v: &str) -> Result<u64, RangeError> {
+	if v.is_empty() || !v.bytes().all(|b| b.is_ascii_digit()) {
+		return Err(RangeError::Malformed);
+	}
+	return v.parse::<u64>().map_err(|_| RangeError::Malformed);
+}
+
+// Parses a `Range` header (RFC 7233 2.1) against a representation of `total_len` bytes.
+// Ranges are clamped to the representation, ranges starting past its end are dropped. The rest
+// come back in order, overlapping and adjacent ones merged; more than `MAX_RANGES` of them makes
+// the header malformed, a client cannot blow one representation up into many copies of it.
+pub fn parse_range(header: &str, total_len: u64) -> Result<Vec<ByteRange>, RangeError> {
+	let header = header.trim();
+	let specs = match header.split_once('=') {
+		Some((unit, specs)) => {
+			if !unit.trim().eq_ignore_ascii_case("bytes") {
+				return Err(RangeError::Malformed);
+			}
+			specs
+		}
+		None => {
+			return Err(RangeError::Malformed);
+		}
+	};
+
+	if specs.split(',').filter(|spec| !spec.trim().is_empty()).count() > MAX_RANGES {
+		return Err(RangeError::Malformed);
+	}
+	let mut ranges = vec![];
+	for spec in specs.split(',') {
+		let spec = spec.trim();
+		if spec.is_empty() {
+			continue;
+		}
+		let (first, last) = match spec.split_once('-') {
+			Some(v) => {
+				v
+			}
+			None => {
+				return Err(RangeError::Malformed);
+			}
+		};
+
+		if first.is_empty() {
+			// suffix range, the last `n` bytes
+			let n = parse_num(last)?;
+			if n == 0 || total_len == 0 {
+				continue;
+			}
+			ranges.push(ByteRange { start: total_len.saturating_sub(n), end: total_len - 1 });
+			continue;
+		}
+
+		let start = parse_num(first)?;
+		let end = if last.is_empty() {
+			u64::MAX
+		} else {
+			let end = parse_num(last)?;
+			if end < start {
+				return Err(RangeError::Malformed);
+			}
+			end
+		};
+		if start >= total_len {
+			continue;
+		}
+		ranges.push(ByteRange { start, end: end.min(total_len - 1) });
+	}
+
+	if ranges.is_empty() {
+		return Err(RangeError::Unsatisfiable);
+	}
+	ranges.sort_by_key(|r| r.start);
+	let mut merged: Vec<ByteRange> = Vec::with_capacity(ranges.len());
+	for range in ranges {
+		match merged.last_mut() {
+			Some(last) if range.start <= last.end.saturating_add(1) => {
+				last.end = last.end.max(range.end);
+			}
+			_ => {
+				merged.push(range);
+			}
+		}
+	}
+	return Ok(merged);
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::h2tp::status_code::StatusCode;
+	use crate::h2tp::utils::range::{ByteRange, MAX_RANGES, parse_range, RangeError};
+
+	#[test]
+	fn test_parse_range() {
+		assert_eq!(parse_range("bytes=0-499", 1000).unwrap(), vec![ByteRange { start: 0, end: 499 }]);
+		assert_eq!(parse_range("bytes=500-", 1000).unwrap(), vec![ByteRange { start: 500, end: 999 }]);
+		assert_eq!(parse_range("bytes=-500", 1000).unwrap(), vec![ByteRange { start: 500, end: 999 }]);
+		assert_eq!(parse_range("bytes=-5000", 1000).unwrap(), vec![ByteRange { start: 0, end: 999 }]);
+		assert_eq!(parse_range("bytes=900-1999", 1000).unwrap(), vec![ByteRange { start: 900, end: 999 }]);
+		assert_eq!(
+			parse_range("bytes=0-0, 10-19 ,-1", 1000).unwrap(),
+			vec![ByteRange { start: 0, end: 0 }, ByteRange { start: 10, end: 19 }, ByteRange { start: 999, end: 999 }],
+		);
+		assert_eq!(parse_range("bytes=0-499", 1000).unwrap()[0].content_range(1000), "bytes 0-499/1000");
+	}
+
+	#[test]
+	fn test_coalesce_ranges() {
+		// overlapping and adjacent ranges become one, in order
+		assert_eq!(parse_range("bytes=0-,0-,0-", 1000).unwrap(), vec![ByteRange { start: 0, end: 999 }]);
+		assert_eq!(
+			parse_range("bytes=500-599,0-9,10-19,-450,5-7", 1000).unwrap(),
+			vec![ByteRange { start: 0, end: 19 }, ByteRange { start: 500, end: 999 }],
+		);
+
+		// too many ranges, the full representation is sent instead
+		let header = format!("bytes={}", vec!["0-"; MAX_RANGES + 1].join(","));
+		assert_eq!(parse_range(header.as_str(), 1000).unwrap_err(), RangeError::Malformed);
+		let header = format!("bytes={}", vec!["0-"; MAX_RANGES].join(","));
+		assert_eq!(parse_range(header.as_str(), 1000).unwrap().len(), 1);
+	}
+
+	#[test]
+	fn test_unsatisfiable_range() {
+		let err = parse_range("bytes=1000-", 1000).unwrap_err();
+		assert_eq!(err, RangeError::Unsatisfiable);
+		assert!(err.statuscode() == StatusCode::RangeNotSatisfiable);
+		assert_eq!(RangeError::content_range(1000), "bytes */1000");
+		assert_eq!(parse_range("bytes=-0", 1000).unwrap_err(), RangeError::Unsatisfiable);
+		assert_eq!(parse_range("bytes=0-", 0).unwrap_err(), RangeError::Unsatisfiable);
+		// the satisfiable ones are kept
+		assert_eq!(parse_range("bytes=2000-3000,0-1", 1000).unwrap(), vec![ByteRange { start: 0, end: 1 }]);
+
+		assert_eq!(parse_range("items=0-1", 1000).unwrap_err(), RangeError::Malformed);
+		assert_eq!(parse_range("bytes=5-1", 1000).unwrap_err(), RangeError::Malformed);
+		assert_eq!(parse_range("bytes=a-b", 1000).unwrap_err(), RangeError::Malformed);
+		assert_eq!(parse_range("bytes=+1-2", 1000).unwrap_err(), RangeError::Malformed);
+	}
+}
\ No newline at end of file
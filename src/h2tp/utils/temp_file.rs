@@ -0,0 +1,81 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::io::AsyncWriteExt;
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+// A file of its own in `dir`, removed again when dropped.
+pub struct TempFile {
+	path: PathBuf,
+	file: tokio::fs::File,
+	len: u64,
+}
+
+impl TempFile {
+	pub async fn create(dir: &Path) -> std::io::Result<Self> {
+		loop {
+			let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+			let path = dir.join(format!("spk-{}-{}.tmp", std::process::id(), id));
+			// another process may have left one behind under this name
+			match tokio::fs::OpenOptions::new().write(true).create_new(true).open(&path).await {
+				Ok(file) => {
+					return Ok(Self { path, file, len: 0 });
+				}
+				Err(e) => {
+					if e.kind() != std::io::ErrorKind::AlreadyExists {
+						return Err(e);
+					}
+				}
+			}
+		}
+	}
+
+	pub async fn write(&mut self, data: &[u8]) -> std::io::Result<()> {
+		self.file.write_all(data).await?;
+		self.len += data.len() as u64;
+		return Ok(());
+	}
+
+	// everything written is on disk
+	pub async fn flush(&mut self) -> std::io::Result<()> {
+		self.file.flush().await?;
+		return self.file.sync_data().await;
+	}
+
+	pub fn path(&self) -> &Path {
+		return self.path.as_path();
+	}
+
+	pub fn len(&self) -> u64 {
+		return self.len;
+	}
+}
+
+impl Drop for TempFile {
+	fn drop(&mut self) {
+		let _ = std::fs::remove_file(&self.path);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::h2tp::utils::temp_file::TempFile;
+
+	#[tokio::test]
+	async fn test_temp_file() {
+		let dir = std::env::temp_dir();
+		let mut a = TempFile::create(&dir).await.unwrap();
+		let b = TempFile::create(&dir).await.unwrap();
+		assert_ne!(a.path(), b.path());
+
+		a.write(b"Hello ").await.unwrap();
+		a.write(b"World").await.unwrap();
+		a.flush().await.unwrap();
+		assert_eq!(a.len(), 11);
+		assert_eq!(std::fs::read(a.path()).unwrap(), b"Hello World");
+
+		let path = a.path().to_path_buf();
+		drop(a);
+		assert!(!path.exists());
+	}
+}
\ No newline at end of file
@@ -0,0 +1,160 @@
+use crate::h2tp::utils::uricoding::{DecodeError, decode_uri};
+
+// A request target split into its components (RFC 3986 3), e.g.
+// "http://spk.local:8080/a%20b?x=1#top" -> scheme "http", authority "spk.local:8080", path "/a%20b",
+// query "x=1", fragment "top". Components are kept as received, `decoded_path` percent-decodes.
+pub struct Uri {
+	scheme: Option<String>,
+	authority: Option<String>,
+	path: String,
+	query: Option<String>,
+	fragment: Option<String>,
+}
+
+fn is_scheme(v: &str) -> bool {
+	let bytes = v.as_bytes();
+	if bytes.is_empty() || !bytes[0].is_ascii_alphabetic() {
+		return false;
+	}
+	return bytes.iter().all(|b| { return b.is_ascii_alphanumeric() || *b == b'+' || *b == b'-' || *b == b'.'; });
+}
+
+impl Uri {
+	pub fn new() -> Self {
+		return Self {
+			scheme: None,
+			authority: None,
+			path: String::from("/"),
+			query: None,
+			fragment: None,
+		};
+	}
+
+	pub fn parse(v: &str) -> Self {
+		let mut uri = Self::new();
+		let mut v = v;
+
+		match v.find('#') {
+			Some(idx) => {
+				uri.fragment = Some(v[idx + 1..].to_string());
+				v = &v[..idx];
+			}
+			None => {}
+		}
+		match v.find('?') {
+			Some(idx) => {
+				uri.query = Some(v[idx + 1..].to_string());
+				v = &v[..idx];
+			}
+			None => {}
+		}
+		match v.find(':') {
+			// a colon after the first slash belongs to the path
+			Some(idx) if is_scheme(&v[..idx]) && !v[..idx].contains('/') => {
+				uri.scheme = Some(v[..idx].to_ascii_lowercase());
+				v = &v[idx + 1..];
+			}
+			_ => {}
+		}
+		if v.starts_with("//") {
+			v = &v[2..];
+			match v.find('/') {
+				Some(idx) => {
+					uri.authority = Some(v[..idx].to_string());
+					v = &v[idx..];
+				}
+				None => {
+					uri.authority = Some(v.to_string());
+					v = "";
+				}
+			}
+		}
+		if !v.is_empty() {
+			uri.path.clear();
+			uri.path.push_str(v);
+		}
+		return uri;
+	}
+
+	// authority-form, the target of CONNECT
+	pub fn from_authority(v: &str) -> Self {
+		let mut uri = Self::new();
+		uri.authority = Some(v.to_string());
+		uri.path.clear();
+		return uri;
+	}
+
+	pub fn scheme(&self) -> Option<&str> {
+		return self.scheme.as_deref();
+	}
+
+	pub fn authority(&self) -> Option<&str> {
+		return self.authority.as_deref();
+	}
+
+	pub fn path(&self) -> &str {
+		return self.path.as_str();
+	}
+
+	pub fn decoded_path(&self) -> Result<String, DecodeError> {
+		let mut dist = String::with_capacity(self.path.len());
+		decode_uri(&mut dist, self.path.as_str())?;
+		return Ok(dist);
+	}
+
+	pub fn query(&self) -> Option<&str> {
+		return self.query.as_deref();
+	}
+
+	pub fn fragment(&self) -> Option<&str> {
+		return self.fragment.as_deref();
+	}
+
+	// absolute-form, as sent to proxies
+	pub fn is_absolute(&self) -> bool {
+		return self.scheme.is_some();
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::h2tp::utils::uri::Uri;
+
+	#[test]
+	fn test_absolute() {
+		let uri = Uri::parse("HTTP://spk.local:8080/a%20b/c?x=1&y=2#top");
+		assert!(uri.is_absolute());
+		assert_eq!(uri.scheme(), Some("http"));
+		assert_eq!(uri.authority(), Some("spk.local:8080"));
+		assert_eq!(uri.path(), "/a%20b/c");
+		assert_eq!(uri.decoded_path().unwrap(), "/a b/c");
+		assert_eq!(uri.query(), Some("x=1&y=2"));
+		assert_eq!(uri.fragment(), Some("top"));
+
+		let uri = Uri::parse("http://spk.local");
+		assert_eq!(uri.authority(), Some("spk.local"));
+		assert_eq!(uri.path(), "/");
+	}
+
+	#[test]
+	fn test_origin_form() {
+		let uri = Uri::parse("/search?q=a:b");
+		assert!(!uri.is_absolute());
+		assert!(uri.authority().is_none());
+		assert_eq!(uri.path(), "/search");
+		assert_eq!(uri.query(), Some("q=a:b"));
+		assert!(uri.fragment().is_none());
+
+		let uri = Uri::parse("");
+		assert_eq!(uri.path(), "/");
+
+		let uri = Uri::parse("docs/a:b");
+		assert!(uri.scheme().is_none());
+		assert_eq!(uri.path(), "docs/a:b");
+
+		let uri = Uri::parse("//cdn.local/x");
+		assert!(uri.scheme().is_none());
+		assert_eq!(uri.authority(), Some("cdn.local"));
+		assert_eq!(uri.path(), "/x");
+	}
+}
\ No newline at end of file
@@ -1,12 +1,12 @@
-use crate::h2tp::utils::uricoding_excepts::ENCODE_URI_EXCEPTS;
+use crate::h2tp::utils::uricoding_excepts::{ENCODE_URI_COMPONENT_EXCEPTS, ENCODE_URI_EXCEPTS};
 
 const UPPERHEX: &[u8] = "0123456789ABCDEF".as_bytes();
 
-pub fn encode_uri(dist: &mut String, src: &str) {
+fn encode(dist: &mut String, src: &str, excepts: &[bool; 128]) {
 	let bytes = src.as_bytes();
 	for i in 0..bytes.len() {
 		let b = bytes[i];
-		if b < 128 && ENCODE_URI_EXCEPTS[b as usize] {
+		if b < 128 && excepts[b as usize] {
 			dist.push(b as char);
 			continue;
 		}
@@ -16,13 +16,80 @@ pub fn encode_uri(dist: &mut String, src: &str) {
 	}
 }
 
-pub fn encode_uri_component(_dist: &mut str, _src: &str) {}
+pub fn encode_uri(dist: &mut String, src: &str) {
+	encode(dist, src, &ENCODE_URI_EXCEPTS);
+}
+
+pub fn encode_uri_component(dist: &mut String, src: &str) {
+	encode(dist, src, &ENCODE_URI_COMPONENT_EXCEPTS);
+}
+
+// Encodes each segment of `path` as a component, the `/` between them stay as they are.
+pub fn encode_path(dist: &mut String, path: &str) {
+	for (idx, segment) in path.split('/').enumerate() {
+		if idx > 0 {
+			dist.push('/');
+		}
+		encode_uri_component(dist, segment);
+	}
+}
 
-pub fn decode_uri(_dist: &mut str, _src: &str) {}
+fn unhex(b: u8) -> Option<u8> {
+	return match b {
+		b'0'..=b'9' => Some(b - b'0'),
+		b'a'..=b'f' => Some(b - b'a' + 10),
+		b'A'..=b'F' => Some(b - b'A' + 10),
+		_ => None,
+	};
+}
+
+#[derive(PartialEq, Debug)]
+pub enum DecodeErrorReason {
+	// `%` followed by something other than two hex digits
+	InvalidHex,
+	// `%` with less than two characters left
+	Truncated,
+}
+
+#[derive(PartialEq, Debug)]
+pub struct DecodeError {
+	// byte offset of the `%` starting the malformed escape
+	pub offset: usize,
+	pub reason: DecodeErrorReason,
+}
+
+// Percent-decodes `src` into `dist`, which is left untouched on error.
+pub fn decode_uri(dist: &mut String, src: &str) -> Result<(), DecodeError> {
+	let bytes = src.as_bytes();
+	let mut out: Vec<u8> = Vec::with_capacity(bytes.len());
+	let mut i = 0;
+	while i < bytes.len() {
+		let b = bytes[i];
+		if b != b'%' {
+			out.push(b);
+			i += 1;
+			continue;
+		}
+		if i + 2 >= bytes.len() {
+			return Err(DecodeError { offset: i, reason: DecodeErrorReason::Truncated });
+		}
+		match (unhex(bytes[i + 1]), unhex(bytes[i + 2])) {
+			(Some(hi), Some(lo)) => {
+				out.push((hi << 4) | lo);
+				i += 3;
+			}
+			_ => {
+				return Err(DecodeError { offset: i, reason: DecodeErrorReason::InvalidHex });
+			}
+		}
+	}
+	dist.push_str(String::from_utf8_lossy(&out).as_ref());
+	return Ok(());
+}
 
 #[cfg(test)]
 mod tests {
-	use crate::h2tp::utils::uricoding::encode_uri;
+	use crate::h2tp::utils::uricoding::{DecodeError, DecodeErrorReason, decode_uri, encode_path, encode_uri, encode_uri_component};
 
 	#[test]
 	fn test_encode_uri() {
@@ -30,4 +97,45 @@ mod tests {
 		encode_uri(&mut dist, "ABC abc 123😄");
 		println!("{}", dist);
 	}
+
+	#[test]
+	fn test_encode_path() {
+		let mut dist = String::new();
+		encode_uri_component(&mut dist, "a b/c?d");
+		assert_eq!(dist, "a%20b%2Fc%3Fd");
+
+		let mut dist = String::new();
+		encode_path(&mut dist, "/files/my caf\u{e9}.txt/");
+		assert_eq!(dist, "/files/my%20caf%C3%A9.txt/");
+
+		// `?` and `#` would end the path
+		let mut dist = String::new();
+		encode_path(&mut dist, "a?b#c");
+		assert_eq!(dist, "a%3Fb%23c");
+	}
+
+	#[test]
+	fn test_decode_uri() {
+		let mut dist = String::new();
+		decode_uri(&mut dist, "ABC%20abc%2f123%F0%9F%98%84").unwrap();
+		assert_eq!(dist, "ABC abc/123😄");
+	}
+
+	#[test]
+	fn test_decode_error() {
+		let mut dist = String::new();
+		assert_eq!(
+			decode_uri(&mut dist, "/a%20b/%G0"),
+			Err(DecodeError { offset: 7, reason: DecodeErrorReason::InvalidHex }),
+		);
+		assert_eq!(
+			decode_uri(&mut dist, "/a%20b%A"),
+			Err(DecodeError { offset: 6, reason: DecodeErrorReason::Truncated }),
+		);
+		assert_eq!(
+			decode_uri(&mut dist, "%"),
+			Err(DecodeError { offset: 0, reason: DecodeErrorReason::Truncated }),
+		);
+		assert!(dist.is_empty());
+	}
 }
\ No newline at end of file
@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use crate::h2tp::handler::{BoxedFuture, Handler};
+use crate::h2tp::request::Request;
+
+// `host` lowercased and without its port, `[::1]:8080` gives `[::1]`
+fn hostname(host: &str) -> String {
+	let host = host.trim();
+	let name = if host.starts_with('[') {
+		match host.find(']') {
+			Some(end) => {
+				&host[..=end]
+			}
+			None => {
+				host
+			}
+		}
+	} else {
+		match host.rsplit_once(':') {
+			Some((name, _)) => {
+				name
+			}
+			None => {
+				host
+			}
+		}
+	};
+	return name.to_ascii_lowercase();
+}
+
+// Dispatches on the host a request is for, its absolute-form target or else its `Host`. Names
+// match case-insensitively whatever port is given, requests for any other host go to `default`.
+pub struct VhostHandler {
+	hosts: HashMap<String, Arc<dyn Handler + Send + Sync>>,
+	default: Arc<dyn Handler + Send + Sync>,
+}
+
+impl VhostHandler {
+	pub fn new(default: Arc<dyn Handler + Send + Sync>) -> Self {
+		return Self { hosts: HashMap::new(), default };
+	}
+
+	pub fn host(&mut self, name: &str, handler: Arc<dyn Handler + Send + Sync>) -> &mut Self {
+		self.hosts.insert(hostname(name), handler);
+		return self;
+	}
+}
+
+impl Handler for VhostHandler {
+	fn handle(&self, req: Request) -> BoxedFuture {
+		let handler = match req.authority().and_then(|v| self.hosts.get(hostname(v).as_str())) {
+			Some(v) => {
+				v
+			}
+			None => {
+				&self.default
+			}
+		};
+		return handler.handle(req);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::Arc;
+	use crate::h2tp::cfg::Config;
+	use crate::h2tp::handler::{BoxedFuture, Handler};
+	use crate::h2tp::request::Request;
+	use crate::h2tp::response::Response;
+	use crate::h2tp::vhost::VhostHandler;
+
+	async fn request(raw: &[u8]) -> Request {
+		let mut stream = raw;
+		let mut req = Request::new();
+		let mut cfg = Config::new();
+		cfg.allow_absolute_form = true;
+		assert!(req.from(&mut stream, &cfg).await.is_none());
+		return req;
+	}
+
+	// answers with its name
+	struct Site(&'static str);
+
+	impl Handler for Site {
+		fn handle(&self, _req: Request) -> BoxedFuture {
+			let name = self.0;
+			return Box::pin(async move {
+				let mut resp = Response::new();
+				resp.builder().text(name);
+				return Ok(resp);
+			});
+		}
+	}
+
+	fn site(name: &'static str) -> Arc<Site> {
+		return Arc::new(Site(name));
+	}
+
+	#[tokio::test]
+	async fn test_vhost() {
+		let mut vhost = VhostHandler::new(site("default"));
+		vhost.host("example.com", site("example")).host("Static.Example.com:8080", site("static"));
+
+		let cases: [(&[u8], &[u8]); 6] = [
+			(b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n", b"example"),
+			(b"GET / HTTP/1.1\r\nHost: EXAMPLE.com:8080\r\n\r\n", b"example"),
+			(b"GET / HTTP/1.1\r\nHost: static.example.com\r\n\r\n", b"static"),
+			(b"GET http://static.example.com:81/ HTTP/1.1\r\nHost: example.com\r\n\r\n", b"static"),
+			(b"GET / HTTP/1.1\r\nHost: other.com\r\n\r\n", b"default"),
+			(b"GET / HTTP/1.0\r\n\r\n", b"default"),
+		];
+		for (raw, site) in cases {
+			let resp = vhost.handle(request(raw).await).await.unwrap();
+			assert_eq!(resp.body().unwrap().as_ref(), site, "{}", String::from_utf8_lossy(raw));
+		}
+	}
+
+	#[test]
+	fn test_hostname() {
+		assert_eq!(super::hostname("Example.COM:80"), "example.com");
+		assert_eq!(super::hostname("[::1]:8080"), "[::1]");
+		assert_eq!(super::hostname("[::1]"), "[::1]");
+		assert_eq!(super::hostname("localhost"), "localhost");
+	}
+}
\ No newline at end of file